@@ -1,5 +1,94 @@
 use crate::include::*;
-use std::process::Child;
+use std::{
+    env,
+    io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write},
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+        mpsc::Receiver,
+    },
+    thread,
+};
+
+/// Returns the command for `program` overridable with an `APART_{PROGRAM}_CMD` env var,
+/// ie `APART_UMOUNT_CMD` for "umount" or `APART_FSCK_F2FS_CMD` for "fsck.f2fs"
+pub fn cmd(program: &str) -> String {
+    let env_var = format!(
+        "APART_{}_CMD",
+        program
+            .to_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    env::var(env_var).unwrap_or_else(|_| program.to_owned())
+}
+
+/// Runs the command to completion, errors include the command's stderr output
+pub fn run(cmd: &mut Command) -> IoResult<()> {
+    let output = cmd.stdin(Stdio::null()).stdout(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(IoError::other(match stderr.trim() {
+            "" => format!("{:?} failed: {}", cmd.get_program(), output.status),
+            reason => reason.to_owned(),
+        }))
+    }
+}
+
+/// Reads all of a child's piped stderr output concurrently, received once the child exits
+pub fn read_stderr(cmd: &mut Child, log_name: &str) -> IoResult<Receiver<String>> {
+    let mut stderr = cmd.stderr.take().expect("!stderr piped");
+    let (tx, output) = mpsc::channel();
+    thread::Builder::new()
+        .name(format!("stderr-reader {}", log_name))
+        .spawn(move || {
+            let mut out = String::new();
+            if let Err(err) = stderr.read_to_string(&mut out) {
+                warn!("Failed to read stderr: {}", err);
+            }
+            // receiver is dropped with the job
+            let _ = tx.send(out);
+        })?;
+    Ok(output)
+}
+
+/// Copies `from` into `to` concurrently, ie piping a file into a child's stdin, returning the
+/// count of bytes copied so far
+pub fn counted_copy<R, W>(mut from: R, mut to: W, log_name: &str) -> IoResult<Arc<AtomicU64>>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let count = Arc::new(AtomicU64::new(0));
+    let copied = Arc::clone(&count);
+    let log_name = log_name.to_owned();
+    thread::Builder::new()
+        .name(format!("copier {}", log_name))
+        .spawn(move || {
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let read = match from.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        warn!("Failed to read for {}: {}", log_name, err);
+                        break;
+                    }
+                };
+                if let Err(err) = to.write_all(&buf[..read]) {
+                    // expected when the reading child is killed, ie the job is cancelled
+                    debug!("Stopped copying for {}: {}", log_name, err);
+                    break;
+                }
+                copied.fetch_add(read as u64, Ordering::Relaxed);
+            }
+        })?;
+    Ok(count)
+}
 
 /// Handle a child process no longer desired running
 pub fn drop_log_errors(cmd: &mut Child, log_name: &str) {
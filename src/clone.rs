@@ -1,5 +1,13 @@
 use crate::{
-    asynchronous, child, compression::Compression, include::*, lsblk, partclone, partclone::*,
+    asynchronous, child,
+    compression::Compression,
+    failure::{Failure, PendingFailure},
+    include::*,
+    jobs::JobSummary,
+    lsblk, partclone,
+    partclone::*,
+    retention::RetentionPolicy,
+    space,
 };
 use chrono::prelude::*;
 use regex::Regex;
@@ -13,11 +21,20 @@ use std::{
     path::Path,
     process::{Child, Command, Stdio},
     str,
-    sync::{mpsc, mpsc::Receiver},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+        mpsc::{Receiver, TryRecvError},
+    },
     thread,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
+/// Minimum time between destination free space checks while cloning
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct CloneStatusCommon {
     pub id: String,
@@ -28,13 +45,19 @@ pub struct CloneStatusCommon {
     pub source_uuid: Option<String>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum CloneStatus {
     Running {
         common: CloneStatusCommon,
         complete: f64,
         rate: Option<String>,
         estimated_finish: Option<DateTime<Utc>>,
+        smoothed_estimated_finish: Option<DateTime<Utc>>,
+        metrics: Metrics,
+        /// image bytes written so far
+        compressed_bytes: Option<u64>,
+        /// image bytes written per byte read from partclone so far
+        compression_ratio: Option<f64>,
     },
     Syncing {
         common: CloneStatusCommon,
@@ -43,11 +66,15 @@ pub enum CloneStatus {
         common: CloneStatusCommon,
         finish: DateTime<Utc>,
         image_size: u64,
+        /// `image_size` per byte read from partclone
+        compression_ratio: Option<f64>,
     },
     Failed {
         common: CloneStatusCommon,
         reason: String,
         finish: DateTime<Utc>,
+        /// command output the reason was classified from
+        output: Vec<String>,
     },
 }
 
@@ -60,10 +87,25 @@ pub struct CloneJob {
     source_uuid: Option<String>,
     partclone_cmd: RefCell<Child>,
     compress_cmd: RefCell<Child>,
+    compress_stderr: Receiver<String>,
+    /// bytes read from partclone & piped into the compress command
+    partclone_bytes: Arc<AtomicU64>,
     sent_first_msg: Cell<bool>,
+    latest_status: RefCell<Option<CloneStatus>>,
+    /// `complete` of the latest running status, kept once the job fails
+    latest_complete: Cell<f64>,
     partclone_status: Receiver<PartcloneStatus>,
     partclone_finished: Cell<bool>,
     rename_task: RefCell<Option<Receiver<IoResult<Metadata>>>>,
+    directory: String,
+    name: String,
+    retention: Option<RetentionPolicy>,
+    /// estimated image size checked against the destination free space before starting
+    estimated_image_size: Option<u64>,
+    last_space_check: Cell<Option<Instant>>,
+    /// destination free space being looked up concurrently, as `df` can be slow
+    space_lookup: RefCell<Option<Receiver<IoResult<u64>>>>,
+    failure: RefCell<Option<PendingFailure>>,
 }
 
 fn destination_raw_fd(
@@ -93,15 +135,74 @@ fn destination_raw_fd(
 
 impl CloneJob {
     pub fn try_recv(&self) -> Result<CloneStatus, Box<dyn Error>> {
+        let status = self.recv_status()?;
+        if let CloneStatus::Running { complete, .. } = status {
+            self.latest_complete.set(complete);
+        }
+        *self.latest_status.borrow_mut() = Some(status.clone());
+        Ok(status)
+    }
+
+    /// Returns the latest status received, for clients asking rather than following messages
+    pub fn summary(&self) -> JobSummary {
+        let CloneStatusCommon {
+            id,
+            source,
+            destination,
+            start,
+            ..
+        } = self.clone_status_common();
+        let status = self
+            .latest_status
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| self.started_status());
+        let (complete, rate, estimated_finish, phase) = match status {
+            CloneStatus::Running {
+                complete,
+                rate,
+                estimated_finish,
+                ..
+            } => (complete, rate, estimated_finish, "cloning"),
+            CloneStatus::Syncing { .. } => (0.9999, None, None, "syncing"),
+            CloneStatus::Finished { .. } => (1.0, None, None, "finished"),
+            CloneStatus::Failed { .. } => (self.latest_complete.get(), None, None, "failed"),
+        };
+        JobSummary {
+            id,
+            kind: "clone",
+            source,
+            destination,
+            start,
+            complete,
+            rate,
+            estimated_finish,
+            phase,
+        }
+    }
+
+    fn started_status(&self) -> CloneStatus {
+        CloneStatus::Running {
+            common: self.clone_status_common(),
+            complete: 0.0,
+            rate: None,
+            estimated_finish: None,
+            smoothed_estimated_finish: None,
+            metrics: Metrics::default(),
+            compressed_bytes: None,
+            compression_ratio: None,
+        }
+    }
+
+    fn recv_status(&self) -> Result<CloneStatus, Box<dyn Error>> {
         if !self.sent_first_msg.get() {
             // bosh out an initial running message to show the clone has started
             self.sent_first_msg.set(true);
-            return Ok(CloneStatus::Running {
-                common: self.clone_status_common(),
-                complete: 0.0,
-                rate: None,
-                estimated_finish: None,
-            });
+            return Ok(self.started_status());
+        }
+
+        if self.failure.borrow().is_some() {
+            return self.try_failed_status();
         }
 
         if self.partclone_finished.get() {
@@ -120,54 +221,136 @@ impl CloneJob {
                             common: self.clone_status_common(),
                             finish: Utc::now(),
                             image_size: meta.len(),
+                            compression_ratio: self.compression_ratio(meta.len()),
                         }),
                         Err(err) => {
                             error!("Failed to rename {}: {}", self.destination, err);
-                            Ok(CloneStatus::Failed {
-                                common: self.clone_status_common(),
-                                finish: Utc::now(),
-                                reason: format!("Failed to rename {}", self.destination),
-                            })
+                            Ok(self.fail_status(&format!("Failed to rename {}", self.destination)))
                         }
                     }
                 }
                 Ok(None) => Err("Waiting for commands to finish".into()),
                 Err(err) => {
                     error!("Clone failed: {:?}", err);
-                    Ok(CloneStatus::Failed {
-                        common: self.clone_status_common(),
-                        finish: Utc::now(),
-                        reason: "Failed".to_owned(),
-                    })
+                    *self.failure.borrow_mut() = Some(PendingFailure::new(Utc::now(), vec![]));
+                    self.try_failed_status()
                 }
             };
         }
 
         Ok(match self.partclone_status.try_recv()? {
+            PartcloneStatus::Running { complete, .. } if self.out_of_space(complete) => {
+                self.fail_status(space::OUT_OF_SPACE)
+            }
             PartcloneStatus::Running {
                 rate,
                 estimated_finish,
+                smoothed_estimated_finish,
                 complete,
-            } => CloneStatus::Running {
-                common: self.clone_status_common(),
-                complete: if complete > 0.9999 { 0.9999 } else { complete },
-                rate: Some(rate),
-                estimated_finish: Some(estimated_finish),
-            },
+                metrics,
+            } => {
+                let compressed_bytes = fs::metadata(&self.destination).ok().map(|m| m.len());
+                CloneStatus::Running {
+                    common: self.clone_status_common(),
+                    complete: if complete > 0.9999 { 0.9999 } else { complete },
+                    rate: Some(rate),
+                    estimated_finish: Some(estimated_finish),
+                    smoothed_estimated_finish,
+                    metrics,
+                    compressed_bytes,
+                    compression_ratio: compressed_bytes
+                        .and_then(|bytes| self.compression_ratio(bytes)),
+                }
+            }
             PartcloneStatus::Synced { .. } => {
                 self.partclone_finished.set(true);
                 CloneStatus::Syncing {
                     common: self.clone_status_common(),
                 }
             }
-            PartcloneStatus::Failed { finish } => CloneStatus::Failed {
-                common: self.clone_status_common(),
-                finish,
-                reason: "Failed".to_owned(),
-            },
+            PartcloneStatus::Failed { finish, output } => {
+                *self.failure.borrow_mut() = Some(PendingFailure::new(finish, output));
+                return self.try_failed_status();
+            }
+        })
+    }
+
+    /// Returns the failed status once the compressor's output to classify the failure with is
+    /// available, see `PendingFailure`
+    fn try_failed_status(&self) -> Result<CloneStatus, Box<dyn Error>> {
+        let failure = self.failure.borrow();
+        let failure = failure.as_ref().expect("!failure");
+        let Failure { reason, output } = failure
+            .try_failure(&self.compress_stderr)
+            .ok_or("Waiting for compressor output")?;
+        Ok(CloneStatus::Failed {
+            common: self.clone_status_common(),
+            finish: failure.finish,
+            reason,
+            output,
         })
     }
 
+    /// Returns `true` when the destination has less space available than the remaining image is
+    /// projected to need. Free space is looked up concurrently at most every
+    /// `SPACE_CHECK_INTERVAL`, checked once a later status finds the lookup done.
+    fn out_of_space(&self, complete: f64) -> bool {
+        if !(0.01..1.0).contains(&complete) {
+            return false;
+        }
+        let mut space_lookup = self.space_lookup.borrow_mut();
+        let Some(lookup) = space_lookup.as_ref() else {
+            if self
+                .last_space_check
+                .get()
+                .is_none_or(|last| last.elapsed() >= SPACE_CHECK_INTERVAL)
+            {
+                self.last_space_check.set(Some(Instant::now()));
+                let directory = self.directory.clone();
+                *space_lookup = Some(asynchronous::receiver(move || space::available(&directory)));
+            }
+            return false;
+        };
+        let available = match lookup.try_recv() {
+            Ok(available) => available,
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => Err(IoError::other("lookup failed")),
+        };
+        *space_lookup = None;
+
+        let written = fs::metadata(&self.destination).map_or(0, |meta| meta.len());
+        let projected = match written {
+            0 => match self.estimated_image_size {
+                Some(estimate) => estimate,
+                None => return false,
+            },
+            _ => (written as f64 / complete) as u64,
+        };
+        let remaining = projected.saturating_sub(written);
+        match available {
+            Ok(available) if available < remaining => {
+                warn!(
+                    "{} needs ~{} more bytes, {} available",
+                    self, remaining, available
+                );
+                true
+            }
+            Ok(_) => false,
+            Err(err) => {
+                warn!("Could not check free space of {}: {}", self.directory, err);
+                false
+            }
+        }
+    }
+
+    /// Returns image bytes per byte read from partclone, unknown until partclone has output
+    fn compression_ratio(&self, image_bytes: u64) -> Option<f64> {
+        match self.partclone_bytes.load(Ordering::Relaxed) {
+            0 => None,
+            read => Some((image_bytes as f64 / read as f64 * 10_000.0).round() / 10_000.0),
+        }
+    }
+
     /// Returns `Ok(Some(()))` when both partclone & compress commands have exited successfully
     fn try_wait(&self) -> Result<Option<()>, Box<dyn Error>> {
         let pcl = match self.partclone_cmd.borrow_mut().try_wait() {
@@ -221,19 +404,31 @@ impl CloneJob {
         without_inprogress
     }
 
+    /// Returns the directory, backup name & policy to prune with after a successful clone
+    pub fn retention(&self) -> Option<(&str, &str, RetentionPolicy)> {
+        self.retention
+            .map(|policy| (self.directory.as_str(), self.name.as_str(), policy))
+    }
+
     pub fn fail_status(&self, reason: &str) -> CloneStatus {
         CloneStatus::Failed {
             common: self.clone_status_common(),
             reason: reason.to_owned(),
             finish: Utc::now(),
+            output: vec![],
         }
     }
 
+    /// Starts cloning, `estimated_image_size` having been checked against the destination free
+    /// space with `space::check`
     pub fn new(
+        id: Uuid,
         source: String,
         destination: &str,
         name: &str,
         z: Compression,
+        retention: Option<RetentionPolicy>,
+        estimated_image_size: Option<u64>,
     ) -> IoResult<CloneJob> {
         let (partclone_variant, partclone_cmd) = match lsblk::fstype(&source) {
             Some(fstype) => match partclone::cmd(&fstype) {
@@ -270,15 +465,20 @@ impl CloneJob {
                 .spawn()?
         };
 
-        let compress_cmd = Command::new(z.command)
+        let mut compress_cmd = Command::new(z.command)
             .args(z.write_args)
-            .stdin(unsafe {
-                Stdio::from_raw_fd(partclone_cmd.stdout.take().unwrap().into_raw_fd())
-            })
+            .stdin(Stdio::piped())
             .stdout(unsafe { Stdio::from_raw_fd(dest_raw_fd) })
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()?;
 
+        let compress_stderr = child::read_stderr(&mut compress_cmd, z.command)?;
+        let partclone_bytes = child::counted_copy(
+            partclone_cmd.stdout.take().unwrap(),
+            compress_cmd.stdin.take().unwrap(),
+            &source,
+        )?;
+
         let stderr = partclone_cmd.stderr.take().unwrap();
         let (tx, partclone_status) = mpsc::channel();
         thread::Builder::new()
@@ -298,11 +498,22 @@ impl CloneJob {
             start: Utc::now(),
             partclone_cmd: RefCell::new(partclone_cmd),
             compress_cmd: RefCell::new(compress_cmd),
+            compress_stderr,
+            partclone_bytes,
             partclone_status,
-            id: Uuid::new_v4(),
+            id,
             sent_first_msg: Cell::new(false),
+            latest_status: RefCell::new(None),
+            latest_complete: Cell::new(0.0),
             partclone_finished: Cell::new(false),
             rename_task: RefCell::new(None),
+            directory: destination.to_owned(),
+            name: name.to_owned(),
+            retention,
+            estimated_image_size,
+            last_space_check: Cell::new(None),
+            space_lookup: RefCell::new(None),
+            failure: RefCell::new(None),
         })
     }
 }
@@ -352,6 +563,16 @@ pub fn is_valid_image_name(filename: &str) -> bool {
     partclone_variant_from_image(filename).is_ok()
 }
 
+/// Returns the backup name & local creation timestamp of an image file name
+pub fn image_name_and_timestamp(filename: &str) -> Option<(String, NaiveDateTime)> {
+    let image_re =
+        Regex::new(r"^(?:.*/)?([^/]+)-(\d{4,}-\d\d-\d\dT\d{4})\.apt\..+\..+$").expect("!image_re");
+
+    let caps = image_re.captures(filename)?;
+    let timestamp = NaiveDateTime::parse_from_str(&caps[2], "%Y-%m-%dT%H%M").ok()?;
+    Some((caps[1].to_owned(), timestamp))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -379,6 +600,21 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn name_and_timestamp_from_image() {
+        assert_eq!(
+            image_name_and_timestamp("/mnt/backups/my-img-2017-04-20T1500.apt.ext2.gz"),
+            Some((
+                "my-img".to_owned(),
+                NaiveDate::from_ymd_opt(2017, 4, 20)
+                    .unwrap()
+                    .and_hms_opt(15, 0, 0)
+                    .unwrap()
+            ))
+        );
+        assert_eq!(image_name_and_timestamp("my-img.apt.ext2.gz"), None);
+    }
+
     #[test]
     fn image_invalid() {
         assert!(!is_valid_image_name(
@@ -11,6 +11,11 @@ pub struct Compression {
     pub command: &'static str,
     pub write_args: &'static [&'static str],
     pub read_args: &'static [&'static str],
+    /// approximate compressed size as a percentage of the input
+    pub size_percent: u64,
+    /// approximate compression throughput in MB/s, per core when `multithreaded`
+    pub write_mb_per_sec: u64,
+    pub multithreaded: bool,
 }
 
 // ~110 MB/s per core, compression 100->25
@@ -19,6 +24,9 @@ const PIGZ: Compression = Compression {
     command: "pigz",
     write_args: &["-1c"],
     read_args: &["-dc"],
+    size_percent: 25,
+    write_mb_per_sec: 110,
+    multithreaded: true,
 };
 // ~1250 MB/s single threaded, compression 100->30
 const LZ4: Compression = Compression {
@@ -26,6 +34,9 @@ const LZ4: Compression = Compression {
     command: "lz4",
     write_args: &["-c"],
     read_args: &["-dc"],
+    size_percent: 30,
+    write_mb_per_sec: 1250,
+    multithreaded: false,
 };
 // ~450 MB/s per core, compression 100->22
 const ZSTD: Compression = Compression {
@@ -34,12 +45,18 @@ const ZSTD: Compression = Compression {
     write_args: &["-T0", "-c"],
     // support up to --long=31 recompression
     read_args: &["-T0", "--long=31", "-dc"],
+    size_percent: 22,
+    write_mb_per_sec: 450,
+    multithreaded: true,
 };
 const NONE: Compression = Compression {
     name: "uncompressed",
     command: "cat",
     write_args: &["-"],
     read_args: &["-"],
+    size_percent: 100,
+    write_mb_per_sec: 2000,
+    multithreaded: false,
 };
 
 const ALL: &[Compression] = &[PIGZ, NONE, ZSTD, LZ4];
@@ -65,6 +82,19 @@ impl Compression {
         Err(format!("Unknown compression used in file `{}`", file))
     }
 
+    /// Returns the compression of a file from its first bytes, uncompressed if unrecognised
+    pub fn from_magic(start: &[u8]) -> Compression {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            PIGZ
+        } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            ZSTD
+        } else if start.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            LZ4
+        } else {
+            NONE
+        }
+    }
+
     pub fn all_installed() -> impl Iterator<Item = Compression> {
         ALL.iter().filter(|z| z.is_installed()).copied()
     }
@@ -112,4 +142,15 @@ mod compression_tests {
         let old_z = Compression::from_file_name("some-backup-2017-08-09G1106.apt.f2fs.zstd");
         assert_eq!(old_z, Ok(ZSTD));
     }
+
+    #[test]
+    fn from_magic() {
+        assert_eq!(Compression::from_magic(&[0x1f, 0x8b, 0x08, 0x00]), PIGZ);
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x64]),
+            ZSTD
+        );
+        assert_eq!(Compression::from_magic(&[0x04, 0x22, 0x4d, 0x18]), LZ4);
+        assert_eq!(Compression::from_magic(b"partclone-image\0"), NONE);
+    }
 }
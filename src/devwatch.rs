@@ -0,0 +1,218 @@
+use crate::include::*;
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    time::{Duration, Instant},
+};
+
+/// Minimum duration between re-reading the block device & mount tables
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn proc_partitions_file() -> String {
+    env::var("APART_PROC_PARTITIONS").unwrap_or_else(|_| "/proc/partitions".to_owned())
+}
+
+fn proc_mountinfo_file() -> String {
+    env::var("APART_PROC_MOUNTINFO").unwrap_or_else(|_| "/proc/self/mountinfo".to_owned())
+}
+
+/// Block device additions/removals & mount changes between two polls
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct DevicesChanged {
+    /// device names, ie "sdc1"
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// `(device, mountpoint)` pairs, ie ("/dev/sdc1", "/media/usb")
+    pub mounted: Vec<(String, String)>,
+    pub unmounted: Vec<(String, String)>,
+}
+
+impl DevicesChanged {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.mounted.is_empty()
+            && self.unmounted.is_empty()
+    }
+}
+
+struct Snapshot {
+    devices: BTreeSet<String>,
+    mounts: BTreeSet<(String, String)>,
+}
+
+impl Snapshot {
+    fn read() -> Snapshot {
+        let read = |file: String| match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(err) => {
+                debug!("Could not read {}: {}", file, err);
+                String::new()
+            }
+        };
+        Snapshot {
+            devices: parse_partitions(&read(proc_partitions_file())),
+            mounts: parse_mountinfo(&read(proc_mountinfo_file())),
+        }
+    }
+
+    fn changes_to(&self, new: &Snapshot) -> DevicesChanged {
+        DevicesChanged {
+            added: new.devices.difference(&self.devices).cloned().collect(),
+            removed: self.devices.difference(&new.devices).cloned().collect(),
+            mounted: new.mounts.difference(&self.mounts).cloned().collect(),
+            unmounted: self.mounts.difference(&new.mounts).cloned().collect(),
+        }
+    }
+}
+
+/// Watches `/proc/partitions` & `/proc/self/mountinfo` for block device changes
+pub struct DeviceWatcher {
+    snapshot: Snapshot,
+    last_poll: Instant,
+}
+
+impl DeviceWatcher {
+    pub fn new() -> DeviceWatcher {
+        DeviceWatcher {
+            snapshot: Snapshot::read(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns changes since the last poll, re-reading at most every `POLL_INTERVAL`
+    pub fn poll(&mut self) -> Option<DevicesChanged> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let snapshot = Snapshot::read();
+        let changes = self.snapshot.changes_to(&snapshot);
+        self.snapshot = snapshot;
+        if changes.is_empty() {
+            None
+        } else {
+            Some(changes)
+        }
+    }
+}
+
+/**
+ * example /proc/partitions
+ * major minor  #blocks  name
+ *
+ *    8        0  732574584 sda
+ *    8        1     102400 sda1
+ */
+fn parse_partitions(partitions: &str) -> BTreeSet<String> {
+    partitions
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [major, _, _, name] if major.parse::<u32>().is_ok() => Some((*name).to_owned()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/**
+ * example /proc/self/mountinfo line
+ * 36 35 98:0 / /mnt/my\040usb rw,noatime master:1 - ext4 /dev/sdc1 rw,errors=continue
+ */
+fn parse_mountinfo(mountinfo: &str) -> BTreeSet<(String, String)> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mountpoint = line.split(' ').nth(4)?;
+            let (_, after_separator) = line.split_once(" - ")?;
+            let source = after_separator.split(' ').nth(1)?;
+            if source.starts_with("/dev/") {
+                Some((unescape_octal(source), unescape_octal(mountpoint)))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// mountinfo escapes space, tab, newline & backslash as octal, ie `\040`
+fn unescape_octal(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let raw = s.as_bytes();
+    let mut idx = 0;
+    while idx < raw.len() {
+        if raw[idx] == b'\\'
+            && let Some(octal) = s.get(idx + 1..idx + 4)
+            && let Ok(byte) = u8::from_str_radix(octal, 8)
+        {
+            bytes.push(byte);
+            idx += 4;
+        } else {
+            bytes.push(raw[idx]);
+            idx += 1;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PARTITIONS: &str = "major minor  #blocks  name\n\
+                               \n   \
+                               8        0  732574584 sda\n   \
+                               8        1     102400 sda1\n \
+                               259        0  250059096 nvme0n1\n";
+
+    static MOUNTINFO: &str = "22 28 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw\n\
+         28 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+         36 28 8:33 / /media/my\\040usb rw,noatime shared:2 - vfat /dev/sdc1 rw\n";
+
+    #[test]
+    fn partitions() {
+        assert_eq!(
+            parse_partitions(PARTITIONS).into_iter().collect::<Vec<_>>(),
+            vec!["nvme0n1", "sda", "sda1"]
+        );
+    }
+
+    #[test]
+    fn mountinfo() {
+        assert_eq!(
+            parse_mountinfo(MOUNTINFO).into_iter().collect::<Vec<_>>(),
+            vec![
+                ("/dev/sda1".to_owned(), "/".to_owned()),
+                ("/dev/sdc1".to_owned(), "/media/my usb".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_between_snapshots() {
+        let old = Snapshot {
+            devices: parse_partitions(PARTITIONS),
+            mounts: parse_mountinfo(MOUNTINFO),
+        };
+        let new = Snapshot {
+            devices: parse_partitions(&format!(
+                "{}   8       32   62109253 sdc\n   8       33   62108204 sdc1\n",
+                PARTITIONS.replace("sda1", "sda2")
+            )),
+            mounts: parse_mountinfo(&MOUNTINFO.replace("/media/my\\040usb", "/mnt")),
+        };
+        assert_eq!(
+            old.changes_to(&new),
+            DevicesChanged {
+                added: vec!["sda2".to_owned(), "sdc".to_owned(), "sdc1".to_owned()],
+                removed: vec!["sda1".to_owned()],
+                mounted: vec![("/dev/sdc1".to_owned(), "/mnt".to_owned())],
+                unmounted: vec![("/dev/sdc1".to_owned(), "/media/my usb".to_owned())],
+            }
+        );
+        assert!(new.changes_to(&new).is_empty());
+    }
+}
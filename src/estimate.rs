@@ -0,0 +1,156 @@
+use crate::{compression::Compression, include::*, lsblk, partclone};
+use json::JsonValue;
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    thread,
+};
+
+/// Assumed partition read throughput in MB/s
+const ROTATIONAL_READ_MB_PER_SEC: u64 = 150;
+const SOLID_STATE_READ_MB_PER_SEC: u64 = 500;
+
+const MB: u64 = 1_000_000;
+
+/// Estimated image size & clone duration using a compression option
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Estimate {
+    pub compression: &'static str,
+    pub image_size: u64,
+    pub seconds: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CloneEstimate {
+    pub source: String,
+    /// partition size in bytes
+    pub size: u64,
+    /// bytes partclone will read
+    pub used: u64,
+    /// `false` when the used bytes of a file system are unknown, ie partclone couldn't read them,
+    /// so the partition size is used instead
+    pub used_exact: bool,
+    pub estimates: Vec<Estimate>,
+}
+
+/// Result of estimating a clone of a partition, referenced as in the request
+pub struct CloneEstimateResult(pub String, pub IoResult<CloneEstimate>);
+
+fn estimates(
+    used: u64,
+    read_mb_per_sec: u64,
+    cores: u64,
+    compressions: impl Iterator<Item = Compression>,
+) -> Vec<Estimate> {
+    compressions
+        .map(|z| {
+            let z_mb_per_sec = match z.multithreaded {
+                true => z.write_mb_per_sec * cores,
+                false => z.write_mb_per_sec,
+            };
+            let bytes_per_sec = read_mb_per_sec.min(z_mb_per_sec) * MB;
+            Estimate {
+                compression: z.name,
+                image_size: image_size(used, z),
+                seconds: used.div_ceil(bytes_per_sec),
+            }
+        })
+        .collect()
+}
+
+/// Returns the approximate image size of `used` bytes compressed with `z`
+pub fn image_size(used: u64, z: Compression) -> u64 {
+    used * z.size_percent / 100
+}
+
+/// Returns the bytes partclone will read from a lsblk partition & whether this is exact, without
+/// reading the file system so unmounted file systems are generally not exact
+pub fn used(part: &JsonValue) -> (u64, bool) {
+    let size = lsblk::parse_size(&part["size"]).unwrap_or_default();
+    let partclone_supported = part["fstype"]
+        .as_str()
+        .is_some_and(|fstype| partclone::cmd(fstype).is_ok());
+    // dd reads the entire partition, partclone only the used blocks
+    match lsblk::parse_size(&part["fsused"]) {
+        Some(used) if partclone_supported => (used, true),
+        _ => (size, !partclone_supported),
+    }
+}
+
+/// Returns the bytes partclone will read from the partition at `source` & whether this is exact,
+/// reading the used blocks of unmounted file systems with partclone
+pub fn used_reading_fs(part: &JsonValue, source: &str) -> (u64, bool) {
+    let (used, exact) = used(part);
+    if exact {
+        return (used, exact);
+    }
+    let fstype = part["fstype"].as_str().unwrap_or_default();
+    match partclone::used_bytes(fstype, source) {
+        Ok(used) => (used, true),
+        Err(err) => {
+            warn!("Could not read used blocks of {}: {}", source, err);
+            (used, exact)
+        }
+    }
+}
+
+/// Estimates image sizes & durations cloning a partition with each installed compression option
+pub fn estimate(source: String) -> IoResult<CloneEstimate> {
+    let part = lsblk::partition_matching(&source).ok_or_else(|| {
+        IoError::new(
+            ErrorKind::NotFound,
+            format!("No partition found at {}", source),
+        )
+    })?;
+    let size = lsblk::parse_size(&part["size"]).unwrap_or_default();
+    let (used, used_exact) = used_reading_fs(&part, &source);
+
+    let read_mb_per_sec = match lsblk::parse_flag(&part["rota"]) {
+        Some(false) => SOLID_STATE_READ_MB_PER_SEC,
+        _ => ROTATIONAL_READ_MB_PER_SEC,
+    };
+    let cores = thread::available_parallelism().map_or(1, |n| n.get() as u64);
+
+    Ok(CloneEstimate {
+        source,
+        size,
+        used,
+        used_exact,
+        estimates: estimates(used, read_mb_per_sec, cores, Compression::all_installed()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_per_compression() {
+        let gz = Compression::from_name("gz").unwrap();
+        let lz4 = Compression::from_name("lz4").unwrap();
+        let none = Compression::from_name("uncompressed").unwrap();
+
+        let estimates = estimates(10_000 * MB, 500, 2, [gz, lz4, none].into_iter());
+        assert_eq!(
+            estimates,
+            vec![
+                // 2 cores of 110MB/s
+                Estimate {
+                    compression: "gz",
+                    image_size: 2_500 * MB,
+                    seconds: 46,
+                },
+                // limited by reading at 500MB/s
+                Estimate {
+                    compression: "lz4",
+                    image_size: 3_000 * MB,
+                    seconds: 20,
+                },
+                Estimate {
+                    compression: "uncompressed",
+                    image_size: 10_000 * MB,
+                    seconds: 20,
+                },
+            ]
+        );
+    }
+}
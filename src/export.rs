@@ -0,0 +1,116 @@
+use crate::{child, include::*};
+use std::{
+    fs,
+    io::{ErrorKind, Result as IoResult},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+/// Disk image formats images can be exported to
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExportFormat {
+    /// sparse raw disk image
+    #[default]
+    Raw,
+    /// converted from raw with `qemu-img`
+    Qcow2,
+}
+
+const ALL: &[ExportFormat] = &[ExportFormat::Raw, ExportFormat::Qcow2];
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Result<ExportFormat, String> {
+        ALL.iter()
+            .find(|format| format.name() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown export format `{}`", name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Raw => "raw",
+            ExportFormat::Qcow2 => "qcow2",
+        }
+    }
+
+    pub fn all_installed() -> impl Iterator<Item = ExportFormat> {
+        ALL.iter().filter(|format| format.is_installed()).copied()
+    }
+
+    fn is_installed(self) -> bool {
+        // checked once, rather than on every status request
+        static QEMU_IMG_INSTALLED: OnceLock<bool> = OnceLock::new();
+        match self {
+            ExportFormat::Raw => true,
+            ExportFormat::Qcow2 => *QEMU_IMG_INSTALLED.get_or_init(|| {
+                match Command::new(child::cmd("qemu-img"))
+                    .arg("--version")
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                {
+                    Ok(_) => true,
+                    Err(e) => {
+                        if e.kind() != ErrorKind::NotFound {
+                            warn!("Error checking if `qemu-img` is installed: {}", e);
+                        }
+                        false
+                    }
+                }
+            }),
+        }
+    }
+}
+
+/// Final destination & format of an image export
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Export {
+    pub destination: String,
+    pub format: ExportFormat,
+}
+
+impl Export {
+    /// Returns the file the raw disk image is restored into before `finish`
+    pub fn inprogress_destination(&self) -> String {
+        format!("{}.inprogress", self.destination)
+    }
+
+    /// Moves or converts the restored raw disk image to the export destination
+    pub fn finish(&self) -> IoResult<()> {
+        let raw = self.inprogress_destination();
+        match self.format {
+            ExportFormat::Raw => fs::rename(&raw, &self.destination),
+            ExportFormat::Qcow2 => {
+                info!("Converting {} to qcow2 {}", raw, self.destination);
+                let converted = child::run(
+                    Command::new(child::cmd("qemu-img"))
+                        .args(["convert", "-f", "raw", "-O", "qcow2"])
+                        .arg(&raw)
+                        .arg(&self.destination),
+                );
+                if converted.is_err()
+                    && let Err(err) = fs::remove_file(&self.destination)
+                    && err.kind() != ErrorKind::NotFound
+                {
+                    error!("Could not rm partial export {}: {}", self.destination, err);
+                }
+                // a conversion failure takes precedence over failing to remove the raw image
+                let removed = fs::remove_file(&raw);
+                converted.and(removed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_name() {
+        assert_eq!(ExportFormat::from_name("qcow2"), Ok(ExportFormat::Qcow2));
+        assert_eq!(ExportFormat::from_name("raw"), Ok(ExportFormat::Raw));
+        assert!(ExportFormat::from_name("vmdk").is_err());
+    }
+}
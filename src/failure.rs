@@ -0,0 +1,186 @@
+use crate::space;
+use chrono::prelude::*;
+use std::{
+    sync::mpsc::{Receiver, TryRecvError},
+    time::{Duration, Instant},
+};
+
+/// Longest a failed job waits for its (de)compressor to exit & output why
+const COMPRESSOR_OUTPUT_WAIT: Duration = Duration::from_secs(1);
+
+/// Lowercase output fragments & the failure cause they indicate, in order of precedence as ie a
+/// compressor out of space also breaks partclone's pipe
+const CAUSES: &[(&[&str], &str)] = &[
+    (&["no space left on device"], space::OUT_OF_SPACE),
+    (
+        &[
+            "crc error",
+            "crc32",
+            "checksum",
+            "corrupt",
+            "invalid compressed data",
+            "unexpected end of file",
+            "decompression error",
+            "not in gzip format",
+        ],
+        "Image corrupt",
+    ),
+    (
+        &[
+            "input/output error",
+            "i/o error",
+            "bad sector",
+            "read error",
+        ],
+        "Bad sectors",
+    ),
+    (
+        &["device or resource busy", "is mounted", "target is busy"],
+        "Source busy",
+    ),
+    (&["unsupported"], "Unsupported filesystem feature"),
+    (
+        &["permission denied", "operation not permitted"],
+        "Permission denied",
+    ),
+    (&["broken pipe"], "Broken pipe"),
+];
+
+/// Returns the cause of a failure described in command output
+pub fn classify<'a>(output: impl IntoIterator<Item = &'a str>) -> Option<&'static str> {
+    let output: Vec<_> = output.into_iter().map(str::to_lowercase).collect();
+    CAUSES
+        .iter()
+        .find(|(fragments, _)| {
+            output
+                .iter()
+                .any(|line| fragments.iter().any(|fragment| line.contains(fragment)))
+        })
+        .map(|&(_, cause)| cause)
+}
+
+/// A failed clone or restore's classified reason & the output it was derived from
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Failure {
+    pub reason: String,
+    /// partclone output tail followed by (de)compressor output
+    pub output: Vec<String>,
+}
+
+impl Failure {
+    /// Classifies partclone output along with (de)compressor stderr output
+    fn classified(partclone_output: Vec<String>, z_out: &str) -> Failure {
+        let mut output = partclone_output;
+        output.extend(
+            z_out
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned),
+        );
+        Failure {
+            reason: classify(output.iter().map(String::as_str))
+                .unwrap_or("Failed")
+                .to_owned(),
+            output,
+        }
+    }
+}
+
+/// A failed clone or restore waiting, without blocking, for its (de)compressor's stderr output
+/// to classify the failure with
+#[derive(Debug)]
+pub struct PendingFailure {
+    pub finish: DateTime<Utc>,
+    partclone_output: Vec<String>,
+    since: Instant,
+}
+
+impl PendingFailure {
+    pub fn new(finish: DateTime<Utc>, partclone_output: Vec<String>) -> PendingFailure {
+        PendingFailure {
+            finish,
+            partclone_output,
+            since: Instant::now(),
+        }
+    }
+
+    /// Returns the classified failure once the (de)compressor has output or exited, or has been
+    /// waited on long enough, otherwise `None`
+    pub fn try_failure(&self, z_stderr: &Receiver<String>) -> Option<Failure> {
+        let z_out = match z_stderr.try_recv() {
+            Ok(z_out) => z_out,
+            Err(TryRecvError::Empty) if self.since.elapsed() < COMPRESSOR_OUTPUT_WAIT => {
+                return None;
+            }
+            Err(_) => String::new(),
+        };
+        Some(Failure::classified(self.partclone_output.clone(), &z_out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_causes() {
+        assert_eq!(
+            classify(["pigz: abort: write error on <stdout> (No space left on device)"]),
+            Some("Destination out of space")
+        );
+        assert_eq!(
+            classify(["pigz: skipping: <stdin>: corrupted -- crc32 mismatch"]),
+            Some("Image corrupt")
+        );
+        assert_eq!(
+            classify(["read error at block 123456: Input/output error"]),
+            Some("Bad sectors")
+        );
+        assert_eq!(
+            classify(["open /dev/sda1 error: Device or resource busy"]),
+            Some("Source busy")
+        );
+        assert_eq!(
+            classify(["Filesystem has unsupported feature(s): metadata_csum_seed"]),
+            Some("Unsupported filesystem feature")
+        );
+        assert_eq!(
+            classify(["open /dev/sdb1 error: Permission denied"]),
+            Some("Permission denied")
+        );
+        assert_eq!(classify(["write error: Broken pipe"]), Some("Broken pipe"));
+        assert_eq!(classify(["Mock failure"]), None);
+    }
+
+    #[test]
+    fn pending_failure_waits_for_compressor_output() {
+        let (tx, z_stderr) = std::sync::mpsc::channel();
+        let pending = PendingFailure::new(Utc::now(), vec!["write error: Broken pipe".to_owned()]);
+        assert_eq!(pending.try_failure(&z_stderr), None);
+
+        tx.send("pigz: abort: write error on <stdout> (No space left on device)\n".to_owned())
+            .unwrap();
+        assert_eq!(
+            pending.try_failure(&z_stderr),
+            Some(Failure {
+                reason: "Destination out of space".to_owned(),
+                output: vec![
+                    "write error: Broken pipe".to_owned(),
+                    "pigz: abort: write error on <stdout> (No space left on device)".to_owned(),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_space_precedes_broken_pipe() {
+        assert_eq!(
+            classify([
+                "write error: Broken pipe",
+                "zstd: error 70 : Write error : cannot write block : No space left on device",
+            ]),
+            Some("Destination out of space")
+        );
+    }
+}
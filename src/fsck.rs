@@ -0,0 +1,228 @@
+use crate::{child, include::*, lsblk};
+use chrono::prelude::*;
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fmt,
+    io::{BufRead, BufReader, Error as IoError, ErrorKind, Read, Result as IoResult},
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc,
+        mpsc::{Receiver, Sender, TryRecvError},
+    },
+    thread,
+};
+use uuid::Uuid;
+
+/// Returns the read-only checker program & args for a file system type
+fn checker(fstype: &str) -> Option<(&'static str, &'static [&'static str])> {
+    match fstype {
+        "ext2" | "ext3" | "ext4" => Some(("e2fsck", &["-n"])),
+        "f2fs" => Some(("fsck.f2fs", &["--dry-run"])),
+        "ntfs" => Some(("ntfsfix", &["-n"])),
+        "btrfs" => Some(("btrfs", &["check", "--readonly"])),
+        _ => None,
+    }
+}
+
+/// Forces a check of an ext2/3/4 file system repairing any errors, as required before
+/// modifying it with `resize2fs` or `tune2fs`
+pub fn repair_ext(device: &str) -> IoResult<()> {
+    let status = Command::new(child::cmd("e2fsck"))
+        .args(["-f", "-y", device])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    // exit code 1 => errors corrected
+    match status.code() {
+        Some(0 | 1) => Ok(()),
+        _ => Err(IoError::other(format!("e2fsck failed: {}", status))),
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FsckStatusCommon {
+    pub id: String,
+    pub device: String,
+    pub fstype: String,
+    pub start: DateTime<Utc>,
+    /// `true` when checking before cloning the device, ie `fsck_first`
+    pub before_clone: bool,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum FsckStatus {
+    Running {
+        common: FsckStatusCommon,
+        /// a line of checker output
+        output: Option<String>,
+    },
+    Finished {
+        common: FsckStatusCommon,
+        finish: DateTime<Utc>,
+        /// `false` when the checker found errors
+        clean: bool,
+        exit_code: Option<i32>,
+    },
+}
+
+/// A read-only file system check of a device
+#[derive(Debug)]
+pub struct FsckJob {
+    id: Uuid,
+    device: String,
+    fstype: String,
+    program: &'static str,
+    start: DateTime<Utc>,
+    before_clone: bool,
+    fsck_cmd: RefCell<Child>,
+    sent_first_msg: Cell<bool>,
+    output: Receiver<String>,
+}
+
+fn send_lines(output: impl Read, tx: Sender<String>) {
+    for line in BufReader::new(output).lines() {
+        match line {
+            Ok(line) => {
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                warn!("Failed to read fsck output: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+impl FsckJob {
+    pub fn new(device: String, before_clone: bool) -> IoResult<FsckJob> {
+        let fstype = lsblk::fstype(&device).unwrap_or_default();
+        let (program, args) = checker(&fstype).ok_or_else(|| {
+            IoError::new(
+                ErrorKind::Unsupported,
+                format!("No filesystem check available for {device} fstype '{fstype}'"),
+            )
+        })?;
+
+        let mut fsck_cmd = Command::new(child::cmd(program))
+            .args(args)
+            .arg(&device)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| IoError::new(err.kind(), format!("Failed to run {program}: {err}")))?;
+
+        let (tx, output) = mpsc::channel();
+        let stdout = fsck_cmd.stdout.take().unwrap();
+        let stderr = fsck_cmd.stderr.take().unwrap();
+        let stderr_tx = tx.clone();
+        thread::Builder::new()
+            .name(format!("fsck-stdout-reader {}", device))
+            .spawn(move || send_lines(stdout, tx))?;
+        thread::Builder::new()
+            .name(format!("fsck-stderr-reader {}", device))
+            .spawn(move || send_lines(stderr, stderr_tx))?;
+
+        Ok(FsckJob {
+            id: Uuid::new_v4(),
+            device,
+            fstype,
+            program,
+            start: Utc::now(),
+            before_clone,
+            fsck_cmd: RefCell::new(fsck_cmd),
+            sent_first_msg: Cell::new(false),
+            output,
+        })
+    }
+
+    pub fn try_recv(&self) -> Result<FsckStatus, Box<dyn Error>> {
+        if !self.sent_first_msg.get() {
+            self.sent_first_msg.set(true);
+            return Ok(FsckStatus::Running {
+                common: self.status_common(),
+                output: None,
+            });
+        }
+
+        match self.output.try_recv() {
+            Ok(line) => Ok(FsckStatus::Running {
+                common: self.status_common(),
+                output: Some(line),
+            }),
+            Err(TryRecvError::Empty) => Err(TryRecvError::Empty.into()),
+            // all output has been read
+            Err(TryRecvError::Disconnected) => match self.fsck_cmd.borrow_mut().try_wait()? {
+                Some(status) => Ok(FsckStatus::Finished {
+                    common: self.status_common(),
+                    finish: Utc::now(),
+                    clean: status.success(),
+                    exit_code: status.code(),
+                }),
+                None => Err("Waiting for fsck to finish".into()),
+            },
+        }
+    }
+
+    pub fn status_common(&self) -> FsckStatusCommon {
+        FsckStatusCommon {
+            id: self.id(),
+            device: self.device.clone(),
+            fstype: self.fstype.clone(),
+            start: self.start,
+            before_clone: self.before_clone,
+        }
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}", self.id)
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Describes why a finished check failed, ie "e2fsck found errors on /dev/sda1 (exit code 4)"
+    pub fn failure_reason(&self, exit_code: Option<i32>) -> String {
+        match exit_code {
+            Some(code) => format!(
+                "{} found errors on {} (exit code {})",
+                self.program, self.device, code
+            ),
+            None => format!("{} was killed checking {}", self.program, self.device),
+        }
+    }
+}
+
+impl Drop for FsckJob {
+    fn drop(&mut self) {
+        child::drop_log_errors(&mut self.fsck_cmd.borrow_mut(), "FsckJob#fsck_cmd");
+    }
+}
+
+impl fmt::Display for FsckJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FsckJob({} {})", self.program, self.device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_checkers() {
+        assert_eq!(checker("ext4"), Some(("e2fsck", &["-n"][..])));
+        assert_eq!(checker("f2fs"), Some(("fsck.f2fs", &["--dry-run"][..])));
+        assert_eq!(checker("ntfs"), Some(("ntfsfix", &["-n"][..])));
+        assert_eq!(
+            checker("btrfs"),
+            Some(("btrfs", &["check", "--readonly"][..]))
+        );
+        assert_eq!(checker("swap"), None);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{child, fsck, include::*};
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    process::Command,
+};
+use uuid::Uuid;
+
+/// A file system uuid to set after restoring
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NewUuid {
+    Random,
+    Given(Uuid),
+}
+
+impl NewUuid {
+    pub fn to_uuid(self) -> Uuid {
+        match self {
+            NewUuid::Random => Uuid::new_v4(),
+            NewUuid::Given(uuid) => uuid,
+        }
+    }
+}
+
+/// File system types `set` supports, "extfs" being any ext of an image partclone.extfs made
+pub fn is_supported(fstype: &str) -> bool {
+    matches!(fstype, "ext2" | "ext3" | "ext4" | "extfs" | "xfs" | "btrfs")
+}
+
+/// Rewrites the uuid of the file system on the device
+pub fn set(device: &str, fstype: &str, uuid: Uuid) -> IoResult<()> {
+    info!(
+        "Setting {} filesystem uuid on {} to {}",
+        fstype, device, uuid
+    );
+    let uuid = uuid.to_string();
+    match fstype {
+        "ext2" | "ext3" | "ext4" | "extfs" => {
+            // tune2fs requires a freshly checked file system to change checksummed metadata
+            fsck::repair_ext(device)?;
+            child::run(
+                Command::new(child::cmd("tune2fs"))
+                    .args(["-U", &uuid])
+                    .arg(device),
+            )
+        }
+        "xfs" => child::run(
+            Command::new(child::cmd("xfs_admin"))
+                .args(["-U", &uuid])
+                .arg(device),
+        ),
+        // -f to skip the confirmation prompt
+        "btrfs" => child::run(
+            Command::new(child::cmd("btrfstune"))
+                .args(["-f", "-U", &uuid])
+                .arg(device),
+        ),
+        _ => Err(IoError::new(
+            ErrorKind::Unsupported,
+            format!("Cannot set {} filesystem uuid", fstype),
+        )),
+    }
+}
@@ -0,0 +1,425 @@
+use crate::{
+    clone::CloneStatus, fsck::FsckStatusCommon, include::*, restore::RestoreStatus,
+    server::DeleteResult,
+};
+use chrono::{TimeDelta, prelude::*};
+use json::JsonValue;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Error as IoError, ErrorKind, Result as IoResult, Write},
+    path::{Path, PathBuf},
+};
+
+/// A finished or failed job, or image deletion, as appended to the history file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HistoryEntry {
+    /// "clone", "restore", "export", "fsck" or "delete"
+    pub kind: String,
+    /// job id, `None` for deletions
+    pub id: Option<String>,
+    /// what was requested, ie the "source", "destination" & other request parameters
+    pub parameters: BTreeMap<String, String>,
+    /// "finished" or "failed"
+    pub outcome: String,
+    pub reason: Option<String>,
+    pub start: DateTime<Utc>,
+    pub finish: DateTime<Utc>,
+    /// bytes of the image written, read or deleted
+    pub image_size: Option<u64>,
+    /// file system uuid of the cloned partition
+    pub source_uuid: Option<String>,
+}
+
+pub fn parameters<const N: usize>(params: [(&str, &str); N]) -> BTreeMap<String, String> {
+    params
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn outcome(reason: &Option<String>) -> String {
+    match reason {
+        Some(_) => "failed".to_owned(),
+        None => "finished".to_owned(),
+    }
+}
+
+impl HistoryEntry {
+    /// Returns the entry of a finished or failed clone, `None` while it's still running
+    pub fn of_clone(status: &CloneStatus) -> Option<HistoryEntry> {
+        let (common, finish, reason, image_size) = match status {
+            CloneStatus::Finished {
+                common,
+                finish,
+                image_size,
+                ..
+            } => (common, *finish, None, Some(*image_size)),
+            CloneStatus::Failed {
+                common,
+                finish,
+                reason,
+                ..
+            } => (common, *finish, Some(reason.clone()), None),
+            _ => return None,
+        };
+        Some(HistoryEntry {
+            kind: "clone".to_owned(),
+            id: Some(common.id.clone()),
+            parameters: parameters([
+                ("source", &common.source),
+                ("destination", &common.destination),
+            ]),
+            outcome: outcome(&reason),
+            reason,
+            start: common.start,
+            finish,
+            image_size,
+            source_uuid: common.source_uuid.clone(),
+        })
+    }
+
+    /// Returns the entry of a finished or failed restore or export, `None` while it's still
+    /// running
+    pub fn of_restore(status: &RestoreStatus) -> Option<HistoryEntry> {
+        let (common, finish, reason, new_uuid) = match status {
+            RestoreStatus::Finished {
+                common,
+                finish,
+                new_uuid,
+            } => (common, *finish, None, new_uuid.as_deref()),
+            RestoreStatus::Failed {
+                common,
+                finish,
+                reason,
+                ..
+            } => (common, *finish, Some(reason.clone()), None),
+            _ => return None,
+        };
+        let mut parameters = parameters([
+            ("source", &common.source),
+            ("destination", &common.destination),
+        ]);
+        if let Some(new_uuid) = new_uuid {
+            parameters.insert("new_uuid".to_owned(), new_uuid.to_owned());
+        }
+        Some(HistoryEntry {
+            kind: common.kind.to_owned(),
+            id: Some(common.id.clone()),
+            parameters,
+            outcome: outcome(&reason),
+            reason,
+            start: common.start,
+            finish,
+            image_size: fs::metadata(&common.source).ok().map(|meta| meta.len()),
+            source_uuid: None,
+        })
+    }
+
+    /// Returns the entry of a finished file system check, `reason` being why it wasn't clean
+    pub fn of_fsck(
+        common: &FsckStatusCommon,
+        finish: DateTime<Utc>,
+        reason: Option<String>,
+    ) -> HistoryEntry {
+        let mut parameters = parameters([("device", &common.device), ("fstype", &common.fstype)]);
+        if common.before_clone {
+            parameters.insert("before_clone".to_owned(), "true".to_owned());
+        }
+        HistoryEntry {
+            kind: "fsck".to_owned(),
+            id: Some(common.id.clone()),
+            parameters,
+            outcome: outcome(&reason),
+            reason,
+            start: common.start,
+            finish,
+            image_size: None,
+            source_uuid: None,
+        }
+    }
+
+    /// Returns the entry of an image deletion, started at `start` of an image of `image_size`
+    pub fn of_delete(
+        result: &DeleteResult,
+        start: DateTime<Utc>,
+        image_size: Option<u64>,
+    ) -> HistoryEntry {
+        let DeleteResult(ref file, ref result) = *result;
+        let reason = result.as_ref().err().map(|err| err.to_string());
+        HistoryEntry {
+            kind: "delete".to_owned(),
+            id: None,
+            parameters: parameters([("file", file)]),
+            outcome: outcome(&reason),
+            reason,
+            start,
+            finish: Utc::now(),
+            image_size,
+            source_uuid: None,
+        }
+    }
+
+    /// Returns the entry with the requested parameters it doesn't already have
+    pub fn with_parameters(mut self, requested: &BTreeMap<String, String>) -> HistoryEntry {
+        for (key, value) in requested {
+            (self.parameters.entry(key.clone())).or_insert_with(|| value.clone());
+        }
+        self
+    }
+
+    pub fn duration(&self) -> TimeDelta {
+        self.finish - self.start
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        json["kind"] = self.kind.as_str().into();
+        json["id"] = self.id.clone().into();
+        let mut parameters = JsonValue::new_object();
+        for (key, value) in &self.parameters {
+            parameters[key.as_str()] = value.as_str().into();
+        }
+        json["parameters"] = parameters;
+        json["outcome"] = self.outcome.as_str().into();
+        json["reason"] = self.reason.clone().into();
+        json["start"] = format!("{:?}", self.start).into();
+        json["finish"] = format!("{:?}", self.finish).into();
+        json["duration_seconds"] = (self.duration().num_milliseconds() as f64 / 1000.0).into();
+        json["image_size"] = self.image_size.into();
+        json["source_uuid"] = self.source_uuid.clone().into();
+        json
+    }
+
+    fn from_json(json: &JsonValue) -> Option<HistoryEntry> {
+        let timestamp = |key: &str| {
+            DateTime::parse_from_rfc3339(json[key].as_str()?)
+                .ok()
+                .map(|dt| dt.with_timezone(&Utc))
+        };
+        Some(HistoryEntry {
+            kind: json["kind"].as_str()?.to_owned(),
+            id: json["id"].as_str().map(|id| id.to_owned()),
+            parameters: json["parameters"]
+                .entries()
+                .filter_map(|(key, value)| Some((key.to_owned(), value.as_str()?.to_owned())))
+                .collect(),
+            outcome: json["outcome"].as_str()?.to_owned(),
+            reason: json["reason"].as_str().map(|reason| reason.to_owned()),
+            start: timestamp("start")?,
+            finish: timestamp("finish")?,
+            image_size: json["image_size"].as_u64(),
+            source_uuid: json["source_uuid"].as_str().map(|uuid| uuid.to_owned()),
+        })
+    }
+}
+
+/// Criteria of a `history-request`, entries must match all that are given
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct HistoryFilter {
+    pub source_uuid: Option<String>,
+    /// entries started at or after
+    pub since: Option<DateTime<Utc>>,
+    /// entries started before
+    pub until: Option<DateTime<Utc>>,
+    /// "finished" or "failed"
+    pub outcome: Option<String>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.source_uuid
+            .as_ref()
+            .is_none_or(|uuid| entry.source_uuid.as_ref() == Some(uuid))
+            && self.since.is_none_or(|since| entry.start >= since)
+            && self.until.is_none_or(|until| entry.start < until)
+            && self
+                .outcome
+                .as_ref()
+                .is_none_or(|outcome| &entry.outcome == outcome)
+    }
+}
+
+/// Append-only log of finished jobs & deletions, one json object per line
+#[derive(Clone, Default, Debug)]
+pub struct HistoryLog {
+    file: Option<PathBuf>,
+}
+
+/// Response to a `history-request`
+pub struct HistoryResult(pub IoResult<Vec<HistoryEntry>>);
+
+impl HistoryLog {
+    /// Returns the log at `APART_HISTORY_FILE`, or the default file without it,
+    /// an empty `APART_HISTORY_FILE` disabling history
+    pub fn from_env() -> HistoryLog {
+        let file = match env::var_os("APART_HISTORY_FILE") {
+            Some(file) if file.is_empty() => None,
+            Some(file) => Some(PathBuf::from(file)),
+            None => Some(default_file(env::var_os("XDG_STATE_HOME"))),
+        };
+        HistoryLog { file }
+    }
+
+    /// Appends an entry, logging failure to do so
+    pub fn record(&self, entry: Option<HistoryEntry>) {
+        if let (Some(file), Some(entry)) = (&self.file, entry)
+            && let Err(err) = append(file, &entry)
+        {
+            error!("Failed to record history in {}: {}", file.display(), err);
+        }
+    }
+
+    /// Returns the recorded entries matching the filter, oldest first
+    pub fn query(&self, filter: &HistoryFilter) -> IoResult<Vec<HistoryEntry>> {
+        let file = self.file.as_ref().ok_or_else(|| {
+            IoError::new(
+                ErrorKind::NotFound,
+                "No history recorded, APART_HISTORY_FILE is empty",
+            )
+        })?;
+        let lines = match File::open(file) {
+            Ok(file) => BufReader::new(file).lines(),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line?;
+            match json::parse(&line)
+                .ok()
+                .and_then(|j| HistoryEntry::from_json(&j))
+            {
+                Some(entry) if filter.matches(&entry) => entries.push(entry),
+                Some(_) => (),
+                None if line.trim().is_empty() => (),
+                None => warn!("Ignoring invalid history line: {}", line),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Returns `$XDG_STATE_HOME/apart/history.jsonl`, or `/var/lib/apart/history.jsonl` without a
+/// state home
+fn default_file(state_home: Option<OsString>) -> PathBuf {
+    state_home
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| PathBuf::from("/var/lib"), PathBuf::from)
+        .join("apart")
+        .join("history.jsonl")
+}
+
+fn append(file: &Path, entry: &HistoryEntry) -> IoResult<()> {
+    let line = format!("{}\n", entry.to_json().dump());
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?
+        .write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn entry(kind: &str, outcome: &str, start: DateTime<Utc>) -> HistoryEntry {
+        HistoryEntry {
+            kind: kind.to_owned(),
+            id: Some("some-id".to_owned()),
+            parameters: parameters([("source", "/dev/sda5"), ("destination", "/mnt/a.gz")]),
+            outcome: outcome.to_owned(),
+            reason: match outcome {
+                "failed" => Some("Mock failure".to_owned()),
+                _ => None,
+            },
+            start,
+            finish: start + TimeDelta::milliseconds(12_500),
+            image_size: Some(1234),
+            source_uuid: Some("0c6c4fcb".to_owned()),
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let entry = entry("clone", "failed", Utc::now());
+        let json = entry.to_json();
+        assert_eq!(json["duration_seconds"].as_f64(), Some(12.5));
+        assert_eq!(HistoryEntry::from_json(&json), Some(entry));
+    }
+
+    #[test]
+    fn filter() {
+        let start = Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap();
+        let entry = entry("clone", "finished", start);
+        assert!(HistoryFilter::default().matches(&entry));
+
+        let by_uuid = |uuid: &str| HistoryFilter {
+            source_uuid: Some(uuid.to_owned()),
+            ..<_>::default()
+        };
+        assert!(by_uuid("0c6c4fcb").matches(&entry));
+        assert!(!by_uuid("a4d2fc10").matches(&entry));
+
+        let between = |since, until| HistoryFilter {
+            since: Some(since),
+            until: Some(until),
+            ..<_>::default()
+        };
+        assert!(between(start, start + TimeDelta::days(1)).matches(&entry));
+        assert!(!between(start - TimeDelta::days(1), start).matches(&entry));
+
+        let failed = HistoryFilter {
+            outcome: Some("failed".to_owned()),
+            ..<_>::default()
+        };
+        assert!(!failed.matches(&entry));
+    }
+
+    #[test]
+    fn default_history_file() {
+        assert_eq!(
+            default_file(Some("/home/alex/.local/state".into())),
+            PathBuf::from("/home/alex/.local/state/apart/history.jsonl")
+        );
+        assert_eq!(
+            default_file(Some("".into())),
+            PathBuf::from("/var/lib/apart/history.jsonl")
+        );
+        assert_eq!(
+            default_file(None),
+            PathBuf::from("/var/lib/apart/history.jsonl")
+        );
+    }
+
+    #[test]
+    fn record_and_query() {
+        let dir = env::temp_dir().join(format!("apart-history-{}", Uuid::new_v4()));
+        let file = dir.join("history.jsonl");
+        let log = HistoryLog {
+            file: Some(file.clone()),
+        };
+        assert_eq!(log.query(&HistoryFilter::default()).unwrap(), vec![]);
+
+        let start = Utc::now();
+        log.record(Some(entry("clone", "finished", start)));
+        log.record(None);
+        log.record(Some(entry("restore", "failed", start)));
+        let failed = HistoryFilter {
+            outcome: Some("failed".to_owned()),
+            ..<_>::default()
+        };
+        let entries = log.query(&failed);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(entries.unwrap(), vec![entry("restore", "failed", start)]);
+        assert!(HistoryLog::default().query(&failed).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{asynchronous, child, history::HistoryEntry, include::*};
+use std::{
+    env,
+    io::Error as IoError,
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default time a hook may run before it's killed, a pre-hook failing its job
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Time between checks of a running hook for exit, cancellation or timeout
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Returns how long a hook may run, overridable in seconds with `APART_HOOK_TIMEOUT`
+pub fn hook_timeout() -> Duration {
+    match env::var("APART_HOOK_TIMEOUT") {
+        Ok(secs) => secs.parse().map(Duration::from_secs).unwrap_or_else(|_| {
+            warn!("Invalid APART_HOOK_TIMEOUT {}, using default", secs);
+            DEFAULT_HOOK_TIMEOUT
+        }),
+        Err(_) => DEFAULT_HOOK_TIMEOUT,
+    }
+}
+
+/// Shell commands run before a clone or restore job starts & after it finishes or fails
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Hooks {
+    pub pre: Option<String>,
+    pub post: Option<String>,
+}
+
+impl Hooks {
+    /// Returns the hooks set with `APART_PRE_HOOK` & `APART_POST_HOOK`
+    pub fn from_env() -> Hooks {
+        Hooks {
+            pre: env::var("APART_PRE_HOOK").ok(),
+            post: env::var("APART_POST_HOOK").ok(),
+        }
+    }
+
+    /// Returns these hooks falling back to `defaults`, an empty command disabling a hook
+    pub fn or(self, defaults: &Hooks) -> Hooks {
+        let or = |hook: Option<String>, default: &Option<String>| {
+            hook.or_else(|| default.clone())
+                .filter(|cmd| !cmd.is_empty())
+        };
+        Hooks {
+            pre: or(self.pre, &defaults.pre),
+            post: or(self.post, &defaults.post),
+        }
+    }
+}
+
+/// Describes a job to its hooks, as `APART_JOB_*` env vars
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct JobEnv {
+    /// `None` before the job starts
+    pub id: Option<String>,
+    /// "clone", "restore" or "export"
+    pub kind: String,
+    pub source: String,
+    pub destination: String,
+    /// the image file cloned to or restored from, when known
+    pub image: Option<String>,
+    /// "finished" or "failed" after the job
+    pub outcome: Option<String>,
+    pub reason: Option<String>,
+}
+
+impl JobEnv {
+    /// Returns the env of a job about to start
+    pub fn new(kind: &str, source: &str, destination: &str) -> JobEnv {
+        JobEnv {
+            kind: kind.to_owned(),
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            image: (kind != "clone").then(|| source.to_owned()),
+            ..JobEnv::default()
+        }
+    }
+
+    /// Returns the env of a job that failed to start
+    pub fn failed(self, reason: &str) -> JobEnv {
+        JobEnv {
+            outcome: Some("failed".to_owned()),
+            reason: Some(reason.to_owned()),
+            ..self
+        }
+    }
+
+    /// Returns the env of a finished or failed job
+    pub fn of_entry(entry: &HistoryEntry) -> JobEnv {
+        let param = |key: &str| entry.parameters.get(key).cloned().unwrap_or_default();
+        let (source, destination) = (param("source"), param("destination"));
+        JobEnv {
+            id: entry.id.clone(),
+            kind: entry.kind.clone(),
+            image: Some(match entry.kind.as_str() {
+                "clone" => destination.clone(),
+                _ => source.clone(),
+            }),
+            source,
+            destination,
+            outcome: Some(entry.outcome.clone()),
+            reason: entry.reason.clone(),
+        }
+    }
+
+    fn vars(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("APART_JOB_ID", self.id.as_deref()),
+            ("APART_JOB_KIND", Some(self.kind.as_str())),
+            ("APART_JOB_SOURCE", Some(self.source.as_str())),
+            ("APART_JOB_DESTINATION", Some(self.destination.as_str())),
+            ("APART_JOB_IMAGE", self.image.as_deref()),
+            ("APART_JOB_OUTCOME", self.outcome.as_deref()),
+            ("APART_JOB_REASON", self.reason.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(var, value)| Some((var, value?)))
+        .collect()
+    }
+}
+
+/// Returns the hook command in a new process group, so it can be killed with what it started
+fn command(hook: &str, cmd: &str, job: &JobEnv) -> Command {
+    info!("Running {}-hook for {} job: {}", hook, job.kind, cmd);
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(cmd)
+        .env("APART_HOOK", hook)
+        .envs(job.vars())
+        .process_group(0);
+    command
+}
+
+/// Kills the hook's process group, not just its `sh`, then waits for it
+fn kill_group(hook_cmd: &mut Child, log_name: &str) {
+    // SAFETY: killpg only sends a signal, the group id being the hook's own pid
+    if unsafe { libc::killpg(hook_cmd.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+        let err = IoError::last_os_error();
+        error!("Failed to kill {} process group: {}", log_name, err);
+    }
+    child::drop_log_errors(hook_cmd, log_name);
+}
+
+/// Runs a hook command with `sh -c` to completion, killing it beyond the `hook_timeout`. Errors
+/// include the command's stderr output.
+fn run(hook: &str, cmd: &str, job: &JobEnv) -> Result<(), String> {
+    run_killable(hook, cmd, job, &AtomicBool::new(false), hook_timeout())
+}
+
+/// Runs a hook command like `run`, killing it once `cancel` is set or it runs beyond `timeout`
+fn run_killable(
+    hook: &str,
+    cmd: &str,
+    job: &JobEnv,
+    cancel: &AtomicBool,
+    timeout: Duration,
+) -> Result<(), String> {
+    let failed = |reason: String| format!("{}-hook failed: {}", hook, reason);
+    let log_name = format!("{}-hook", hook);
+    let start = Instant::now();
+    let mut hook_cmd = command(hook, cmd, job)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(err.to_string()))?;
+    let stderr =
+        child::read_stderr(&mut hook_cmd, &log_name).map_err(|err| failed(err.to_string()))?;
+    loop {
+        match hook_cmd.try_wait() {
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => {
+                let stderr = stderr.recv().unwrap_or_default();
+                return Err(failed(match stderr.trim() {
+                    "" => format!("{:?} failed: {}", cmd, status),
+                    reason => reason.to_owned(),
+                }));
+            }
+            Ok(None) if cancel.load(Ordering::Relaxed) => {
+                kill_group(&mut hook_cmd, &log_name);
+                return Err(format!("{} cancelled", log_name));
+            }
+            Ok(None) if start.elapsed() > timeout => {
+                kill_group(&mut hook_cmd, &log_name);
+                return Err(format!("{} timed out after {:?}", log_name, timeout));
+            }
+            Ok(None) => thread::sleep(HOOK_POLL_INTERVAL),
+            Err(err) => return Err(failed(err.to_string())),
+        }
+    }
+}
+
+/// Runs a pre-hook concurrently, received once it exits. The hook is killed once `cancel` is set
+/// or it runs beyond the `hook_timeout`.
+pub fn pre(cmd: String, job: JobEnv, cancel: Arc<AtomicBool>) -> Receiver<Result<(), String>> {
+    let timeout = hook_timeout();
+    asynchronous::receiver(move || run_killable("pre", &cmd, &job, &cancel, timeout))
+}
+
+/// Runs a post-hook, logging failure as the job has already ended. The hook is killed once it
+/// runs beyond the `hook_timeout`.
+pub fn post(cmd: &str, job: &JobEnv) {
+    if let Err(err) = run("post", cmd, job) {
+        error!("{}", err);
+    }
+}
+
+/// Runs a post-hook concurrently
+pub fn spawn_post(cmd: String, job: JobEnv) {
+    thread::spawn(move || post(&cmd, &job));
+}
+
+/// Runs post-hooks concurrently, waiting at most `wait` for them all. Hooks still running after
+/// are left to finish or time out on their own.
+pub fn post_all(hooks: Vec<(String, JobEnv)>, wait: Duration) {
+    let deadline = Instant::now() + wait;
+    let running: Vec<_> = (hooks.into_iter())
+        .map(|(cmd, job)| asynchronous::receiver(move || post(&cmd, &job)))
+        .collect();
+    for hook in running {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if hook.recv_timeout(remaining).is_err() {
+            warn!("Not waiting for post-hooks still running after {:?}", wait);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_hooks_override_defaults() {
+        let defaults = Hooks {
+            pre: Some("quiesce".to_owned()),
+            post: Some("sync".to_owned()),
+        };
+        let hooks = Hooks {
+            pre: None,
+            post: Some("upload".to_owned()),
+        };
+        assert_eq!(
+            hooks.or(&defaults),
+            Hooks {
+                pre: Some("quiesce".to_owned()),
+                post: Some("upload".to_owned()),
+            }
+        );
+        let disabled = Hooks {
+            pre: Some("".to_owned()),
+            post: None,
+        };
+        assert_eq!(
+            disabled.or(&defaults),
+            Hooks {
+                pre: None,
+                post: Some("sync".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn run_with_job_env() {
+        let job = JobEnv::new("restore", "/mnt/backups/a.apt.dd.gz", "/dev/sda5");
+        assert_eq!(
+            run(
+                "pre",
+                r#"[ "$APART_HOOK $APART_JOB_IMAGE $APART_JOB_DESTINATION" = "pre /mnt/backups/a.apt.dd.gz /dev/sda5" ] \
+                   && [ -z "${APART_JOB_ID+set}" ]"#,
+                &job
+            ),
+            Ok(())
+        );
+        let failed = job.failed("Cancelled");
+        assert_eq!(
+            run(
+                "post",
+                r#"echo "$APART_JOB_OUTCOME: $APART_JOB_REASON" >&2; exit 3"#,
+                &failed
+            ),
+            Err("post-hook failed: failed: Cancelled".to_owned())
+        );
+    }
+
+    #[test]
+    fn kill_pre_hook() {
+        let job = JobEnv::new("clone", "/dev/sda5", "/mnt/backups");
+        let timeout = Duration::from_secs(60);
+        assert_eq!(
+            run_killable(
+                "pre",
+                "echo busy >&2; exit 2",
+                &job,
+                &AtomicBool::new(false),
+                timeout
+            ),
+            Err("pre-hook failed: busy".to_owned())
+        );
+
+        let start = Instant::now();
+        let short_timeout = Duration::from_millis(100);
+        let marker = env::temp_dir().join(format!("apart-hook-{}", std::process::id()));
+        let spawning = format!("sh -c 'sleep 0.5; touch {}'; true", marker.display());
+        assert_eq!(
+            run_killable(
+                "pre",
+                &spawning,
+                &job,
+                &AtomicBool::new(false),
+                short_timeout
+            ),
+            Err("pre-hook timed out after 100ms".to_owned())
+        );
+        assert_eq!(
+            run_killable(
+                "pre",
+                "sleep 10; true",
+                &job,
+                &AtomicBool::new(true),
+                timeout
+            ),
+            Err("pre-hook cancelled".to_owned())
+        );
+        assert!(start.elapsed() < Duration::from_secs(5), "hook not killed");
+        thread::sleep(Duration::from_secs(1));
+        assert!(!marker.exists(), "process started by hook not killed");
+    }
+}
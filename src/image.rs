@@ -0,0 +1,200 @@
+use crate::{child, compression::Compression, include::*};
+use std::{
+    fs::File,
+    io::{Error as IoError, Read, Result as IoResult},
+    process::{Command, Stdio},
+};
+
+/// Bytes of decompressed image read to parse the header, enough for all partclone versions
+const HEADER_LEN: usize = 512;
+
+/// Result of reading an image file's partclone header, `None` for dd images
+pub struct ImageInfo(pub String, pub IoResult<Option<PartcloneHeader>>);
+
+/// Metadata from the header of a partclone image, absent in dd images
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartcloneHeader {
+    /// image format version, ie "0001" or "0002"
+    pub version: String,
+    /// version of partclone that created the image, ie "0.3.13", absent in v1 images
+    pub partclone_version: Option<String>,
+    /// file system type, ie "EXTFS", "NTFS"
+    pub fs: String,
+    /// size of the imaged partition in bytes
+    pub device_size: u64,
+    pub block_size: u32,
+    pub total_blocks: u64,
+    pub used_blocks: u64,
+}
+
+fn str_at(bytes: &[u8], start: usize, end: usize) -> Option<String> {
+    let field = bytes.get(start..end)?;
+    let field = field.split(|b| *b == 0).next().unwrap_or_default();
+    Some(String::from_utf8_lossy(field).trim().to_owned())
+}
+
+fn u32_at(bytes: &[u8], start: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(start..start + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(bytes: &[u8], start: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(start..start + 8)?.try_into().ok()?,
+    ))
+}
+
+impl PartcloneHeader {
+    /// Parses the start of a decompressed partclone image, `None` if not a partclone image
+    pub fn parse(bytes: &[u8]) -> Option<PartcloneHeader> {
+        if !bytes.starts_with(b"partclone-image") {
+            return None;
+        }
+        match str_at(bytes, 30, 34)?.as_str() {
+            // struct image_head
+            "0001" => Some(PartcloneHeader {
+                version: "0001".to_owned(),
+                partclone_version: None,
+                fs: str_at(bytes, 15, 30)?,
+                block_size: u32_at(bytes, 36)?,
+                device_size: u64_at(bytes, 40)?,
+                total_blocks: u64_at(bytes, 48)?,
+                used_blocks: u64_at(bytes, 56)?,
+            }),
+            // struct image_desc_v2, after the partclone version & endianness
+            "0002" => Some(PartcloneHeader {
+                version: "0002".to_owned(),
+                partclone_version: str_at(bytes, 16, 30),
+                fs: str_at(bytes, 36, 52)?,
+                device_size: u64_at(bytes, 52)?,
+                total_blocks: u64_at(bytes, 60)?,
+                used_blocks: u64_at(bytes, 68)?,
+                block_size: u32_at(bytes, 84)?,
+            }),
+            version => {
+                warn!("Unknown partclone image version {}", version);
+                None
+            }
+        }
+    }
+
+    /// Returns the partclone variant able to restore the image, ie "extfs" for `partclone.extfs`
+    pub fn partclone_variant(&self) -> String {
+        match self.fs.as_str() {
+            "FAT12" | "FAT16" | "FAT32" => "fat".to_owned(),
+            "HFS Plus" => "hfsp".to_owned(),
+            fs => fs
+                .to_lowercase()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), ""),
+        }
+    }
+}
+
+/// Returns the first decompressed bytes of an image file
+pub fn read_start(file: &str, z: Compression) -> IoResult<Vec<u8>> {
+    let mut decompress = Command::new(z.command)
+        .args(z.read_args)
+        .stdin(File::open(file)?)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN);
+    let read = decompress
+        .stdout
+        .take()
+        .expect("!decompress.stdout")
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut bytes);
+    child::drop_log_errors(&mut decompress, "image::read_start");
+    read?;
+    Ok(bytes)
+}
+
+/// Returns the partclone header of an image file, `None` for dd images
+pub fn partclone_header(file: &str) -> IoResult<Option<PartcloneHeader>> {
+    let z = Compression::from_file_name(file).map_err(IoError::other)?;
+    Ok(PartcloneHeader::parse(&read_start(file, z)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header() -> Vec<u8> {
+        let mut bytes = vec![0; 110];
+        bytes[..16].copy_from_slice(b"partclone-image\0");
+        bytes[16..23].copy_from_slice(b"0.3.13\0");
+        bytes[30..34].copy_from_slice(b"0002");
+        bytes[34..36].copy_from_slice(&0xC0DE_u16.to_le_bytes());
+        bytes[36..42].copy_from_slice(b"EXTFS\0");
+        bytes[52..60].copy_from_slice(&1_073_741_824_u64.to_le_bytes());
+        bytes[60..68].copy_from_slice(&262_144_u64.to_le_bytes());
+        bytes[68..76].copy_from_slice(&12_345_u64.to_le_bytes());
+        bytes[76..84].copy_from_slice(&12_345_u64.to_le_bytes());
+        bytes[84..88].copy_from_slice(&4096_u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn parse_v2_header() {
+        assert_eq!(
+            PartcloneHeader::parse(&v2_header()),
+            Some(PartcloneHeader {
+                version: "0002".to_owned(),
+                partclone_version: Some("0.3.13".to_owned()),
+                fs: "EXTFS".to_owned(),
+                device_size: 1_073_741_824,
+                block_size: 4096,
+                total_blocks: 262_144,
+                used_blocks: 12_345,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_v1_header() {
+        let mut bytes = vec![0; 64];
+        bytes[..15].copy_from_slice(b"partclone-image");
+        bytes[15..19].copy_from_slice(b"NTFS");
+        bytes[30..34].copy_from_slice(b"0001");
+        bytes[36..40].copy_from_slice(&4096_u32.to_le_bytes());
+        bytes[40..48].copy_from_slice(&104_857_600_u64.to_le_bytes());
+        bytes[48..56].copy_from_slice(&25_600_u64.to_le_bytes());
+        bytes[56..64].copy_from_slice(&6_400_u64.to_le_bytes());
+        assert_eq!(
+            PartcloneHeader::parse(&bytes),
+            Some(PartcloneHeader {
+                version: "0001".to_owned(),
+                partclone_version: None,
+                fs: "NTFS".to_owned(),
+                device_size: 104_857_600,
+                block_size: 4096,
+                total_blocks: 25_600,
+                used_blocks: 6_400,
+            })
+        );
+    }
+
+    #[test]
+    fn partclone_variant_from_fs() {
+        let header = |fs: &str| PartcloneHeader {
+            fs: fs.to_owned(),
+            ..PartcloneHeader::parse(&v2_header()).unwrap()
+        };
+        assert_eq!(header("EXTFS").partclone_variant(), "extfs");
+        assert_eq!(header("NTFS").partclone_variant(), "ntfs");
+        assert_eq!(header("FAT32").partclone_variant(), "fat");
+        assert_eq!(header("HFS Plus").partclone_variant(), "hfsp");
+    }
+
+    #[test]
+    fn parse_non_partclone() {
+        assert_eq!(
+            PartcloneHeader::parse(b"mock-partition-/dev/sda5-data"),
+            None
+        );
+        assert_eq!(PartcloneHeader::parse(&v2_header()[..60]), None);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{compression::Compression, image, image::PartcloneHeader, include::*};
+use chrono::prelude::*;
+use regex::Regex;
+use std::{
+    fs,
+    fs::{File, OpenOptions},
+    io,
+    io::{Error as IoError, ErrorKind, Read, Result as IoResult},
+    os::unix,
+    path::{Path, PathBuf},
+};
+
+/// Result of importing a Clonezilla image directory or partclone image file
+pub struct ImportResult(pub String, pub IoResult<Vec<String>>);
+
+/// A partition image to import, possibly split into multiple pieces
+#[derive(Debug)]
+struct Import {
+    /// apart backup name, ie "sda1"
+    name: String,
+    variant: String,
+    compression: Compression,
+    /// files in order, ie "sda1.ext4-ptcl-img.gz.aa", "sda1.ext4-ptcl-img.gz.ab"
+    pieces: Vec<PathBuf>,
+}
+
+/// Parses a Clonezilla partition image file name, ie "sda1.ext4-ptcl-img.gz.aa", returning the
+/// partition, partclone variant & compression
+fn parse_clonezilla_name(file_name: &str) -> Option<(String, String, IoResult<Compression>)> {
+    let clonezilla_re = Regex::new(r"^([^.]+)\.([^.]+)-ptcl-img\.([^.]+)(?:\.[a-z]{2,})?$")
+        .expect("!clonezilla_re");
+
+    let caps = clonezilla_re.captures(file_name)?;
+    let z = match &caps[3] {
+        "uncomp" => Compression::from_name("uncompressed"),
+        z => Compression::from_name(z),
+    }
+    .map_err(|_| {
+        IoError::new(
+            ErrorKind::Unsupported,
+            format!("Unsupported {} compression of {}", &caps[3], file_name),
+        )
+    });
+    Some((caps[1].to_owned(), caps[2].to_owned(), z))
+}
+
+/// Returns the partition images of a Clonezilla image directory
+fn clonezilla_imports(dir: &Path, name: Option<&str>) -> IoResult<Vec<Import>> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    // split pieces sort by suffix ".aa", ".ab", ...
+    files.sort();
+
+    let mut imports: Vec<Import> = Vec::new();
+    for file in files {
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+        let Some((partition, variant, z)) = parse_clonezilla_name(&file_name) else {
+            continue;
+        };
+        let name = match name {
+            Some(name) => format!("{}-{}", name, partition),
+            None => partition,
+        };
+        match imports.iter_mut().find(|import| import.name == name) {
+            Some(import) => import.pieces.push(file),
+            None => imports.push(Import {
+                name,
+                variant,
+                compression: z?,
+                pieces: vec![file],
+            }),
+        }
+    }
+
+    if imports.is_empty() {
+        return Err(IoError::new(
+            ErrorKind::NotFound,
+            format!("No Clonezilla partition images found in {}", dir.display()),
+        ));
+    }
+    Ok(imports)
+}
+
+/// Returns the partition image of a single file, either named by Clonezilla or a partclone
+/// image with any supported compression
+fn file_import(file: &Path, name: Option<&str>) -> IoResult<Import> {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let default_name = file_name.split('.').next().unwrap_or_default();
+    let name = name.unwrap_or(default_name).to_owned();
+
+    if let Some((_, variant, z)) = parse_clonezilla_name(&file_name) {
+        return Ok(Import {
+            name,
+            variant,
+            compression: z?,
+            pieces: vec![file.to_owned()],
+        });
+    }
+
+    let mut magic = Vec::with_capacity(4);
+    File::open(file)?.take(4).read_to_end(&mut magic)?;
+    let z = Compression::from_magic(&magic);
+    let path = file.to_string_lossy();
+    let header = PartcloneHeader::parse(&image::read_start(&path, z)?).ok_or_else(|| {
+        IoError::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a partclone image", path),
+        )
+    })?;
+    Ok(Import {
+        name,
+        variant: header.partclone_variant(),
+        compression: z,
+        pieces: vec![file.to_owned()],
+    })
+}
+
+/// Links or concatenates an image into `directory` with an apart image name
+fn import_image(import: Import, directory: &str) -> IoResult<String> {
+    let first = &import.pieces[0];
+    let timestamp = DateTime::<Local>::from(first.metadata()?.modified()?);
+    let file = format!(
+        "{directory}/{name}-{timestamp}.apt.{variant}.{z_name}",
+        directory = directory,
+        name = import.name,
+        timestamp = timestamp.format("%Y-%m-%dT%H%M"),
+        variant = import.variant,
+        z_name = import.compression.name
+    );
+    if Path::new(&file).exists() {
+        return Err(IoError::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", file),
+        ));
+    }
+
+    if let [piece] = import.pieces.as_slice() {
+        if let Err(err) = fs::hard_link(piece, &file) {
+            debug!("Cannot hard link {}, symlinking: {}", piece.display(), err);
+            unix::fs::symlink(fs::canonicalize(piece)?, &file)?;
+        }
+        return Ok(file);
+    }
+
+    let inprogress = format!("{}.inprogress", file);
+    let concatenated = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&inprogress)
+        .and_then(|mut dest| {
+            for piece in &import.pieces {
+                io::copy(&mut File::open(piece)?, &mut dest)?;
+            }
+            fs::rename(&inprogress, &file)
+        });
+    if let Err(err) = concatenated {
+        if let Err(rm_err) = fs::remove_file(&inprogress) {
+            warn!("Could not rm {}: {}", inprogress, rm_err);
+        }
+        return Err(err);
+    }
+    Ok(file)
+}
+
+/// Imports a Clonezilla image directory or partclone image file into `directory`, returning the
+/// new image files
+pub fn import(source: &str, directory: &str, name: Option<&str>) -> IoResult<Vec<String>> {
+    let source_path = Path::new(source);
+    let imports = if source_path.is_dir() {
+        clonezilla_imports(source_path, name)?
+    } else {
+        vec![file_import(source_path, name)?]
+    };
+    imports
+        .into_iter()
+        .map(|import| import_image(import, directory))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clonezilla_names() {
+        let (partition, variant, z) = parse_clonezilla_name("sda1.ext4-ptcl-img.gz.aa").unwrap();
+        assert_eq!(partition, "sda1");
+        assert_eq!(variant, "ext4");
+        assert_eq!(z.unwrap().name, "gz");
+
+        let (partition, variant, z) = parse_clonezilla_name("nvme0n1p2.dd-ptcl-img.zst").unwrap();
+        assert_eq!(partition, "nvme0n1p2");
+        assert_eq!(variant, "dd");
+        assert_eq!(z.unwrap().name, "zst");
+
+        let (_, _, z) = parse_clonezilla_name("sda3.ntfs-ptcl-img.uncomp.aa").unwrap();
+        assert_eq!(z.unwrap().name, "uncompressed");
+
+        let (_, _, z) = parse_clonezilla_name("sda1.ext4-ptcl-img.bz2.aa").unwrap();
+        assert_eq!(
+            z.unwrap_err().to_string(),
+            "Unsupported bz2 compression of sda1.ext4-ptcl-img.bz2.aa"
+        );
+
+        assert!(parse_clonezilla_name("sda-pt.sf").is_none());
+        assert!(parse_clonezilla_name("clonezilla-img").is_none());
+    }
+}
@@ -1,25 +1,69 @@
 use self::Request::*;
-use crate::{compression::Compression, include::*};
-use yaml_rust::YamlLoader;
+use crate::{
+    compression::Compression,
+    export::{Export, ExportFormat},
+    fsuuid::NewUuid,
+    history::HistoryFilter,
+    hooks::Hooks,
+    include::*,
+    lsblk::PartitionRef,
+    retention::RetentionPolicy,
+};
+use chrono::prelude::*;
+use std::time::Duration;
+use yaml_rust::{Yaml, YamlLoader};
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Request {
     Status,
     Kill,
+    Jobs,
+    Job {
+        id: String,
+    },
+    /// Sets the interval between progress messages of jobs not requesting their own
+    ProgressInterval {
+        interval: Option<Duration>,
+    },
 
     Clone {
-        source: String,
+        source: PartitionRef,
         destination: String,
         name: String,
         compression: Compression,
+        retention: Option<RetentionPolicy>,
+        unmount_first: bool,
+        fsck_first: bool,
+        progress_interval: Option<Duration>,
+        hooks: Hooks,
     },
     CancelClone {
         id: String,
     },
+    CloneEstimate {
+        source: PartitionRef,
+    },
 
     Restore {
+        source: String,
+        destination: PartitionRef,
+        unmount_first: bool,
+        expand_filesystem: bool,
+        new_uuid: Option<NewUuid>,
+        progress_interval: Option<Duration>,
+        hooks: Hooks,
+    },
+    RestoreToFile {
         source: String,
         destination: String,
+        progress_interval: Option<Duration>,
+        hooks: Hooks,
+    },
+    ExportRaw {
+        source: String,
+        export: Export,
+        progress_interval: Option<Duration>,
+        hooks: Hooks,
     },
     CancelRestore {
         id: String,
@@ -28,6 +72,144 @@ pub enum Request {
     DeleteImage {
         file: String,
     },
+    ImageInfo {
+        file: String,
+    },
+    ImportImage {
+        source: String,
+        destination: String,
+        name: Option<String>,
+    },
+    Prune {
+        directory: String,
+        name: String,
+        retention: RetentionPolicy,
+    },
+
+    Mount {
+        device: PartitionRef,
+    },
+    Unmount {
+        device: PartitionRef,
+    },
+
+    Fsck {
+        device: PartitionRef,
+    },
+
+    History {
+        filter: HistoryFilter,
+    },
+}
+
+/// Parses a partition reference given as exactly one of a device `path` or the `uuid`,
+/// `label` or `partuuid` keys
+fn partition_ref(
+    msg: &Yaml,
+    path: &str,
+    uuid: &str,
+    label: &str,
+    partuuid: &str,
+) -> Option<PartitionRef> {
+    let mut given: Vec<_> = [
+        msg[path].as_str().map(|p| PartitionRef::Path(p.to_owned())),
+        msg[uuid]
+            .as_str()
+            .map(|id| PartitionRef::Uuid(id.to_owned())),
+        msg[label]
+            .as_str()
+            .map(|l| PartitionRef::Label(l.to_owned())),
+        msg[partuuid]
+            .as_str()
+            .map(|id| PartitionRef::PartUuid(id.to_owned())),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if given.len() > 1 {
+        warn!("Ambiguous partition, only one of {path}, {uuid}, {label}, {partuuid} allowed");
+        return None;
+    }
+    given.pop()
+}
+
+/// Parses a `retention` yaml hash, `Err` if any rule is invalid or the policy would keep
+/// no images at all
+fn retention_policy(yaml: &Yaml) -> Result<Option<RetentionPolicy>, String> {
+    match yaml {
+        Yaml::BadValue => return Ok(None),
+        Yaml::Hash(_) => {}
+        other => return Err(format!("Invalid retention {:?}", other)),
+    }
+    let rule = |key: &str| match &yaml[key] {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(n) if *n >= 0 => Ok(Some(*n as usize)),
+        other => Err(format!("Invalid retention {}: {:?}", key, other)),
+    };
+    let policy = RetentionPolicy {
+        keep_last: rule("keep_last")?,
+        keep_daily: rule("keep_daily")?,
+        keep_weekly: rule("keep_weekly")?,
+    };
+    let rules = [policy.keep_last, policy.keep_daily, policy.keep_weekly];
+    if !policy.is_empty() && rules.iter().all(|n| n.unwrap_or(0) == 0) {
+        return Err(format!("Invalid retention {:?}, keeps no images", policy));
+    }
+    Ok(Some(policy))
+}
+
+/// Parses an optional `new_uuid: true|<uuid>`
+fn new_uuid(value: &Yaml) -> Result<Option<NewUuid>, String> {
+    match value {
+        Yaml::BadValue | Yaml::Boolean(false) => Ok(None),
+        Yaml::Boolean(true) => Ok(Some(NewUuid::Random)),
+        Yaml::String(uuid) => match uuid::Uuid::parse_str(uuid) {
+            Ok(uuid) => Ok(Some(NewUuid::Given(uuid))),
+            Err(err) => Err(format!("Invalid new_uuid {}: {}", uuid, err)),
+        },
+        _ => Err(format!("Invalid new_uuid {:?}", value)),
+    }
+}
+
+/// Parses an optional `progress_interval` in seconds, ie `0.5`
+fn progress_interval(value: &Yaml) -> Result<Option<Duration>, String> {
+    let seconds = match value {
+        Yaml::BadValue | Yaml::Null => return Ok(None),
+        Yaml::Integer(n) => *n as f64,
+        Yaml::Real(_) => value.as_f64().unwrap_or(-1.0),
+        _ => -1.0,
+    };
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(interval) => Ok(Some(interval)),
+        Err(_) => Err(format!("Invalid progress_interval {:?}", value)),
+    }
+}
+
+/// Parses an optional rfc3339 timestamp, ie `2017-04-18T15:44:12Z`
+fn timestamp(value: &Yaml) -> Result<Option<DateTime<Utc>>, String> {
+    match value {
+        Yaml::BadValue | Yaml::Null => Ok(None),
+        Yaml::String(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+            .map(|dt| Some(dt.with_timezone(&Utc)))
+            .map_err(|err| format!("Invalid timestamp {}: {}", timestamp, err)),
+        _ => Err(format!("Invalid timestamp {:?}", value)),
+    }
+}
+
+/// Parses the optional filters of a `history-request`
+fn history_filter(msg: &Yaml) -> Result<HistoryFilter, String> {
+    let outcome = match msg["outcome"].as_str() {
+        None => None,
+        Some(outcome @ ("finished" | "failed")) => Some(outcome.to_owned()),
+        Some(outcome) => return Err(format!("Invalid history outcome {}", outcome)),
+    };
+    Ok(HistoryFilter {
+        source_uuid: msg["source_uuid"].as_str().map(|uuid| uuid.to_owned()),
+        since: timestamp(&msg["since"])?,
+        until: timestamp(&msg["until"])?,
+        outcome,
+    })
 }
 
 impl Request {
@@ -43,9 +225,40 @@ impl Request {
             if let Some("kill-request") = msg_type {
                 return Some(Kill);
             }
+            if let Some("jobs-request") = msg_type {
+                return Some(Jobs);
+            }
+            if let (Some("job-request"), Some(id)) = (msg_type, msg["id"].as_str()) {
+                return Some(Job { id: id.to_owned() });
+            }
+            if let Some("history-request") = msg_type {
+                return match history_filter(&msg) {
+                    Ok(filter) => Some(History { filter }),
+                    Err(err) => {
+                        warn!("{}", err);
+                        None
+                    }
+                };
+            }
+            let progress_interval = match progress_interval(&msg["progress_interval"]) {
+                Ok(interval) => interval,
+                Err(err) => {
+                    warn!("{}", err);
+                    return None;
+                }
+            };
+            let hooks = Hooks {
+                pre: msg["pre_hook"].as_str().map(|cmd| cmd.to_owned()),
+                post: msg["post_hook"].as_str().map(|cmd| cmd.to_owned()),
+            };
+            if let Some("progress-interval") = msg_type {
+                return Some(ProgressInterval {
+                    interval: progress_interval,
+                });
+            }
             if let (Some("clone"), Some(source), Some(dest), Some(name), compression) = (
                 msg_type,
-                msg["source"].as_str(),
+                partition_ref(&msg, "source", "source_uuid", "source_label", "partuuid"),
                 msg["destination"].as_str(),
                 msg["name"].as_str(),
                 msg["compression"].as_str(),
@@ -64,21 +277,88 @@ impl Request {
                     }
                 };
 
+                let retention = match retention_policy(&msg["retention"]) {
+                    Ok(retention) => retention,
+                    Err(err) => {
+                        warn!("{}", err);
+                        return None;
+                    }
+                };
+
                 return Some(Clone {
-                    source: source.to_owned(),
+                    source,
                     destination: dest.to_owned(),
                     name: name.to_owned(),
                     compression: z,
+                    retention,
+                    unmount_first: msg["unmount_first"].as_bool().unwrap_or(false),
+                    fsck_first: msg["fsck_first"].as_bool().unwrap_or(false),
+                    progress_interval,
+                    hooks,
+                });
+            }
+            if let (Some("restore"), Some(source), Some(destination)) = (
+                msg_type,
+                msg["source"].as_str(),
+                partition_ref(
+                    &msg,
+                    "destination",
+                    "destination_uuid",
+                    "destination_label",
+                    "partuuid",
+                ),
+            ) {
+                return match new_uuid(&msg["new_uuid"]) {
+                    Ok(new_uuid) => Some(Restore {
+                        source: source.to_owned(),
+                        destination,
+                        unmount_first: msg["unmount_first"].as_bool().unwrap_or(false),
+                        expand_filesystem: msg["expand_filesystem"].as_bool().unwrap_or(false),
+                        new_uuid,
+                        progress_interval,
+                        hooks,
+                    }),
+                    Err(err) => {
+                        warn!("{}", err);
+                        None
+                    }
+                };
+            }
+            if let (Some("restore-to-file"), Some(source), Some(destination)) = (
+                msg_type,
+                msg["source"].as_str(),
+                msg["destination"].as_str(),
+            ) {
+                return Some(RestoreToFile {
+                    source: source.to_owned(),
+                    destination: destination.to_owned(),
+                    progress_interval,
+                    hooks,
                 });
             }
-            if let (Some("restore"), Some(source), Some(dest)) = (
+            if let (Some("export-raw"), Some(source), Some(destination)) = (
                 msg_type,
                 msg["source"].as_str(),
                 msg["destination"].as_str(),
             ) {
-                return Some(Restore {
+                let format = match msg["format"].as_str() {
+                    Some(name) => match ExportFormat::from_name(name) {
+                        Ok(format) => format,
+                        Err(err) => {
+                            warn!("{}", err);
+                            return None;
+                        }
+                    },
+                    None => ExportFormat::default(),
+                };
+                return Some(ExportRaw {
                     source: source.to_owned(),
-                    destination: dest.to_owned(),
+                    export: Export {
+                        destination: destination.to_owned(),
+                        format,
+                    },
+                    progress_interval,
+                    hooks,
                 });
             }
             if let (Some("cancel-clone"), Some(id)) = (msg_type, msg["id"].as_str()) {
@@ -92,6 +372,57 @@ impl Request {
                     file: file.to_owned(),
                 });
             }
+            if let (Some("image-info"), Some(file)) = (msg_type, msg["file"].as_str()) {
+                return Some(ImageInfo {
+                    file: file.to_owned(),
+                });
+            }
+            if let (Some("import-image"), Some(source), Some(destination)) = (
+                msg_type,
+                msg["source"].as_str(),
+                msg["destination"].as_str(),
+            ) {
+                return Some(ImportImage {
+                    source: source.to_owned(),
+                    destination: destination.to_owned(),
+                    name: msg["name"].as_str().map(|name| name.to_owned()),
+                });
+            }
+            if let (Some("prune"), Some(directory), Some(name)) =
+                (msg_type, msg["directory"].as_str(), msg["name"].as_str())
+            {
+                return match retention_policy(&msg["retention"]) {
+                    Ok(Some(retention)) => Some(Prune {
+                        directory: directory.to_owned(),
+                        name: name.to_owned(),
+                        retention,
+                    }),
+                    Ok(None) => {
+                        warn!("Prune request missing retention");
+                        None
+                    }
+                    Err(err) => {
+                        warn!("{}", err);
+                        None
+                    }
+                };
+            }
+            if let (Some("clone-estimate"), Some(source)) = (
+                msg_type,
+                partition_ref(&msg, "source", "source_uuid", "source_label", "partuuid"),
+            ) {
+                return Some(CloneEstimate { source });
+            }
+            if let (Some(kind @ ("mount" | "unmount" | "fsck")), Some(device)) = (
+                msg_type,
+                partition_ref(&msg, "device", "uuid", "label", "partuuid"),
+            ) {
+                return Some(match kind {
+                    "mount" => Mount { device },
+                    "unmount" => Unmount { device },
+                    _ => Fsck { device },
+                });
+            }
         }
         None
     }
@@ -111,6 +442,57 @@ mod tests {
         assert_eq!(Request::parse("type: kill-request"), Some(Kill))
     }
 
+    #[test]
+    fn parse_jobs_request() {
+        assert_eq!(Request::parse("type: jobs-request"), Some(Jobs))
+    }
+
+    #[test]
+    fn parse_job_request() {
+        assert_eq!(
+            Request::parse("type: job-request\nid: 8db93abe"),
+            Some(Job {
+                id: "8db93abe".to_owned()
+            })
+        );
+        assert_eq!(Request::parse("type: job-request"), None);
+    }
+
+    #[test]
+    fn parse_history_request() {
+        assert_eq!(
+            Request::parse("type: history-request"),
+            Some(History {
+                filter: HistoryFilter::default()
+            })
+        );
+        assert_eq!(
+            Request::parse(
+                "type: history-request\n\
+                 source_uuid: 0c6c4fcb-9c5e-4d8c-a3e3-4b0e8d1a3b5f\n\
+                 since: 2017-04-01T00:00:00Z\n\
+                 until: 2017-05-01T00:00:00+01:00\n\
+                 outcome: failed"
+            ),
+            Some(History {
+                filter: HistoryFilter {
+                    source_uuid: Some("0c6c4fcb-9c5e-4d8c-a3e3-4b0e8d1a3b5f".to_owned()),
+                    since: Some(Utc.with_ymd_and_hms(2017, 4, 1, 0, 0, 0).unwrap()),
+                    until: Some(Utc.with_ymd_and_hms(2017, 4, 30, 23, 0, 0).unwrap()),
+                    outcome: Some("failed".to_owned()),
+                }
+            })
+        );
+        assert_eq!(
+            Request::parse("type: history-request\nsince: yesterday"),
+            None
+        );
+        assert_eq!(
+            Request::parse("type: history-request\noutcome: cancelled"),
+            None
+        );
+    }
+
     #[test]
     fn parse_empty() {
         assert_eq!(Request::parse(""), None)
@@ -127,10 +509,181 @@ mod tests {
         assert_eq!(
             message,
             Some(Clone {
-                source: "/dev/abc12".to_owned(),
+                source: PartitionRef::Path("/dev/abc12".to_owned()),
+                destination: "/mnt/backups/".to_owned(),
+                name: "alex".to_owned(),
+                compression: Compression::default(),
+                retention: None,
+                unmount_first: false,
+                fsck_first: false,
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_clone_request_with_retention() {
+        let message = Request::parse(
+            "type: clone\n\
+             source: /dev/abc12\n\
+             destination: /mnt/backups/\n\
+             name: alex\n\
+             fsck_first: true\n\
+             retention:\n  \
+               keep_last: 3\n  \
+               keep_weekly: 4",
+        );
+        assert_eq!(
+            message,
+            Some(Clone {
+                source: PartitionRef::Path("/dev/abc12".to_owned()),
                 destination: "/mnt/backups/".to_owned(),
                 name: "alex".to_owned(),
                 compression: Compression::default(),
+                retention: Some(RetentionPolicy {
+                    keep_last: Some(3),
+                    keep_daily: None,
+                    keep_weekly: Some(4),
+                }),
+                unmount_first: false,
+                fsck_first: true,
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_clone_estimate_request() {
+        let message = Request::parse(
+            "type: clone-estimate\n\
+             source_label: Arch",
+        );
+        assert_eq!(
+            message,
+            Some(CloneEstimate {
+                source: PartitionRef::Label("Arch".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_image_info_request() {
+        let message = Request::parse(
+            "type: image-info\n\
+             file: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz",
+        );
+        assert_eq!(
+            message,
+            Some(ImageInfo {
+                file: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_import_image_request() {
+        let message = Request::parse(
+            "type: import-image\n\
+             source: /mnt/clonezilla/2019-05-01-12-img\n\
+             destination: /mnt/backups\n\
+             name: laptop",
+        );
+        assert_eq!(
+            message,
+            Some(ImportImage {
+                source: "/mnt/clonezilla/2019-05-01-12-img".to_owned(),
+                destination: "/mnt/backups".to_owned(),
+                name: Some("laptop".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_prune_request() {
+        let message = Request::parse(
+            "type: prune\n\
+             directory: /mnt/backups\n\
+             name: alex\n\
+             retention:\n  \
+               keep_daily: 7",
+        );
+        assert_eq!(
+            message,
+            Some(Prune {
+                directory: "/mnt/backups".to_owned(),
+                name: "alex".to_owned(),
+                retention: RetentionPolicy {
+                    keep_daily: Some(7),
+                    ..RetentionPolicy::default()
+                },
+            })
+        );
+        assert_eq!(
+            Request::parse(
+                "type: prune\n\
+                 directory: /mnt/backups\n\
+                 name: alex\n\
+                 retention:\n  \
+                   keep_daily: -1",
+            ),
+            None
+        );
+        assert_eq!(
+            Request::parse(
+                "type: prune\n\
+                 directory: /mnt/backups\n\
+                 name: alex\n\
+                 retention:\n  \
+                   keep_last: 0",
+            ),
+            None
+        );
+        assert_eq!(
+            Request::parse(
+                "type: prune\n\
+                 directory: /mnt/backups\n\
+                 name: alex\n\
+                 retention: 3",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_progress_interval_request() {
+        assert_eq!(
+            Request::parse("type: progress-interval\nprogress_interval: 0.5"),
+            Some(ProgressInterval {
+                interval: Some(Duration::from_millis(500))
+            })
+        );
+        assert_eq!(
+            Request::parse("type: progress-interval"),
+            Some(ProgressInterval { interval: None })
+        );
+        assert_eq!(
+            Request::parse("type: progress-interval\nprogress_interval: -1"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_restore_request_with_progress_interval() {
+        let message = Request::parse(
+            "type: restore-to-file\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /tmp/sda1.img\n\
+             progress_interval: 2",
+        );
+        assert_eq!(
+            message,
+            Some(RestoreToFile {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                destination: "/tmp/sda1.img".to_owned(),
+                progress_interval: Some(Duration::from_secs(2)),
+                hooks: Hooks::default(),
             })
         );
     }
@@ -146,7 +699,215 @@ mod tests {
             message,
             Some(Restore {
                 source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
-                destination: "/dev/abc123".to_owned(),
+                destination: PartitionRef::Path("/dev/abc123".to_owned()),
+                unmount_first: false,
+                expand_filesystem: false,
+                new_uuid: None,
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_restore_request_with_hooks() {
+        let message = Request::parse(
+            "type: restore\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /dev/abc123\n\
+             pre_hook: systemctl stop postgresql\n\
+             post_hook: ''",
+        );
+        assert_eq!(
+            message,
+            Some(Restore {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                destination: PartitionRef::Path("/dev/abc123".to_owned()),
+                unmount_first: false,
+                expand_filesystem: false,
+                new_uuid: None,
+                progress_interval: None,
+                hooks: Hooks {
+                    pre: Some("systemctl stop postgresql".to_owned()),
+                    post: Some("".to_owned()),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn parse_clone_request_by_label() {
+        let message = Request::parse(
+            "type: clone\n\
+             source_label: Arch\n\
+             destination: /mnt/backups/\n\
+             name: alex",
+        );
+        assert_eq!(
+            message,
+            Some(Clone {
+                source: PartitionRef::Label("Arch".to_owned()),
+                destination: "/mnt/backups/".to_owned(),
+                name: "alex".to_owned(),
+                compression: Compression::default(),
+                retention: None,
+                unmount_first: false,
+                fsck_first: false,
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_restore_request_by_partuuid() {
+        let message = Request::parse(
+            "type: restore\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             partuuid: 0b4fd5c9-01\n\
+             unmount_first: true\n\
+             expand_filesystem: true\n\
+             new_uuid: true",
+        );
+        assert_eq!(
+            message,
+            Some(Restore {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                destination: PartitionRef::PartUuid("0b4fd5c9-01".to_owned()),
+                unmount_first: true,
+                expand_filesystem: true,
+                new_uuid: Some(NewUuid::Random),
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_restore_to_file_request() {
+        let message = Request::parse(
+            "type: restore-to-file\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /mnt/scratch/sda1.img",
+        );
+        assert_eq!(
+            message,
+            Some(RestoreToFile {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                destination: "/mnt/scratch/sda1.img".to_owned(),
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_export_raw_request() {
+        let message = Request::parse(
+            "type: export-raw\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /mnt/scratch/sda1.qcow2\n\
+             format: qcow2",
+        );
+        assert_eq!(
+            message,
+            Some(ExportRaw {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                export: Export {
+                    destination: "/mnt/scratch/sda1.qcow2".to_owned(),
+                    format: ExportFormat::Qcow2,
+                },
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+
+        let message = Request::parse(
+            "type: export-raw\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /mnt/scratch/sda1.img",
+        );
+        assert!(matches!(
+            message,
+            Some(ExportRaw {
+                export: Export {
+                    format: ExportFormat::Raw,
+                    ..
+                },
+                ..
+            })
+        ));
+
+        let message = Request::parse(
+            "type: export-raw\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /mnt/scratch/sda1.vmdk\n\
+             format: vmdk",
+        );
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn parse_restore_request_new_uuid() {
+        let message = Request::parse(
+            "type: restore\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /dev/abc123\n\
+             new_uuid: 2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4",
+        );
+        assert_eq!(
+            message,
+            Some(Restore {
+                source: "/mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz".to_owned(),
+                destination: PartitionRef::Path("/dev/abc123".to_owned()),
+                unmount_first: false,
+                expand_filesystem: false,
+                new_uuid: Some(NewUuid::Given(
+                    uuid::Uuid::parse_str("2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4").unwrap()
+                )),
+                progress_interval: None,
+                hooks: Hooks::default(),
+            })
+        );
+
+        let message = Request::parse(
+            "type: restore\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /dev/abc123\n\
+             new_uuid: not-a-uuid",
+        );
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn parse_ambiguous_partition() {
+        let message = Request::parse(
+            "type: restore\n\
+             source: /mnt/backups/sda1-2017-04-18T1739.apt.ext4.gz\n\
+             destination: /dev/abc123\n\
+             destination_uuid: 123-234",
+        );
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn parse_unmount_request() {
+        assert_eq!(
+            Request::parse("type: unmount\ndevice: /dev/sdb1"),
+            Some(Unmount {
+                device: PartitionRef::Path("/dev/sdb1".to_owned())
+            })
+        );
+        assert_eq!(
+            Request::parse("type: mount\nlabel: boot"),
+            Some(Mount {
+                device: PartitionRef::Label("boot".to_owned())
+            })
+        );
+        assert_eq!(
+            Request::parse("type: fsck\nuuid: 456-456-456"),
+            Some(Fsck {
+                device: PartitionRef::Uuid("456-456-456".to_owned())
             })
         );
     }
@@ -0,0 +1,24 @@
+use chrono::prelude::*;
+
+/// Latest state of an active clone or restore job, for clients asking rather than following
+/// status messages
+#[derive(Clone, PartialEq, Debug)]
+pub struct JobSummary {
+    pub id: String,
+    /// "clone", "restore" or "export"
+    pub kind: &'static str,
+    pub source: String,
+    pub destination: String,
+    pub start: DateTime<Utc>,
+    pub complete: f64,
+    pub rate: Option<String>,
+    pub estimated_finish: Option<DateTime<Utc>>,
+    /// ie "preparing", "cloning", "restoring", "syncing" or "resizing"
+    pub phase: &'static str,
+}
+
+/// Response to a `jobs-request`
+pub struct JobList(pub Vec<JobSummary>);
+
+/// Response to a `job-request` for the job with an id, `None` when there is no such job
+pub struct JobResult(pub String, pub Option<JobSummary>);
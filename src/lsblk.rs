@@ -1,6 +1,7 @@
+use crate::include::*;
 use json::JsonValue;
 use std::{
-    env,
+    env, fmt,
     io::{Error, ErrorKind, Result},
     process::{Command, Stdio},
     str,
@@ -10,6 +11,12 @@ fn lsblk_cmd() -> String {
     env::var("APART_LSBLK_CMD").unwrap_or_else(|_| "lsblk".to_owned())
 }
 
+/// Columns supported by all lsblk versions with json output
+const COLUMNS: &str = "name,type,size,fstype,label,mountpoint,uuid,partuuid,partlabel,parttype,\
+                       model,serial,tran,rota,rm,ro";
+/// Columns only supported by util-linux >= 2.33, older versions reject unknown columns
+const NEWER_COLUMNS: &str = "path,fsused,fsavail";
+
 /**
  * example json output
  * [{"name": "sda", "size": 750156374016, "fstype": null, "label": null, "mountpoint": null,
@@ -22,11 +29,22 @@ fn lsblk_cmd() -> String {
  */
 pub fn blockdevices() -> Result<Vec<json::JsonValue>> {
     let cmd = lsblk_cmd();
-    let lsblk = Command::new(&cmd)
-        .arg("-Jbo")
-        .arg("name,size,fstype,label,mountpoint,uuid")
-        .stdout(Stdio::piped())
-        .output()?;
+    let output = |columns: &str| {
+        Command::new(&cmd)
+            .arg("-Jbo")
+            .arg(columns)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+    };
+    let mut lsblk = output(&format!("{COLUMNS},{NEWER_COLUMNS}"))?;
+    if !lsblk.status.success() {
+        debug!(
+            "{cmd} failed with newer columns, retrying without: {}",
+            String::from_utf8_lossy(&lsblk.stderr).trim()
+        );
+        lsblk = output(COLUMNS)?;
+    }
 
     match json::parse(&String::from_utf8_lossy(&lsblk.stdout)) {
         Ok(mut json) => match json.remove("blockdevices") {
@@ -40,23 +58,120 @@ pub fn blockdevices() -> Result<Vec<json::JsonValue>> {
     }
 }
 
-/// expecting something like "/dev/sda1"
-fn partition_matching(source: &str) -> Option<JsonValue> {
-    match blockdevices() {
-        Err(_) => None,
-        Ok(devices) => {
-            for mut device in devices {
-                if let JsonValue::Array(parts) = device["children"].take() {
-                    for part in parts {
-                        if part["name"].is_string() && format!("/dev/{}", part["name"]) == source {
-                            return Some(part);
-                        }
-                    }
-                }
-            }
-            None
+/// Returns the device path, ie "/dev/sda1" or "/dev/mapper/vg-root"
+pub fn device_path(device: &JsonValue) -> Option<String> {
+    if let Some(path) = device["path"].as_str() {
+        return Some(path.to_owned());
+    }
+    // older lsblk versions don't support the path column, see `NEWER_COLUMNS`
+    let name = device["name"].as_str()?;
+    match device["type"].as_str() {
+        Some("lvm" | "crypt" | "dm" | "mpath") => Some(format!("/dev/mapper/{name}")),
+        _ => Some(format!("/dev/{name}")),
+    }
+}
+
+/// Partitions & stacked devices, ie lvm volumes, luks mappers & md arrays, at any depth of
+/// the input `blockdevices` output. Top level devices are included when they directly hold a
+/// file system, ie loop devices.
+fn partitions(devices: &[JsonValue]) -> Vec<&JsonValue> {
+    fn push_descendants<'a>(device: &'a JsonValue, parts: &mut Vec<&'a JsonValue>) {
+        for child in device["children"].members() {
+            parts.push(child);
+            push_descendants(child, parts);
         }
     }
+
+    let mut parts = Vec::new();
+    for device in devices {
+        if device["children"].is_empty() && device["fstype"].is_string() {
+            parts.push(device);
+        }
+        push_descendants(device, &mut parts);
+    }
+    parts
+}
+
+/// expecting something like "/dev/sda1", "/dev/mapper/vg-root", "/dev/md0"
+pub fn partition_matching(source: &str) -> Option<JsonValue> {
+    let devices = blockdevices().ok()?;
+    partitions(&devices)
+        .into_iter()
+        .find(|part| device_path(part).as_deref() == Some(source))
+        .cloned()
+}
+
+/// A reference to a partition, either by device path or by one of its identifiers
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PartitionRef {
+    /// something like "/dev/sda1"
+    Path(String),
+    Uuid(String),
+    Label(String),
+    PartUuid(String),
+}
+
+impl PartitionRef {
+    /// Returns the device path of the referenced partition, something like "/dev/sda1"
+    pub fn resolve(&self) -> Result<String> {
+        match self {
+            PartitionRef::Path(path) => Ok(path.clone()),
+            PartitionRef::Uuid(uuid) => resolve_in(&blockdevices()?, "uuid", uuid),
+            PartitionRef::Label(label) => resolve_in(&blockdevices()?, "label", label),
+            PartitionRef::PartUuid(uuid) => resolve_in(&blockdevices()?, "partuuid", uuid),
+        }
+    }
+}
+
+impl fmt::Display for PartitionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionRef::Path(path) => write!(f, "{path}"),
+            PartitionRef::Uuid(uuid) => write!(f, "uuid {uuid}"),
+            PartitionRef::Label(label) => write!(f, "label {label}"),
+            PartitionRef::PartUuid(uuid) => write!(f, "partuuid {uuid}"),
+        }
+    }
+}
+
+/// Returns the path of the single partition with `field` == `value`
+fn resolve_in(devices: &[JsonValue], field: &str, value: &str) -> Result<String> {
+    let mut paths: Vec<_> = partitions(devices)
+        .into_iter()
+        .filter(|part| part[field].as_str() == Some(value))
+        .filter_map(device_path)
+        .collect();
+    // stacked devices, ie md arrays, appear once under each member
+    paths.sort();
+    paths.dedup();
+
+    match paths.as_slice() {
+        [path] => Ok(path.clone()),
+        [] => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("No partition found with {field} {value}"),
+        )),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Multiple partitions found with {field} {value}: {}",
+                paths.join(", ")
+            ),
+        )),
+    }
+}
+
+/// lsblk outputs sizes as json numbers, or strings in older versions
+pub fn parse_size(size: &JsonValue) -> Option<u64> {
+    size.as_u64()
+        .or_else(|| size.as_str().and_then(|s| s.parse::<u64>().ok()))
+}
+
+/// lsblk outputs flags as json booleans, or "0"/"1" in older versions
+pub fn parse_flag(flag: &JsonValue) -> Option<bool> {
+    flag.as_bool()
+        .or_else(|| flag.as_u64().map(|f| f == 1))
+        .or_else(|| flag.as_str().map(|f| f.trim() == "1"))
 }
 
 /// expecting something like "/dev/sda1"
@@ -74,3 +189,105 @@ pub fn uuid(source: &str) -> Option<String> {
         _ => None,
     }
 }
+
+/// expecting something like "/dev/sda1"
+pub fn mountpoint(source: &str) -> Option<String> {
+    match partition_matching(source) {
+        Some(mut part) => part["mountpoint"].take_string(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<JsonValue> {
+        vec![
+            json::parse(
+                r#"
+      {"name": "sda", "size": 750156374016, "fstype": null, "label": null, "uuid": null,
+        "children": [
+          {"name": "sda1", "size": 104857600, "fstype": "ntfs", "label": "data", "uuid": "123",
+           "partuuid": "abc-1"},
+          {"name": "sda2", "size": 536766054400, "fstype": "ext4", "label": "data", "uuid": "234",
+           "partuuid": "abc-2"}
+        ]
+      }"#,
+            )
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn resolve_by_uuid() {
+        assert_eq!(
+            resolve_in(&devices(), "uuid", "234").unwrap(),
+            "/dev/sda2".to_owned()
+        );
+        assert_eq!(
+            resolve_in(&devices(), "partuuid", "abc-1").unwrap(),
+            "/dev/sda1".to_owned()
+        );
+    }
+
+    #[test]
+    fn resolve_stacked_device() {
+        let devices = vec![
+            json::parse(
+                r#"
+      {"name": "sdc", "type": "disk", "size": 62109253632, "fstype": null, "uuid": null,
+        "children": [
+          {"name": "sdc1", "type": "part", "size": 62108204544, "fstype": "crypto_LUKS",
+           "uuid": "aaa",
+           "children": [
+             {"name": "luks-aaa", "type": "crypt", "size": 62091427328, "fstype": "LVM2_member",
+              "uuid": "bbb",
+              "children": [
+                {"name": "vg-root", "type": "lvm", "size": 62087233024, "fstype": "ext4",
+                 "uuid": "ccc"}
+              ]
+             }
+           ]
+          }
+        ]
+      }"#,
+            )
+            .unwrap(),
+            json::parse(
+                r#"{"name": "loop0", "path": "/dev/loop0", "type": "loop", "size": 58327040,
+                    "fstype": "squashfs", "uuid": "ddd"}"#,
+            )
+            .unwrap(),
+        ];
+        assert_eq!(
+            resolve_in(&devices, "uuid", "ccc").unwrap(),
+            "/dev/mapper/vg-root".to_owned()
+        );
+        assert_eq!(
+            resolve_in(&devices, "uuid", "bbb").unwrap(),
+            "/dev/mapper/luks-aaa".to_owned()
+        );
+        assert_eq!(
+            resolve_in(&devices, "uuid", "ddd").unwrap(),
+            "/dev/loop0".to_owned()
+        );
+    }
+
+    #[test]
+    fn resolve_none_matching() {
+        let err = resolve_in(&devices(), "uuid", "999").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "No partition found with uuid 999");
+    }
+
+    #[test]
+    fn resolve_several_matching() {
+        let err = resolve_in(&devices(), "label", "data").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "Multiple partitions found with label data: /dev/sda1, /dev/sda2"
+        );
+    }
+}
@@ -2,12 +2,29 @@ mod asynchronous;
 mod child;
 mod clone;
 mod compression;
+mod devwatch;
+mod estimate;
+mod export;
+mod failure;
+mod fsck;
+mod fsuuid;
+mod history;
+mod hooks;
+mod image;
+mod import;
 mod inbound;
+mod jobs;
 mod lsblk;
+mod mount;
 mod outbound;
+mod outbox;
 mod partclone;
+mod progress;
+mod resize;
 mod restore;
+mod retention;
 mod server;
+mod space;
 
 pub(crate) mod include {
     pub(crate) use log::{debug, error, info, trace, warn};
@@ -41,7 +58,16 @@ fn print_help() {
         "Apart-core\
         \n  usage: apart-core IPC_ADDRESS\n\
         \n  ENV VAR 'APART_PARTCLONE_CMD': override the partclone command location\
-        \n  ENV VAR 'APART_LSBLK_CMD': override the lsblk command location"
+        \n  ENV VAR 'APART_LSBLK_CMD': override the lsblk command location\
+        \n  ENV VAR 'APART_PROC_PARTITIONS': override the /proc/partitions location\
+        \n  ENV VAR 'APART_PROC_MOUNTINFO': override the /proc/self/mountinfo location\
+        \n  ENV VAR 'APART_DISCONNECT_TIMEOUT': seconds the client may not receive before shutdown\
+        \n  ENV VAR 'APART_REATTACH_ADDRESS': address to bind for restarted clients to reattach\
+        \n  ENV VAR 'APART_HISTORY_FILE': job history file, default $XDG_STATE_HOME/apart/history.jsonl\
+        \n  ENV VAR 'APART_PRE_HOOK': shell command run before clone & restore jobs\
+        \n  ENV VAR 'APART_POST_HOOK': shell command run after clone & restore jobs\
+        \n  ENV VAR 'APART_HOOK_TIMEOUT': seconds a hook may run before it's killed, default 600\
+        \n  ENV VAR 'APART_{{PROGRAM}}_CMD': override other command locations, ie APART_UMOUNT_CMD"
     );
     std::process::exit(1);
 }
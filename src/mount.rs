@@ -0,0 +1,79 @@
+use crate::{child, include::*, lsblk};
+use std::{
+    env, fs,
+    io::{Error as IoError, Result as IoResult},
+    path::Path,
+    process::Command,
+};
+use uuid::Uuid;
+
+/// Prefix of temporary mountpoint directories created by `mount_temporary`
+static MOUNT_DIR_PREFIX: &str = "apart-mount-";
+
+#[derive(Debug)]
+pub enum MountResult {
+    Mounted { device: String, mountpoint: String },
+    Unmounted { device: String },
+    MountFailed { device: String, reason: String },
+    UnmountFailed { device: String, reason: String },
+}
+
+/// Mounts the device read-only at a new temporary directory, returning the mountpoint
+pub fn mount_read_only(device: &str) -> IoResult<String> {
+    mount_temporary(device, "ro")
+}
+
+/// Mounts the device with `options` at a new temporary directory, returning the mountpoint
+pub fn mount_temporary(device: &str, options: &str) -> IoResult<String> {
+    let dir = env::temp_dir().join(format!("{}{}", MOUNT_DIR_PREFIX, Uuid::new_v4()));
+    fs::create_dir(&dir)?;
+
+    match child::run(
+        Command::new(child::cmd("mount"))
+            .args(["-o", options, device])
+            .arg(&dir),
+    ) {
+        Ok(_) => Ok(dir.to_string_lossy().into_owned()),
+        Err(err) => {
+            if let Err(rm_err) = fs::remove_dir(&dir) {
+                warn!("Could not rm mount dir {}: {}", dir.display(), rm_err);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Unmounts the device, removing the mountpoint if created by `mount_temporary`
+pub fn unmount(device: &str) -> IoResult<()> {
+    let mountpoint = lsblk::mountpoint(device);
+    child::run(Command::new(child::cmd("umount")).arg(device))?;
+
+    if let Some(mountpoint) = mountpoint {
+        let mountpoint = Path::new(&mountpoint);
+        if mountpoint.parent() == Some(&env::temp_dir())
+            && mountpoint
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(MOUNT_DIR_PREFIX))
+            && let Err(err) = fs::remove_dir(mountpoint)
+        {
+            warn!("Could not rm mount dir {}: {}", mountpoint.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// Unmounts & removes a mountpoint created by `mount_temporary`
+pub fn unmount_temporary(mountpoint: &str) -> IoResult<()> {
+    child::run(Command::new(child::cmd("umount")).arg(mountpoint))?;
+    fs::remove_dir(mountpoint)
+}
+
+/// Unmounts the device if currently mounted
+pub fn unmount_if_mounted(device: &str) -> IoResult<()> {
+    if lsblk::mountpoint(device).is_some() {
+        info!("Unmounting {}", device);
+        unmount(device)
+            .map_err(|err| IoError::other(format!("Failed to unmount {}: {}", device, err)))?;
+    }
+    Ok(())
+}
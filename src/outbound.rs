@@ -1,4 +1,20 @@
-use crate::{clone::*, compression::Compression, restore::*, server::DeleteResult};
+use crate::{
+    clone::*,
+    compression::Compression,
+    devwatch::DevicesChanged,
+    estimate::CloneEstimateResult,
+    export::ExportFormat,
+    fsck::FsckStatus,
+    history::{HistoryEntry, HistoryResult},
+    image::ImageInfo,
+    import::ImportResult,
+    jobs::{JobList, JobResult, JobSummary},
+    lsblk,
+    mount::MountResult,
+    partclone::Metrics,
+    restore::*,
+    server::{DeleteResult, RunningStatus, StartFailed},
+};
 use chrono::prelude::*;
 use json::JsonValue;
 use std::io::ErrorKind;
@@ -6,6 +22,11 @@ use yaml_rust::{emitter::YamlEmitter, yaml, yaml::Yaml};
 
 pub trait ToYaml {
     fn to_yaml(&self) -> String;
+
+    /// Whether the message reports a job ending, kept for a client yet to reattach
+    fn ends_job(&self) -> bool {
+        false
+    }
 }
 
 fn common_yaml(start: DateTime<Utc>, source: &str, destination: &str, id: &str) -> String {
@@ -21,6 +42,52 @@ fn common_yaml(start: DateTime<Utc>, source: &str, destination: &str, id: &str)
     )
 }
 
+/// Returns a yaml `output` list of failure output lines, empty when there are none
+fn output_yaml(output: &[String]) -> String {
+    output
+        .iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            let key = if idx == 0 { "\noutput:" } else { "" };
+            format!("{}\n- {}", key, scalar(line))
+        })
+        .collect()
+}
+
+/// Returns yaml lines of the known numeric progress metrics
+fn metrics_yaml(metrics: &Metrics, compressed_bytes: Option<u64>) -> String {
+    let Metrics {
+        bytes_per_second,
+        bytes_done,
+        bytes_total,
+        elapsed_seconds,
+    } = *metrics;
+    [
+        ("bytes_per_second", bytes_per_second),
+        ("bytes_done", bytes_done),
+        ("bytes_total", bytes_total),
+        ("elapsed_seconds", elapsed_seconds),
+        ("compressed_bytes", compressed_bytes),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|value| format!("\n{}: {}", key, value)))
+    .collect()
+}
+
+/// Returns a yaml `smoothed_estimated_finish` line when known
+fn smoothed_finish_yaml(finish: Option<DateTime<Utc>>) -> String {
+    finish.map_or_else(String::new, |finish| {
+        format!("\nsmoothed_estimated_finish: {:?}", finish)
+    })
+}
+
+/// Returns a yaml `compression_ratio` line when known
+fn compression_ratio_yaml(ratio: Option<f64>) -> String {
+    ratio.map_or_else(String::new, |ratio| {
+        format!("\ncompression_ratio: {}", complete_yaml_str(ratio))
+    })
+}
+
 fn complete_yaml_str(complete: f64) -> String {
     let complete = complete.to_string();
     if complete.len() == 1 {
@@ -50,13 +117,13 @@ impl ToYaml for CloneStatusCommon {
     }
 }
 
-impl ToYaml for RestoreStatusCommon<'_> {
+impl ToYaml for RestoreStatusCommon {
     fn to_yaml(&self) -> String {
         let &RestoreStatusCommon {
             start,
-            source,
-            destination,
-            id,
+            ref source,
+            ref destination,
+            ref id,
             ..
         } = self;
         common_yaml(start, source, destination, id)
@@ -64,6 +131,13 @@ impl ToYaml for RestoreStatusCommon<'_> {
 }
 
 impl ToYaml for CloneStatus {
+    fn ends_job(&self) -> bool {
+        matches!(
+            self,
+            CloneStatus::Finished { .. } | CloneStatus::Failed { .. }
+        )
+    }
+
     fn to_yaml(&self) -> String {
         match *self {
             CloneStatus::Running {
@@ -71,6 +145,10 @@ impl ToYaml for CloneStatus {
                 ref rate,
                 ref common,
                 ref estimated_finish,
+                smoothed_estimated_finish,
+                ref metrics,
+                compressed_bytes,
+                compression_ratio,
             } => {
                 let estimated_finish =
                     estimated_finish.map_or_else(|| "~".to_owned(), |d| format!("{:?}", d));
@@ -81,11 +159,14 @@ impl ToYaml for CloneStatus {
                      complete: {complete}\n\
                      syncing: false\n\
                      rate: {rate}\n\
-                     estimated_finish: {finish}",
+                     estimated_finish: {finish}{smoothed_finish}{metrics}{ratio}",
                     common_yaml = common.to_yaml(),
                     complete = complete_yaml_str(complete),
                     rate = rate,
-                    finish = estimated_finish
+                    finish = estimated_finish,
+                    smoothed_finish = smoothed_finish_yaml(smoothed_estimated_finish),
+                    metrics = metrics_yaml(metrics, compressed_bytes),
+                    ratio = compression_ratio_yaml(compression_ratio)
                 )
             }
             CloneStatus::Syncing { ref common } => format!(
@@ -99,35 +180,46 @@ impl ToYaml for CloneStatus {
                 ref finish,
                 ref common,
                 image_size,
+                compression_ratio,
             } => format!(
                 "type: clone\n\
                  {common_yaml}\n\
                  complete: 1.0\n\
                  syncing: false\n\
                  finish: {finish:?}\n\
-                 image_size: {image_size}",
+                 image_size: {image_size}{ratio}",
                 common_yaml = common.to_yaml(),
                 finish = finish,
-                image_size = image_size
+                image_size = image_size,
+                ratio = compression_ratio_yaml(compression_ratio)
             ),
             CloneStatus::Failed {
                 ref finish,
                 ref common,
                 ref reason,
+                ref output,
             } => format!(
                 "type: clone-failed\n\
                  {common_yaml}\n\
                  finish: {finish:?}\n\
-                 error: {error}",
+                 error: {error}{output}",
                 common_yaml = common.to_yaml(),
                 finish = finish,
-                error = reason
+                error = scalar(reason),
+                output = output_yaml(output)
             ),
         }
     }
 }
 
-impl ToYaml for RestoreStatus<'_> {
+impl ToYaml for RestoreStatus {
+    fn ends_job(&self) -> bool {
+        matches!(
+            self,
+            RestoreStatus::Finished { .. } | RestoreStatus::Failed { .. }
+        )
+    }
+
     fn to_yaml(&self) -> String {
         match *self {
             RestoreStatus::Running {
@@ -136,50 +228,153 @@ impl ToYaml for RestoreStatus<'_> {
                 syncing,
                 ref rate,
                 estimated_finish,
+                smoothed_estimated_finish,
+                ref metrics,
+                compressed_bytes,
             } => {
                 let estimated_finish =
                     estimated_finish.map_or_else(|| "~".to_owned(), |d| format!("{:?}", d));
                 let rate = rate.clone().unwrap_or_else(|| "~".to_owned());
                 format!(
-                    "type: restore\n\
+                    "type: {kind}\n\
                      {common_yaml}\n\
                      complete: {complete}\n\
                      syncing: {syncing}\n\
+                     phase: {phase}\n\
                      rate: {rate}\n\
-                     estimated_finish: {finish}",
+                     estimated_finish: {finish}{smoothed_finish}{metrics}",
+                    kind = common.kind,
                     common_yaml = common.to_yaml(),
                     complete = complete_yaml_str(complete),
                     rate = rate,
                     finish = estimated_finish,
-                    syncing = syncing
+                    smoothed_finish = smoothed_finish_yaml(smoothed_estimated_finish),
+                    syncing = syncing,
+                    phase = if syncing { "syncing" } else { "restoring" },
+                    metrics = metrics_yaml(metrics, Some(compressed_bytes))
                 )
             }
-            RestoreStatus::Finished { ref common, finish } => format!(
-                "type: restore\n\
+            RestoreStatus::Finalizing { ref common, phase } => format!(
+                "type: {kind}\n\
                  {common_yaml}\n\
-                 complete: 1.0\n\
+                 complete: 0.9999\n\
                  syncing: false\n\
-                 finish: {finish:?}",
-                finish = finish,
+                 phase: {phase}",
+                kind = common.kind,
                 common_yaml = common.to_yaml()
             ),
+            RestoreStatus::Finished {
+                ref common,
+                finish,
+                ref new_uuid,
+            } => {
+                let mut yaml = format!(
+                    "type: {kind}\n\
+                     {common_yaml}\n\
+                     complete: 1.0\n\
+                     syncing: false\n\
+                     phase: finished\n\
+                     finish: {finish:?}",
+                    kind = common.kind,
+                    finish = finish,
+                    common_yaml = common.to_yaml()
+                );
+                if let Some(uuid) = new_uuid {
+                    yaml += &format!("\nuuid: {}", uuid);
+                }
+                yaml
+            }
             RestoreStatus::Failed {
                 ref common,
                 ref reason,
                 finish,
+                ref output,
             } => format!(
-                "type: restore-failed\n\
+                "type: {kind}-failed\n\
                  {common_yaml}\n\
                  finish: {finish:?}\n\
-                 error: {error}",
+                 error: {error}{output}",
+                kind = common.kind,
                 common_yaml = common.to_yaml(),
                 finish = finish,
-                error = reason
+                error = scalar(reason),
+                output = output_yaml(output)
             ),
         }
     }
 }
 
+/// Inserts optional lsblk `fields` into the hash using the paired yaml keys
+fn insert_optional(hash: &mut yaml::Hash, device: &JsonValue, fields: &[(&str, &str)]) {
+    for &(field, key) in fields {
+        let value = &device[field];
+        let yaml = match field {
+            "rota" | "rm" | "ro" => lsblk::parse_flag(value).map(Yaml::Boolean),
+            "fsused" | "fsavail" => lsblk::parse_size(value).map(|size| Yaml::Integer(size as i64)),
+            _ => value
+                .as_str()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| Yaml::String(v.to_owned())),
+        };
+        if let Some(yaml) = yaml {
+            hash.insert(Yaml::from_str(key), yaml);
+        }
+    }
+}
+
+/// Partition yaml including any stacked devices, ie lvm volumes, as nested `children`
+fn part_yaml(p: &JsonValue) -> Option<Yaml> {
+    if let (Some(name), Some(size), fstype, label, mountpoint, uuid) = (
+        p["name"].as_str(),
+        lsblk::parse_size(&p["size"]),
+        p["fstype"].as_str(),
+        p["label"].as_str(),
+        p["mountpoint"].as_str(),
+        p["uuid"].as_str(),
+    ) {
+        let mut part = yaml::Hash::new();
+        part.insert(Yaml::from_str("name"), Yaml::from_str(name));
+        if let Some(path) = lsblk::device_path(p) {
+            part.insert(Yaml::from_str("path"), Yaml::String(path));
+        }
+        part.insert(Yaml::from_str("size"), Yaml::from_str(&format!("{}", size)));
+        part.insert(
+            Yaml::from_str("mounted"),
+            Yaml::Boolean(mountpoint.is_some()),
+        );
+        if let Some(t) = fstype {
+            part.insert(Yaml::from_str("fstype"), Yaml::from_str(t));
+        }
+        if let Some(l) = label {
+            part.insert(Yaml::from_str("label"), Yaml::from_str(l));
+        }
+        if let Some(id) = uuid {
+            part.insert(Yaml::from_str("uuid"), Yaml::from_str(id));
+        }
+        insert_optional(
+            &mut part,
+            p,
+            &[
+                ("type", "type"),
+                ("partuuid", "partuuid"),
+                ("partlabel", "partlabel"),
+                ("parttype", "parttype"),
+                ("ro", "read_only"),
+                ("fsused", "fsused"),
+                ("fsavail", "fsavail"),
+            ],
+        );
+
+        let children: yaml::Array = p["children"].members().filter_map(part_yaml).collect();
+        if !children.is_empty() {
+            part.insert(Yaml::from_str("children"), Yaml::Array(children));
+        }
+        return Some(Yaml::Hash(part));
+    }
+    None
+}
+
 pub fn status_yaml(status: &str, lsblk: Vec<JsonValue>) -> String {
     let mut yaml = yaml::Hash::new();
     yaml.insert(Yaml::from_str("type"), Yaml::from_str("status"));
@@ -188,60 +383,38 @@ pub fn status_yaml(status: &str, lsblk: Vec<JsonValue>) -> String {
     if !lsblk.is_empty() {
         let mut sources = yaml::Array::new();
 
-        fn parse_size(size: &JsonValue) -> Option<u64> {
-            size.as_u64()
-                .or_else(|| size.as_str().and_then(|s| s.parse::<u64>().ok()))
-        }
-
         for device in lsblk {
-            match (
-                device["name"].as_str(),
-                parse_size(&device["size"]),
-                &device["children"],
-            ) {
-                (Some(name), Some(size), JsonValue::Array(children)) if !children.is_empty() => {
-                    let mut source = yaml::Hash::new();
-                    source.insert(Yaml::from_str("name"), Yaml::from_str(name));
-                    source.insert(Yaml::from_str("size"), Yaml::from_str(&format!("{}", size)));
-
-                    let mut parts = yaml::Array::new();
-                    for p in children {
-                        if let (Some(name), Some(size), fstype, label, mountpoint, uuid) = (
-                            p["name"].as_str(),
-                            parse_size(&p["size"]),
-                            p["fstype"].as_str(),
-                            p["label"].as_str(),
-                            p["mountpoint"].as_str(),
-                            p["uuid"].as_str(),
-                        ) {
-                            let mut part = yaml::Hash::new();
-                            part.insert(Yaml::from_str("name"), Yaml::from_str(name));
-                            part.insert(
-                                Yaml::from_str("size"),
-                                Yaml::from_str(&format!("{}", size)),
-                            );
-                            part.insert(
-                                Yaml::from_str("mounted"),
-                                Yaml::Boolean(mountpoint.is_some()),
-                            );
-                            if let Some(t) = fstype {
-                                part.insert(Yaml::from_str("fstype"), Yaml::from_str(t));
-                            }
-                            if let Some(l) = label {
-                                part.insert(Yaml::from_str("label"), Yaml::from_str(l));
-                            }
-                            if let Some(id) = uuid {
-                                part.insert(Yaml::from_str("uuid"), Yaml::from_str(id));
-                            }
-
-                            parts.push(Yaml::Hash(part));
-                        }
-                    }
-                    source.insert(Yaml::from_str("parts"), Yaml::Array(parts));
-
-                    sources.push(Yaml::Hash(source));
+            let parts: yaml::Array = match &device["children"] {
+                JsonValue::Array(children) if !children.is_empty() => {
+                    children.iter().filter_map(part_yaml).collect()
                 }
-                _ => (),
+                // devices directly holding a file system, ie loop devices, are their own part
+                _ if device["fstype"].is_string() => part_yaml(&device).into_iter().collect(),
+                _ => continue,
+            };
+
+            if let (Some(name), Some(size)) =
+                (device["name"].as_str(), lsblk::parse_size(&device["size"]))
+            {
+                let mut source = yaml::Hash::new();
+                source.insert(Yaml::from_str("name"), Yaml::from_str(name));
+                source.insert(Yaml::from_str("size"), Yaml::from_str(&format!("{}", size)));
+                insert_optional(
+                    &mut source,
+                    &device,
+                    &[
+                        ("type", "type"),
+                        ("model", "model"),
+                        ("serial", "serial"),
+                        ("tran", "transport"),
+                        ("rota", "rotational"),
+                        ("rm", "removable"),
+                        ("ro", "read_only"),
+                    ],
+                );
+                source.insert(Yaml::from_str("parts"), Yaml::Array(parts));
+
+                sources.push(Yaml::Hash(source));
             }
         }
 
@@ -257,6 +430,19 @@ pub fn status_yaml(status: &str, lsblk: Vec<JsonValue>) -> String {
         Yaml::Array(compression_options),
     );
 
+    let export_formats = ExportFormat::all_installed()
+        .map(|format| Yaml::from_str(format.name()))
+        .collect();
+    yaml.insert(
+        Yaml::from_str("export_formats"),
+        Yaml::Array(export_formats),
+    );
+
+    emit(yaml)
+}
+
+/// Returns the yaml document string of a hash, escaping values as necessary
+fn emit(yaml: yaml::Hash) -> String {
     let mut yaml_str = String::new();
     YamlEmitter::new(&mut yaml_str)
         .dump(&Yaml::Hash(yaml))
@@ -264,6 +450,15 @@ pub fn status_yaml(status: &str, lsblk: Vec<JsonValue>) -> String {
     yaml_str
 }
 
+/// Returns a yaml scalar string, quoting & escaping as necessary
+fn scalar(value: &str) -> String {
+    let mut yaml_str = String::new();
+    YamlEmitter::new(&mut yaml_str)
+        .dump(&Yaml::String(value.to_owned()))
+        .unwrap();
+    yaml_str.trim_start_matches("---").trim_start().to_owned()
+}
+
 impl ToYaml for DeleteResult {
     fn to_yaml(&self) -> String {
         match *self {
@@ -288,6 +483,404 @@ impl ToYaml for DeleteResult {
     }
 }
 
+impl ToYaml for CloneEstimateResult {
+    fn to_yaml(&self) -> String {
+        let CloneEstimateResult(ref source, ref result) = *self;
+        let mut yaml = yaml::Hash::new();
+        match result {
+            Ok(estimate) => {
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("clone-estimate"));
+                yaml.insert(
+                    Yaml::from_str("source"),
+                    Yaml::String(estimate.source.clone()),
+                );
+                yaml.insert(Yaml::from_str("size"), Yaml::Integer(estimate.size as i64));
+                yaml.insert(Yaml::from_str("used"), Yaml::Integer(estimate.used as i64));
+                yaml.insert(
+                    Yaml::from_str("used_exact"),
+                    Yaml::Boolean(estimate.used_exact),
+                );
+                let estimates = estimate
+                    .estimates
+                    .iter()
+                    .map(|e| {
+                        let mut hash = yaml::Hash::new();
+                        hash.insert(Yaml::from_str("compression"), Yaml::from_str(e.compression));
+                        hash.insert(
+                            Yaml::from_str("image_size"),
+                            Yaml::Integer(e.image_size as i64),
+                        );
+                        hash.insert(Yaml::from_str("seconds"), Yaml::Integer(e.seconds as i64));
+                        Yaml::Hash(hash)
+                    })
+                    .collect();
+                yaml.insert(Yaml::from_str("estimates"), Yaml::Array(estimates));
+            }
+            Err(err) => {
+                yaml.insert(
+                    Yaml::from_str("type"),
+                    Yaml::from_str("clone-estimate-failed"),
+                );
+                yaml.insert(Yaml::from_str("source"), Yaml::String(source.clone()));
+                yaml.insert(Yaml::from_str("error"), Yaml::String(err.to_string()));
+            }
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for ImageInfo {
+    fn to_yaml(&self) -> String {
+        let ImageInfo(ref file, ref header) = *self;
+        let mut yaml = yaml::Hash::new();
+        match header {
+            Ok(header) => {
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("image-info"));
+                yaml.insert(Yaml::from_str("file"), Yaml::String(file.clone()));
+                match header {
+                    Some(header) => {
+                        yaml.insert(Yaml::from_str("format"), Yaml::from_str("partclone"));
+                        yaml.insert(Yaml::from_str("fs"), Yaml::String(header.fs.clone()));
+                        yaml.insert(
+                            Yaml::from_str("partclone_variant"),
+                            Yaml::String(header.partclone_variant()),
+                        );
+                        yaml.insert(
+                            Yaml::from_str("version"),
+                            Yaml::String(header.version.clone()),
+                        );
+                        if let Some(partclone_version) = &header.partclone_version {
+                            yaml.insert(
+                                Yaml::from_str("partclone_version"),
+                                Yaml::String(partclone_version.clone()),
+                            );
+                        }
+                        for (key, value) in [
+                            ("device_size", header.device_size),
+                            ("block_size", u64::from(header.block_size)),
+                            ("total_blocks", header.total_blocks),
+                            ("used_blocks", header.used_blocks),
+                        ] {
+                            yaml.insert(Yaml::from_str(key), Yaml::Integer(value as i64));
+                        }
+                    }
+                    None => {
+                        yaml.insert(Yaml::from_str("format"), Yaml::from_str("dd"));
+                    }
+                }
+            }
+            Err(err) => {
+                let reason = match err.kind() {
+                    ErrorKind::NotFound => "No such file".to_owned(),
+                    _ => err.to_string(),
+                };
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("image-info-failed"));
+                yaml.insert(Yaml::from_str("file"), Yaml::String(file.clone()));
+                yaml.insert(Yaml::from_str("error"), Yaml::String(reason));
+            }
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for ImportResult {
+    fn to_yaml(&self) -> String {
+        let ImportResult(ref source, ref result) = *self;
+        let mut yaml = yaml::Hash::new();
+        match result {
+            Ok(images) => {
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("imported-image"));
+                yaml.insert(Yaml::from_str("source"), Yaml::String(source.clone()));
+                yaml.insert(
+                    Yaml::from_str("images"),
+                    Yaml::Array(images.iter().map(|i| Yaml::String(i.clone())).collect()),
+                );
+            }
+            Err(err) => {
+                yaml.insert(
+                    Yaml::from_str("type"),
+                    Yaml::from_str("import-image-failed"),
+                );
+                yaml.insert(Yaml::from_str("source"), Yaml::String(source.clone()));
+                yaml.insert(Yaml::from_str("error"), Yaml::String(err.to_string()));
+            }
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for StartFailed {
+    fn ends_job(&self) -> bool {
+        true
+    }
+
+    fn to_yaml(&self) -> String {
+        let StartFailed(kind, ref id, ref reason) = *self;
+        let mut yaml = yaml::Hash::new();
+        yaml.insert(
+            Yaml::from_str("type"),
+            Yaml::String(format!("{}-failed", kind)),
+        );
+        if let Some(id) = id {
+            yaml.insert(Yaml::from_str("id"), Yaml::String(id.clone()));
+        }
+        yaml.insert(Yaml::from_str("error"), Yaml::String(reason.clone()));
+        emit(yaml)
+    }
+}
+
+fn job_summary_yaml(summary: &JobSummary) -> yaml::Hash {
+    let mut yaml = yaml::Hash::new();
+    for (key, value) in [
+        ("id", summary.id.as_str()),
+        ("kind", summary.kind),
+        ("source", summary.source.as_str()),
+        ("destination", summary.destination.as_str()),
+    ] {
+        yaml.insert(Yaml::from_str(key), Yaml::String(value.to_owned()));
+    }
+    yaml.insert(
+        Yaml::from_str("start"),
+        Yaml::String(format!("{:?}", summary.start)),
+    );
+    yaml.insert(
+        Yaml::from_str("complete"),
+        Yaml::Real(complete_yaml_str(summary.complete)),
+    );
+    yaml.insert(Yaml::from_str("phase"), Yaml::from_str(summary.phase));
+    if let Some(rate) = &summary.rate {
+        yaml.insert(Yaml::from_str("rate"), Yaml::String(rate.clone()));
+    }
+    if let Some(estimated_finish) = summary.estimated_finish {
+        yaml.insert(
+            Yaml::from_str("estimated_finish"),
+            Yaml::String(format!("{:?}", estimated_finish)),
+        );
+    }
+    yaml
+}
+
+impl ToYaml for JobList {
+    fn to_yaml(&self) -> String {
+        let JobList(ref summaries) = *self;
+        let mut yaml = yaml::Hash::new();
+        yaml.insert(Yaml::from_str("type"), Yaml::from_str("jobs"));
+        yaml.insert(
+            Yaml::from_str("jobs"),
+            Yaml::Array(
+                summaries
+                    .iter()
+                    .map(|summary| Yaml::Hash(job_summary_yaml(summary)))
+                    .collect(),
+            ),
+        );
+        emit(yaml)
+    }
+}
+
+impl ToYaml for JobResult {
+    fn to_yaml(&self) -> String {
+        let JobResult(ref id, ref summary) = *self;
+        match summary {
+            Some(summary) => {
+                let mut yaml = yaml::Hash::new();
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("job"));
+                yaml.extend(job_summary_yaml(summary));
+                emit(yaml)
+            }
+            None => {
+                let mut yaml = yaml::Hash::new();
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("job-failed"));
+                yaml.insert(Yaml::from_str("id"), Yaml::String(id.clone()));
+                yaml.insert(
+                    Yaml::from_str("error"),
+                    Yaml::String(format!("No job with id {}", id)),
+                );
+                emit(yaml)
+            }
+        }
+    }
+}
+
+fn history_entry_yaml(entry: &HistoryEntry) -> Yaml {
+    let mut yaml = yaml::Hash::new();
+    let mut insert_str = |key: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            yaml.insert(Yaml::from_str(key), Yaml::String(value.to_owned()));
+        }
+    };
+    insert_str("kind", Some(&entry.kind));
+    insert_str("id", entry.id.as_deref());
+    insert_str("outcome", Some(&entry.outcome));
+    insert_str("reason", entry.reason.as_deref());
+    insert_str("start", Some(&format!("{:?}", entry.start)));
+    insert_str("finish", Some(&format!("{:?}", entry.finish)));
+    insert_str("source_uuid", entry.source_uuid.as_deref());
+    yaml.insert(
+        Yaml::from_str("duration_seconds"),
+        Yaml::Real((entry.duration().num_milliseconds() as f64 / 1000.0).to_string()),
+    );
+    if let Some(image_size) = entry.image_size {
+        yaml.insert(
+            Yaml::from_str("image_size"),
+            Yaml::Integer(image_size as i64),
+        );
+    }
+    yaml.insert(
+        Yaml::from_str("parameters"),
+        Yaml::Hash(
+            (entry.parameters.iter())
+                .map(|(key, value)| (Yaml::String(key.clone()), Yaml::String(value.clone())))
+                .collect(),
+        ),
+    );
+    Yaml::Hash(yaml)
+}
+
+impl ToYaml for HistoryResult {
+    fn to_yaml(&self) -> String {
+        let mut yaml = yaml::Hash::new();
+        match self.0 {
+            Ok(ref entries) => {
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("history"));
+                yaml.insert(
+                    Yaml::from_str("entries"),
+                    Yaml::Array(entries.iter().map(history_entry_yaml).collect()),
+                );
+            }
+            Err(ref err) => {
+                yaml.insert(Yaml::from_str("type"), Yaml::from_str("history-failed"));
+                yaml.insert(Yaml::from_str("error"), Yaml::String(err.to_string()));
+            }
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for FsckStatus {
+    fn ends_job(&self) -> bool {
+        matches!(self, FsckStatus::Finished { .. })
+    }
+
+    fn to_yaml(&self) -> String {
+        let common = match self {
+            FsckStatus::Running { common, .. } | FsckStatus::Finished { common, .. } => common,
+        };
+        let mut yaml = yaml::Hash::new();
+        yaml.insert(Yaml::from_str("type"), Yaml::from_str("fsck"));
+        yaml.insert(Yaml::from_str("id"), Yaml::String(common.id.clone()));
+        yaml.insert(
+            Yaml::from_str("device"),
+            Yaml::String(common.device.clone()),
+        );
+        yaml.insert(
+            Yaml::from_str("fstype"),
+            Yaml::String(common.fstype.clone()),
+        );
+        yaml.insert(
+            Yaml::from_str("start"),
+            Yaml::String(format!("{:?}", common.start)),
+        );
+        if common.before_clone {
+            yaml.insert(Yaml::from_str("before"), Yaml::from_str("clone"));
+        }
+        match self {
+            FsckStatus::Running { output, .. } => {
+                yaml.insert(Yaml::from_str("finished"), Yaml::Boolean(false));
+                if let Some(output) = output {
+                    yaml.insert(Yaml::from_str("output"), Yaml::String(output.clone()));
+                }
+            }
+            FsckStatus::Finished {
+                finish,
+                clean,
+                exit_code,
+                ..
+            } => {
+                yaml.insert(Yaml::from_str("finished"), Yaml::Boolean(true));
+                yaml.insert(
+                    Yaml::from_str("finish"),
+                    Yaml::String(format!("{:?}", finish)),
+                );
+                yaml.insert(Yaml::from_str("clean"), Yaml::Boolean(*clean));
+                if let Some(code) = exit_code {
+                    yaml.insert(Yaml::from_str("exit_code"), Yaml::Integer((*code).into()));
+                }
+            }
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for MountResult {
+    fn to_yaml(&self) -> String {
+        let (kind, device, mountpoint, reason) = match self {
+            MountResult::Mounted { device, mountpoint } => {
+                ("mounted", device, Some(mountpoint), None)
+            }
+            MountResult::Unmounted { device } => ("unmounted", device, None, None),
+            MountResult::MountFailed { device, reason } => {
+                ("mount-failed", device, None, Some(reason))
+            }
+            MountResult::UnmountFailed { device, reason } => {
+                ("unmount-failed", device, None, Some(reason))
+            }
+        };
+        let mut yaml = yaml::Hash::new();
+        yaml.insert(Yaml::from_str("type"), Yaml::from_str(kind));
+        yaml.insert(Yaml::from_str("device"), Yaml::String(device.clone()));
+        if let Some(mountpoint) = mountpoint {
+            yaml.insert(
+                Yaml::from_str("mountpoint"),
+                Yaml::String(mountpoint.clone()),
+            );
+        }
+        if let Some(reason) = reason {
+            yaml.insert(Yaml::from_str("error"), Yaml::String(reason.clone()));
+        }
+        emit(yaml)
+    }
+}
+
+impl ToYaml for RunningStatus {
+    fn to_yaml(&self) -> String {
+        let RunningStatus(ref devices) = *self;
+        status_yaml("running", devices.clone())
+    }
+}
+
+impl ToYaml for DevicesChanged {
+    fn to_yaml(&self) -> String {
+        fn names(names: &[String]) -> Yaml {
+            Yaml::Array(names.iter().cloned().map(Yaml::String).collect())
+        }
+        fn mounts(mounts: &[(String, String)]) -> Yaml {
+            Yaml::Array(
+                mounts
+                    .iter()
+                    .map(|(device, mountpoint)| {
+                        let mut mount = yaml::Hash::new();
+                        mount.insert(Yaml::from_str("device"), Yaml::String(device.clone()));
+                        mount.insert(
+                            Yaml::from_str("mountpoint"),
+                            Yaml::String(mountpoint.clone()),
+                        );
+                        Yaml::Hash(mount)
+                    })
+                    .collect(),
+            )
+        }
+
+        let mut yaml = yaml::Hash::new();
+        yaml.insert(Yaml::from_str("type"), Yaml::from_str("devices-changed"));
+        yaml.insert(Yaml::from_str("added"), names(&self.added));
+        yaml.insert(Yaml::from_str("removed"), names(&self.removed));
+        yaml.insert(Yaml::from_str("mounted"), mounts(&self.mounted));
+        yaml.insert(Yaml::from_str("unmounted"), mounts(&self.unmounted));
+        emit(yaml)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -333,6 +926,102 @@ mod tests {
         );
     }
 
+    #[test]
+    fn status_device_details_yaml() {
+        let lsblk_json = vec!(json::parse(r#"
+      {"name": "sdb", "size": 62109253632, "fstype": null, "label": null, "mountpoint": null,
+        "model": "Cruzer Blade    ", "serial": "4C530001", "tran": "usb", "rota": "1", "rm": "1",
+        "ro": "0",
+        "children": [
+          {"name": "sdb1", "size": 524288000, "fstype": "ext2", "label": "boot", "mountpoint": "/boot",
+           "partuuid": "7c3ab2d1-01", "partlabel": null,
+           "parttype": "0fc63daf-8483-4772-8e79-3d69d8477de4", "ro": false,
+           "fsused": "123456", "fsavail": 400000000}
+        ]
+      }"#).unwrap());
+        let yaml = YamlLoader::load_from_str(&status_yaml("started", lsblk_json))
+            .unwrap()
+            .remove(0);
+
+        let sdb = &yaml["sources"][0];
+        assert_eq!(sdb["model"].as_str(), Some("Cruzer Blade"));
+        assert_eq!(sdb["serial"].as_str(), Some("4C530001"));
+        assert_eq!(sdb["transport"].as_str(), Some("usb"));
+        assert_eq!(sdb["rotational"].as_bool(), Some(true));
+        assert_eq!(sdb["removable"].as_bool(), Some(true));
+        assert_eq!(sdb["read_only"].as_bool(), Some(false));
+
+        let sdb1 = &sdb["parts"][0];
+        assert_eq!(sdb1["partuuid"].as_str(), Some("7c3ab2d1-01"));
+        assert_eq!(sdb1["partlabel"].as_str(), None);
+        assert_eq!(
+            sdb1["parttype"].as_str(),
+            Some("0fc63daf-8483-4772-8e79-3d69d8477de4")
+        );
+        assert_eq!(sdb1["read_only"].as_bool(), Some(false));
+        assert_eq!(sdb1["fsused"].as_i64(), Some(123_456));
+        assert_eq!(sdb1["fsavail"].as_i64(), Some(400_000_000));
+    }
+
+    #[test]
+    fn status_stacked_devices_yaml() {
+        let lsblk_json = vec![
+            json::parse(
+                r#"
+      {"name": "sdc", "type": "disk", "size": 62109253632, "fstype": null, "mountpoint": null,
+        "children": [
+          {"name": "sdc1", "type": "part", "size": 62108204544, "fstype": "crypto_LUKS",
+           "mountpoint": null,
+           "children": [
+             {"name": "luks-aaa", "type": "crypt", "size": 62091427328,
+              "fstype": "LVM2_member", "mountpoint": null,
+              "children": [
+                {"name": "vg-root", "type": "lvm", "size": 62087233024, "fstype": "ext4",
+                 "mountpoint": null}
+              ]
+             }
+           ]
+          }
+        ]
+      }"#,
+            )
+            .unwrap(),
+            json::parse(
+                r#"{"name": "loop0", "path": "/dev/loop0", "type": "loop", "size": 58327040,
+                    "fstype": "squashfs", "mountpoint": "/snap/core/1"}"#,
+            )
+            .unwrap(),
+            json::parse(
+                r#"{"name": "loop1", "type": "loop", "size": 0, "fstype": null,
+                    "mountpoint": null}"#,
+            )
+            .unwrap(),
+        ];
+        let yaml = YamlLoader::load_from_str(&status_yaml("started", lsblk_json))
+            .unwrap()
+            .remove(0);
+        assert_eq!(yaml["sources"].as_vec().map(Vec::len), Some(2));
+
+        let sdc = &yaml["sources"][0];
+        assert_eq!(sdc["type"].as_str(), Some("disk"));
+        let sdc1 = &sdc["parts"][0];
+        assert_eq!(sdc1["path"].as_str(), Some("/dev/sdc1"));
+        assert_eq!(sdc1["type"].as_str(), Some("part"));
+        let luks = &sdc1["children"][0];
+        assert_eq!(luks["type"].as_str(), Some("crypt"));
+        assert_eq!(luks["path"].as_str(), Some("/dev/mapper/luks-aaa"));
+        let lvm = &luks["children"][0];
+        assert_eq!(lvm["type"].as_str(), Some("lvm"));
+        assert_eq!(lvm["path"].as_str(), Some("/dev/mapper/vg-root"));
+        assert_eq!(lvm["fstype"].as_str(), Some("ext4"));
+
+        let loop0 = &yaml["sources"][1];
+        assert_eq!(loop0["type"].as_str(), Some("loop"));
+        assert_eq!(loop0["parts"][0]["path"].as_str(), Some("/dev/loop0"));
+        assert_eq!(loop0["parts"][0]["fstype"].as_str(), Some("squashfs"));
+        assert_eq!(loop0["parts"][0]["mounted"].as_bool(), Some(true));
+    }
+
     #[test]
     fn clone_running_to_yaml() {
         let yaml_str = CloneStatus::Running {
@@ -345,12 +1034,31 @@ mod tests {
                 source_uuid: Some("123-234-345".to_owned()),
             },
             estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 00).unwrap()),
+            smoothed_estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 10).unwrap()),
             complete: 0.123,
             rate: Some("1GB/s".to_owned()),
+            metrics: Metrics {
+                bytes_per_second: Some(16_666_667),
+                bytes_done: Some(1_986_002_944),
+                bytes_total: Some(32_212_254_720),
+                elapsed_seconds: Some(12),
+            },
+            compressed_bytes: Some(496_500_736),
+            compression_ratio: Some(0.25),
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
         assert_eq!(yaml["type"].as_str(), Some("clone"));
+        assert_eq!(
+            yaml["smoothed_estimated_finish"].as_str(),
+            Some("2017-04-18T15:45:10Z")
+        );
+        assert_eq!(yaml["bytes_per_second"].as_i64(), Some(16_666_667));
+        assert_eq!(yaml["bytes_done"].as_i64(), Some(1_986_002_944));
+        assert_eq!(yaml["bytes_total"].as_i64(), Some(32_212_254_720));
+        assert_eq!(yaml["elapsed_seconds"].as_i64(), Some(12));
+        assert_eq!(yaml["compressed_bytes"].as_i64(), Some(496_500_736));
+        assert_eq!(yaml["compression_ratio"].as_f64(), Some(0.25));
         assert_eq!(yaml["complete"].as_f64(), Some(0.123));
         assert_eq!(yaml["id"].as_str(), Some("some-id"));
         assert_eq!(yaml["rate"].as_str(), Some("1GB/s"));
@@ -364,15 +1072,22 @@ mod tests {
     fn restore_running_to_yaml() {
         let yaml_str = RestoreStatus::Running {
             common: RestoreStatusCommon {
-                source: "/mnt/backups/ars2.gz",
-                destination: "/dev/ars2",
+                kind: "restore",
+                source: "/mnt/backups/ars2.gz".to_owned(),
+                destination: "/dev/ars2".to_owned(),
                 start: Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap(),
-                id: "some-id",
+                id: "some-id".to_owned(),
             },
             estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 00).unwrap()),
+            smoothed_estimated_finish: None,
             complete: 0.123,
             syncing: false,
             rate: Some("1GB/s".to_owned()),
+            metrics: Metrics {
+                elapsed_seconds: Some(3),
+                ..Metrics::default()
+            },
+            compressed_bytes: 1024,
         }
         .to_yaml();
 
@@ -387,6 +1102,9 @@ mod tests {
         assert_eq!(yaml["start"].as_str(), Some("2017-04-18T15:44:12Z"));
         assert_eq!(yaml["destination"].as_str(), Some("/dev/ars2"));
         assert_eq!(yaml["source"].as_str(), Some("/mnt/backups/ars2.gz"));
+        assert_eq!(yaml["elapsed_seconds"].as_i64(), Some(3));
+        assert_eq!(yaml["compressed_bytes"].as_i64(), Some(1024));
+        assert!(yaml["bytes_done"].is_badvalue());
     }
 
     #[test]
@@ -401,12 +1119,20 @@ mod tests {
                 source_uuid: None,
             },
             estimated_finish: None,
+            smoothed_estimated_finish: None,
             complete: 0.123,
             rate: None,
+            metrics: Metrics::default(),
+            compressed_bytes: None,
+            compression_ratio: None,
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
         assert_eq!(yaml["rate"].as_str(), None);
+        assert!(yaml["smoothed_estimated_finish"].is_badvalue());
+        assert!(yaml["bytes_per_second"].is_badvalue());
+        assert!(yaml["compressed_bytes"].is_badvalue());
+        assert!(yaml["compression_ratio"].is_badvalue());
         assert_eq!(yaml["estimated_finish"].as_str(), None);
     }
 
@@ -423,6 +1149,7 @@ mod tests {
             },
             finish: Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 34).unwrap(),
             image_size: 123_123,
+            compression_ratio: Some(1.0),
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
@@ -434,23 +1161,31 @@ mod tests {
         assert_eq!(yaml["source"].as_str(), Some("/dev/ars3"));
         assert_eq!(yaml["destination"].as_str(), Some("/mnt/backups/ars3.gz"));
         assert_eq!(yaml["image_size"].as_i64(), Some(123_123));
+        assert_eq!(yaml["compression_ratio"].as_f64(), Some(1.0));
     }
 
     #[test]
     fn restore_finished_to_yaml() {
         let yaml_str = RestoreStatus::Finished {
             common: RestoreStatusCommon {
-                source: "/mnt/backups/ars3.gz",
-                destination: "/dev/ars3",
+                kind: "restore",
+                source: "/mnt/backups/ars3.gz".to_owned(),
+                destination: "/dev/ars3".to_owned(),
                 start: Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap(),
-                id: "some-id",
+                id: "some-id".to_owned(),
             },
             finish: Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 34).unwrap(),
+            new_uuid: Some("2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4".to_owned()),
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
         assert_eq!(yaml["type"].as_str(), Some("restore"));
         assert_eq!(yaml["complete"].as_f64(), Some(1.0));
+        assert_eq!(yaml["phase"].as_str(), Some("finished"));
+        assert_eq!(
+            yaml["uuid"].as_str(),
+            Some("2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4")
+        );
         assert_eq!(yaml["id"].as_str(), Some("some-id"));
         assert_eq!(yaml["start"].as_str(), Some("2017-04-18T15:44:12Z"));
         assert_eq!(yaml["finish"].as_str(), Some("2017-04-18T15:45:34Z"));
@@ -470,16 +1205,88 @@ mod tests {
                 source_uuid: None,
             },
             finish: Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 34).unwrap(),
-            reason: "something went wrong".to_owned(),
+            reason: "Failed to expand filesystem: something went wrong".to_owned(),
+            output: vec![],
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
         assert_eq!(yaml["type"].as_str(), Some("clone-failed"));
+        assert_eq!(
+            yaml["error"].as_str(),
+            Some("Failed to expand filesystem: something went wrong")
+        );
         assert_eq!(yaml["id"].as_str(), Some("some-id"));
         assert_eq!(yaml["start"].as_str(), Some("2017-04-18T15:44:12Z"));
         assert_eq!(yaml["finish"].as_str(), Some("2017-04-18T15:45:34Z"));
         assert_eq!(yaml["source"].as_str(), Some("/dev/ars3"));
         assert_eq!(yaml["destination"].as_str(), Some("/mnt/backups/ars3.gz"));
+        assert!(yaml["output"].is_badvalue());
+    }
+
+    #[test]
+    fn job_failed_output_to_yaml() {
+        let yaml_str = CloneStatus::Failed {
+            common: CloneStatusCommon {
+                source: "/dev/ars3".to_owned(),
+                destination: "/mnt/backups/ars3.gz".to_owned(),
+                inprogress_destination: "/mnt/backups/ars2.gz.inprogress".to_owned(),
+                start: Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap(),
+                id: "some-id".to_owned(),
+                source_uuid: None,
+            },
+            finish: Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 34).unwrap(),
+            reason: "Bad sectors".to_owned(),
+            output: vec![
+                "Block size:   4096 Byte".to_owned(),
+                "read error at block 123: Input/output error".to_owned(),
+            ],
+        }
+        .to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["error"].as_str(), Some("Bad sectors"));
+        assert_eq!(yaml["output"][0].as_str(), Some("Block size:   4096 Byte"));
+        assert_eq!(
+            yaml["output"][1].as_str(),
+            Some("read error at block 123: Input/output error")
+        );
+    }
+
+    #[test]
+    fn fsck_to_yaml() {
+        let common = crate::fsck::FsckStatusCommon {
+            id: "some-id".to_owned(),
+            device: "/dev/sdb1".to_owned(),
+            fstype: "ext2".to_owned(),
+            start: Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap(),
+            before_clone: true,
+        };
+        let yaml_str = FsckStatus::Running {
+            common: common.clone(),
+            output: Some("Pass 1: Checking inodes, blocks, and sizes".to_owned()),
+        }
+        .to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["type"].as_str(), Some("fsck"));
+        assert_eq!(yaml["device"].as_str(), Some("/dev/sdb1"));
+        assert_eq!(yaml["before"].as_str(), Some("clone"));
+        assert_eq!(yaml["finished"].as_bool(), Some(false));
+        assert_eq!(
+            yaml["output"].as_str(),
+            Some("Pass 1: Checking inodes, blocks, and sizes")
+        );
+
+        let yaml_str = FsckStatus::Finished {
+            common,
+            finish: Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 34).unwrap(),
+            clean: false,
+            exit_code: Some(4),
+        }
+        .to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["finished"].as_bool(), Some(true));
+        assert_eq!(yaml["finish"].as_str(), Some("2017-04-18T15:45:34Z"));
+        assert_eq!(yaml["clean"].as_bool(), Some(false));
+        assert_eq!(yaml["exit_code"].as_i64(), Some(4));
     }
 
     #[test]
@@ -494,8 +1301,12 @@ mod tests {
                 source_uuid: None,
             },
             estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 00).unwrap()),
+            smoothed_estimated_finish: None,
             complete: 1.0,
             rate: Some("2GB/s".to_owned()),
+            metrics: Metrics::default(),
+            compressed_bytes: None,
+            compression_ratio: None,
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
@@ -510,11 +1321,56 @@ mod tests {
                 source_uuid: None,
             },
             estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 00).unwrap()),
+            smoothed_estimated_finish: None,
             complete: 0.0,
             rate: Some("3GB/s".to_owned()),
+            metrics: Metrics::default(),
+            compressed_bytes: None,
+            compression_ratio: None,
         }
         .to_yaml();
         let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
         assert_eq!(yaml["complete"].as_f64(), Some(0.0));
     }
+
+    #[test]
+    fn job_list_to_yaml() {
+        let summary = JobSummary {
+            id: "some-id".to_owned(),
+            kind: "restore",
+            source: "/mnt/backups/ars3.gz".to_owned(),
+            destination: "/dev/ars3".to_owned(),
+            start: Utc.with_ymd_and_hms(2017, 4, 18, 15, 44, 12).unwrap(),
+            complete: 1.0,
+            rate: None,
+            estimated_finish: Some(Utc.with_ymd_and_hms(2017, 4, 18, 15, 45, 00).unwrap()),
+            phase: "syncing",
+        };
+        let yaml_str = JobList(vec![summary.clone()]).to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["type"].as_str(), Some("jobs"));
+        let job = &yaml["jobs"][0];
+        assert_eq!(job["id"].as_str(), Some("some-id"));
+        assert_eq!(job["kind"].as_str(), Some("restore"));
+        assert_eq!(job["source"].as_str(), Some("/mnt/backups/ars3.gz"));
+        assert_eq!(job["destination"].as_str(), Some("/dev/ars3"));
+        assert_eq!(job["start"].as_str(), Some("2017-04-18T15:44:12Z"));
+        assert_eq!(job["complete"].as_f64(), Some(1.0));
+        assert_eq!(
+            job["estimated_finish"].as_str(),
+            Some("2017-04-18T15:45:00Z")
+        );
+        assert_eq!(job["phase"].as_str(), Some("syncing"));
+        assert!(job["rate"].is_badvalue());
+
+        let yaml_str = JobResult("some-id".to_owned(), Some(summary)).to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["type"].as_str(), Some("job"));
+        assert_eq!(yaml["id"].as_str(), Some("some-id"));
+
+        let yaml_str = JobResult("other-id".to_owned(), None).to_yaml();
+        let yaml = YamlLoader::load_from_str(&yaml_str).unwrap().remove(0);
+        assert_eq!(yaml["type"].as_str(), Some("job-failed"));
+        assert_eq!(yaml["error"].as_str(), Some("No job with id other-id"));
+    }
 }
@@ -0,0 +1,212 @@
+use crate::include::*;
+use std::{
+    collections::VecDeque,
+    env,
+    error::Error,
+    time::{Duration, Instant},
+};
+
+/// Default time the client may not receive messages before the core gives up on it
+const DEFAULT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Messages zmq buffers for the client before sends would block, kept low so messages queue in
+/// the `Outbox` where progress is coalesced & a client not receiving is noticed
+pub const SEND_HIGH_WATER_MARK: i32 = 16;
+
+/// Returns how long the client may not receive messages before the core shuts down,
+/// overridable in seconds with `APART_DISCONNECT_TIMEOUT`
+pub fn disconnect_timeout() -> Duration {
+    match env::var("APART_DISCONNECT_TIMEOUT") {
+        Ok(secs) => secs.parse().map(Duration::from_secs).unwrap_or_else(|_| {
+            warn!("Invalid APART_DISCONNECT_TIMEOUT {}, using default", secs);
+            DEFAULT_DISCONNECT_TIMEOUT
+        }),
+        Err(_) => DEFAULT_DISCONNECT_TIMEOUT,
+    }
+}
+
+#[derive(Debug)]
+struct Outgoing {
+    msg: String,
+    /// job id of a progress message, superseded by later progress of the job
+    progress_of: Option<String>,
+    /// reports a job ending, kept across a detach so a reattaching client learns of it
+    ends_job: bool,
+}
+
+/// Messages awaiting sending to the client, so a slow client doesn't block jobs. Queued progress
+/// of a job is replaced by its latest progress, unless other messages have been queued since.
+/// Messages reporting a job ending outlive a detached client, see `detach`.
+#[derive(Debug)]
+pub struct Outbox {
+    queue: VecDeque<Outgoing>,
+    /// when the client stopped receiving messages
+    blocked_since: Option<Instant>,
+    disconnect_timeout: Duration,
+}
+
+impl Outbox {
+    pub fn new(disconnect_timeout: Duration) -> Outbox {
+        Outbox {
+            queue: VecDeque::new(),
+            blocked_since: None,
+            disconnect_timeout,
+        }
+    }
+
+    pub fn push(&mut self, msg: String) {
+        self.push_outgoing(msg, false);
+    }
+
+    /// Queues a message reporting a job ending, see `detach`
+    pub fn push_ended(&mut self, msg: String) {
+        self.push_outgoing(msg, true);
+    }
+
+    fn push_outgoing(&mut self, msg: String, ends_job: bool) {
+        // keep progress ordered with other messages, ie a job's progress before it finishing
+        for out in &mut self.queue {
+            out.progress_of = None;
+        }
+        self.queue.push_back(Outgoing {
+            msg,
+            progress_of: None,
+            ends_job,
+        });
+    }
+
+    /// Queues progress of a job, replacing its progress still queued
+    pub fn push_progress(&mut self, job_id: &str, msg: String) {
+        let queued = self
+            .queue
+            .iter_mut()
+            .find(|out| out.progress_of.as_deref() == Some(job_id));
+        match queued {
+            Some(queued) => queued.msg = msg,
+            None => self.queue.push_back(Outgoing {
+                msg,
+                progress_of: Some(job_id.to_owned()),
+                ends_job: false,
+            }),
+        }
+    }
+
+    /// Drops queued messages of a client no longer receiving, except those reporting a job
+    /// ending as the job is gone by the time a client reattaches
+    pub fn detach(&mut self) {
+        self.queue.retain(|out| out.ends_job);
+        self.blocked_since = None;
+    }
+
+    /// Sends queued messages until the client stops receiving, returning the number sent.
+    /// Errors once the client hasn't received messages for longer than the disconnect timeout.
+    pub fn flush(&mut self, socket: &zmq::Socket) -> Result<usize, Box<dyn Error>> {
+        let mut sent = 0;
+        while let Some(out) = self.queue.front() {
+            match socket.send(out.msg.as_str(), zmq::DONTWAIT) {
+                Ok(_) => {
+                    self.queue.pop_front();
+                    self.blocked_since = None;
+                    sent += 1;
+                }
+                Err(zmq::Error::EAGAIN) | Err(zmq::Error::EINTR) => {
+                    let blocked_since = *self.blocked_since.get_or_insert_with(Instant::now);
+                    if blocked_since.elapsed() > self.disconnect_timeout {
+                        return Err(format!(
+                            "Client not receiving for {:?}, {} messages queued",
+                            self.disconnect_timeout,
+                            self.queue.len()
+                        )
+                        .into());
+                    }
+                    break;
+                }
+                Err(err) => return Err(Box::new(err)),
+            }
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn pair(ctx: &zmq::Context) -> zmq::Socket {
+        let socket = ctx.socket(zmq::PAIR).unwrap();
+        socket.set_linger(0).unwrap();
+        socket
+    }
+
+    #[test]
+    fn coalesces_progress_until_client_receives() {
+        let ctx = zmq::Context::new();
+        let core = pair(&ctx);
+        let mut outbox = Outbox::new(Duration::from_secs(60));
+
+        outbox.push_progress("job1", "job1 10%".into());
+        outbox.push_progress("job2", "job2 10%".into());
+        outbox.push_progress("job1", "job1 20%".into());
+        outbox.push("job2 failed".into());
+        outbox.push_progress("job1", "job1 30%".into());
+
+        // no client connected
+        assert_eq!(outbox.flush(&core).unwrap(), 0);
+
+        let client = pair(&ctx);
+        client.bind("inproc://outbox-coalesce").unwrap();
+        core.connect("inproc://outbox-coalesce").unwrap();
+        assert_eq!(outbox.flush(&core).unwrap(), 4);
+
+        let received: Vec<_> = (0..4)
+            .map(|_| client.recv_string(0).unwrap().unwrap())
+            .collect();
+        assert_eq!(
+            received,
+            vec!["job1 20%", "job2 10%", "job2 failed", "job1 30%"]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_disconnect_timeout() {
+        let ctx = zmq::Context::new();
+        let core = pair(&ctx);
+        let mut outbox = Outbox::new(Duration::from_millis(10));
+
+        outbox.push("status".into());
+        assert_eq!(outbox.flush(&core).unwrap(), 0);
+        thread::sleep(Duration::from_millis(20));
+        assert!(outbox.flush(&core).is_err());
+
+        outbox.detach();
+        assert_eq!(outbox.flush(&core).unwrap(), 0);
+    }
+
+    #[test]
+    fn keeps_ended_jobs_across_detach() {
+        let ctx = zmq::Context::new();
+        let core = pair(&ctx);
+        let mut outbox = Outbox::new(Duration::from_millis(10));
+
+        outbox.push_progress("job1", "job1 10%".into());
+        outbox.push_ended("job1 finished".into());
+        outbox.push("status".into());
+        outbox.push_progress("job2", "job2 10%".into());
+        outbox.push_ended("job2 failed".into());
+        assert_eq!(outbox.flush(&core).unwrap(), 0);
+        thread::sleep(Duration::from_millis(20));
+        assert!(outbox.flush(&core).is_err());
+        outbox.detach();
+
+        let client = pair(&ctx);
+        client.bind("inproc://outbox-detach").unwrap();
+        core.connect("inproc://outbox-detach").unwrap();
+        assert_eq!(outbox.flush(&core).unwrap(), 2);
+
+        let received: Vec<_> = (0..2)
+            .map(|_| client.recv_string(0).unwrap().unwrap())
+            .collect();
+        assert_eq!(received, vec!["job1 finished", "job2 failed"]);
+    }
+}
@@ -7,24 +7,38 @@ use std::{
     fmt,
     io::{BufRead, BufReader, Error as IoError, ErrorKind},
     path::Path,
-    process::ChildStderr,
-    rc::Rc,
+    process::{ChildStderr, Command, Stdio},
     str,
     sync::mpsc::Sender,
 };
 
+/// Numeric progress parsed from partclone output
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Metrics {
+    pub bytes_per_second: Option<u64>,
+    /// bytes of the partition processed, up to the current block
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub elapsed_seconds: Option<u64>,
+}
+
 #[derive(Debug)]
 pub enum PartcloneStatus {
     Running {
         complete: f64,
         rate: String,
         estimated_finish: DateTime<Utc>,
+        /// estimated finish from smoothed throughput, see `EtaEstimator`
+        smoothed_estimated_finish: Option<DateTime<Utc>>,
+        metrics: Metrics,
     },
     Synced {
         finish: DateTime<Utc>,
     },
     Failed {
         finish: DateTime<Utc>,
+        /// last lines of partclone output, excluding progress
+        output: Vec<String>,
     },
 }
 
@@ -84,55 +98,188 @@ pub fn cmd(variant: &str) -> Result<String, IoError> {
     }
 }
 
+/// Parses the bytes in use from partclone's file system info output, ie
+/// "Space in use:   1.4 GB = 348357 Blocks" & "Block size:   4096 Byte"
+fn parse_used_bytes(output: &str) -> Option<u64> {
+    let used_re = Regex::new(r"(?m)^Space in use:.*=\s*(\d+) Blocks").unwrap();
+    let block_size_re = Regex::new(r"(?m)^Block size:\s*(\d+) Byte").unwrap();
+    let blocks: u64 = used_re.captures(output)?[1].parse().ok()?;
+    let block_size: u64 = block_size_re.captures(output)?[1].parse().ok()?;
+    Some(blocks * block_size)
+}
+
+/// Returns the bytes in use of the `variant` file system on `device` from the info partclone
+/// outputs in domain mode, which reads the file system's block bitmap rather than its data
+pub fn used_bytes(variant: &str, device: &str) -> Result<u64, IoError> {
+    let output = Command::new(cmd(variant)?)
+        .args(["-D", "-s", device, "-O", "/dev/null"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match parse_used_bytes(&stderr) {
+        Some(used) if output.status.success() => Ok(used),
+        _ => Err(IoError::other(format!(
+            "partclone.{} info failed: {}",
+            variant,
+            stderr.trim()
+        ))),
+    }
+}
+
 static PARTCLONE_LOG_TAIL: usize = 4;
 
+/// Weight of the latest throughput sample in `EtaEstimator`
+const ETA_SMOOTHING: f64 = 0.2;
+
+/// Estimates the time remaining from an exponentially weighted average of the completed fraction
+/// per second, steadier than partclone's "Remaining" where used blocks are clustered
+#[derive(Default, Debug)]
+pub struct EtaEstimator {
+    /// elapsed seconds & completed fraction of the latest sample
+    last: Option<(i64, f64)>,
+    /// smoothed completed fraction per second
+    throughput: Option<f64>,
+}
+
+impl EtaEstimator {
+    /// Updates with partclone progress, returning the estimated time remaining when known
+    pub fn update(&mut self, elapsed: TimeDelta, complete: f64) -> Option<TimeDelta> {
+        let elapsed = elapsed.num_seconds();
+        match self.last {
+            None if elapsed > 0 => {
+                // seed with the average so far
+                self.throughput = Some(complete / elapsed as f64);
+                self.last = Some((elapsed, complete));
+            }
+            None => self.last = Some((elapsed, complete)),
+            // partclone reports whole seconds, ignore samples until the next one
+            Some((last_elapsed, last_complete)) if elapsed > last_elapsed => {
+                let sample =
+                    ((complete - last_complete) / (elapsed - last_elapsed) as f64).max(0.0);
+                self.throughput = Some(match self.throughput {
+                    Some(throughput) => ETA_SMOOTHING * sample + (1.0 - ETA_SMOOTHING) * throughput,
+                    None => sample,
+                });
+                self.last = Some((elapsed, complete));
+            }
+            Some(_) => {}
+        }
+        if complete >= 1.0 {
+            return Some(TimeDelta::zero());
+        }
+        let throughput = self.throughput.filter(|t| *t > 0.0)?;
+        TimeDelta::try_milliseconds(((1.0 - complete) / throughput * 1000.0).round() as i64)
+    }
+}
+
+fn progress_re() -> Regex {
+    Regex::new(
+    r"Elapsed:\s*(\d{2,}:\d{2}:\d{2}), Remaining:\s*(\d{2,}:\d{2}:\d{2}), Completed:\s*(\d{1,3}\.?\d?\d?)%,\s*R?a?t?e?:?\s*([0-9][^,]+)").unwrap()
+}
+
+/// Parses a partclone rate, ie "9.87GB/min", as bytes per second
+fn parse_rate(rate: &str) -> Option<u64> {
+    let rate_re = Regex::new(r"^([0-9.]+)\s*(Byte|B|KB|MB|GB|TB)/(min|sec|s)$").unwrap();
+    let caps = rate_re.captures(rate.trim())?;
+    let value = caps[1].parse::<f64>().ok()?;
+    let unit = match &caps[2] {
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        _ => 1.0,
+    };
+    let per = if &caps[3] == "min" { 60.0 } else { 1.0 };
+    Some((value * unit / per).round() as u64)
+}
+
+/// Parses a partclone duration, ie "00:03:02"
+fn parse_duration(duration: &str) -> Option<TimeDelta> {
+    let duration_re = Regex::new(r"^(\d{2,}):(\d{2}):(\d{2})$").unwrap();
+    let cap = duration_re.captures(duration)?;
+    Some(
+        TimeDelta::try_hours(cap[1].parse().ok()?)?
+            + TimeDelta::try_minutes(cap[2].parse().ok()?)?
+            + TimeDelta::try_seconds(cap[3].parse().ok()?)?,
+    )
+}
+
+/// Returns `true` for partclone output lines worth reporting when it fails, ie not progress
+fn is_tail_worthy(line: &str) -> bool {
+    !line.is_empty()
+        && !line.starts_with("Elapsed:")
+        && !line.starts_with("current block:")
+        && !line.starts_with("Total Time:")
+}
+
 pub fn read_output(
     stderr: ChildStderr,
     tx: &Sender<PartcloneStatus>,
 ) -> Result<(), Box<dyn Error>> {
-    let progress_re = Regex::new(
-    r"Remaining:\s*(\d{2,}:\d{2}:\d{2}), Completed:\s*(\d{1,3}\.?\d?\d?)%,\s*R?a?t?e?:?\s*([0-9][^,]+)").unwrap();
+    let progress_re = progress_re();
+    let blocks_re = Regex::new(r"current block:\s*(\d+), total block:\s*(\d+)").unwrap();
+    let block_size_re = Regex::new(r"^Block size:\s*(\d+) Byte").unwrap();
 
     let (mut started_main_output, mut synced) = (false, false);
-    let duration_re = Regex::new(r"^(\d{2,}):(\d{2}):(\d{2})$").unwrap();
+    let ansi_escape_re = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
 
     let mut partclone_out_tail = Vec::new();
+    let mut block_size: Option<u64> = None;
+    // latest "current block" & "total block", output after each progress line
+    let mut blocks: Option<(u64, u64)> = None;
+    let mut eta = EtaEstimator::default();
 
-    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
-        let out = Rc::new(line);
-        partclone_out_tail.push(out.clone());
-        if partclone_out_tail.len() > PARTCLONE_LOG_TAIL {
-            partclone_out_tail.remove(0);
+    for out in BufReader::new(stderr).lines().map_while(Result::ok) {
+        let plain = ansi_escape_re.replace_all(&out, "");
+        let plain = plain.trim();
+        if is_tail_worthy(plain) {
+            partclone_out_tail.push(plain.to_owned());
+            if partclone_out_tail.len() > PARTCLONE_LOG_TAIL {
+                partclone_out_tail.remove(0);
+            }
         }
         debug!("partclone: {}", out);
+        if let Some(cap) = block_size_re.captures(plain) {
+            block_size = cap[1].parse().ok();
+        }
+        if let Some(cap) = blocks_re.captures(plain)
+            && let (Ok(current), Ok(total)) = (cap[1].parse(), cap[2].parse())
+        {
+            blocks = Some((current, total));
+        }
         if started_main_output {
             if !synced {
                 for cap in progress_re.captures_iter(&out) {
-                    let mut estimated_finish = None;
-                    for cap in duration_re.captures_iter(&cap[1]) {
-                        if let (Ok(hours), Ok(minutes), Ok(seconds)) = (
-                            cap[1].parse::<i64>(),
-                            cap[2].parse::<i64>(),
-                            cap[3].parse::<i64>(),
-                        ) {
-                            let remaining = TimeDelta::try_hours(hours).unwrap_or_default()
-                                + TimeDelta::try_minutes(minutes).unwrap_or_default()
-                                + TimeDelta::try_seconds(seconds).unwrap_or_default();
-                            estimated_finish = Some(Utc::now() + remaining);
-                        }
-                    }
-                    let estimated_finish = estimated_finish
+                    let estimated_finish = parse_duration(&cap[2])
+                        .map(|remaining| Utc::now() + remaining)
                         .ok_or_else(|| OutputInvalidError("!estimated_finish".to_owned()))?;
-                    let complete = cap[2].parse::<f64>()? / 100.0;
-                    let rate = cap[3].to_owned();
+                    let complete = cap[3].parse::<f64>()? / 100.0;
+                    let rate = cap[4].to_owned();
+                    let elapsed = parse_duration(&cap[1]);
+                    let smoothed_estimated_finish = elapsed
+                        .and_then(|elapsed| eta.update(elapsed, complete))
+                        .map(|remaining| Utc::now() + remaining);
+                    let metrics = Metrics {
+                        bytes_per_second: parse_rate(&rate),
+                        bytes_done: block_size
+                            .zip(blocks)
+                            .map(|(size, (current, _))| size * current),
+                        bytes_total: block_size
+                            .zip(blocks)
+                            .map(|(size, (_, total))| size * total),
+                        elapsed_seconds: elapsed.map(|elapsed| elapsed.num_seconds() as u64),
+                    };
                     debug!(
                         "Partclone output: complete: {}, finish: {}, rate: {}",
                         complete, estimated_finish, rate
                     );
                     if let Err(err) = tx.send(PartcloneStatus::Running {
                         estimated_finish,
+                        smoothed_estimated_finish,
                         rate,
                         complete,
+                        metrics,
                     }) {
                         // this can be expected if, for example, the job is cancelled
                         debug!("Could not send, job dropped?: {}", err);
@@ -152,12 +299,115 @@ pub fn read_output(
             debug!("Could not send, job dropped?: {}", err);
         }
     } else {
-        for tail_line in partclone_out_tail {
+        for tail_line in &partclone_out_tail {
             error!("Partclone-failed: {}", tail_line);
         }
-        if let Err(err) = tx.send(PartcloneStatus::Failed { finish: Utc::now() }) {
+        if let Err(err) = tx.send(PartcloneStatus::Failed {
+            finish: Utc::now(),
+            output: partclone_out_tail,
+        }) {
             debug!("Could not send, job dropped?: {}", err);
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("9.87GB/min"), Some(164_500_000));
+        assert_eq!(parse_rate("  1.11MB/min"), Some(18_500));
+        assert_eq!(parse_rate("512.00Byte/min"), Some(9));
+        assert_eq!(parse_rate("fast"), None);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(
+            parse_duration("01:03:02"),
+            Some(TimeDelta::try_seconds(3782).unwrap())
+        );
+        assert_eq!(parse_duration("3m"), None);
+    }
+
+    /// Feeds partclone progress lines to an `EtaEstimator`, returning partclone's remaining &
+    /// the estimated remaining seconds of each
+    fn remaining_seconds(lines: &[&str]) -> Vec<(i64, Option<i64>)> {
+        let mut eta = EtaEstimator::default();
+        lines
+            .iter()
+            .map(|line| {
+                let cap = progress_re().captures(line).expect("!progress line");
+                let elapsed = parse_duration(&cap[1]).unwrap();
+                let remaining = parse_duration(&cap[2]).unwrap().num_seconds();
+                let complete = cap[3].parse::<f64>().unwrap() / 100.0;
+                (
+                    remaining,
+                    eta.update(elapsed, complete).map(|r| r.num_seconds()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn eta_steady_throughput() {
+        let estimates = remaining_seconds(&[
+            "Elapsed: 00:00:00, Remaining: 00:00:00, Completed:   0.00%,   0.00byte/min,",
+            "Elapsed: 00:00:10, Remaining: 00:01:30, Completed:  10.00%,   1.20GB/min,",
+            "Elapsed: 00:00:20, Remaining: 00:01:20, Completed:  20.00%,   1.20GB/min,",
+            "Elapsed: 00:00:30, Remaining: 00:01:10, Completed:  30.00%,   1.20GB/min,",
+        ]);
+        assert_eq!(
+            estimates,
+            vec![(0, None), (90, Some(90)), (80, Some(80)), (70, Some(70))]
+        );
+    }
+
+    #[test]
+    fn used_bytes_from_info() {
+        let output = "Partclone v0.3.13 http://partclone.org\n\
+                      Starting to clone device (/dev/sdb1) to image (/dev/null)\n\
+                      Reading Super Block\n\
+                      Calculating bitmap... Please wait... done!\n\
+                      File system:  EXTFS\n\
+                      Device size:   10.7 GB = 2621440 Blocks\n\
+                      Space in use:   1.4 GB = 348357 Blocks\n\
+                      Free Space:     9.3 GB = 2273083 Blocks\n\
+                      Block size:   4096 Byte\n";
+        assert_eq!(parse_used_bytes(output), Some(348_357 * 4096));
+        assert_eq!(parse_used_bytes("Block size:   4096 Byte\n"), None);
+    }
+
+    #[test]
+    fn eta_smooths_clustered_blocks() {
+        // synthetic output in partclone's format, modelling a file system with densely used
+        // blocks just after 30% that partclone's own estimate overreacts to
+        let estimates = remaining_seconds(&[
+            "Elapsed: 00:00:10, Remaining: 00:01:30, Completed:  10.00%,   9.87GB/min,",
+            "Elapsed: 00:00:20, Remaining: 00:01:20, Completed:  20.00%,   9.87GB/min,",
+            "Elapsed: 00:00:30, Remaining: 00:01:10, Completed:  30.00%,   9.87GB/min,",
+            "Elapsed: 00:01:30, Remaining: 00:03:00, Completed:  33.33%,   3.29GB/min,",
+            "Elapsed: 00:01:40, Remaining: 00:00:40, Completed:  71.43%,   9.87GB/min,",
+        ]);
+        let partclone: Vec<_> = estimates.iter().map(|(raw, _)| *raw).collect();
+        let smoothed: Vec<_> = estimates.iter().map(|(_, eta)| eta.unwrap()).collect();
+        assert_eq!(partclone, vec![90, 80, 70, 180, 40]);
+        assert_eq!(smoothed, vec![90, 80, 70, 82, 20]);
+    }
+
+    #[test]
+    fn eta_ignores_samples_within_a_second() {
+        let estimates = remaining_seconds(&[
+            "Elapsed: 00:00:10, Remaining: 00:01:30, Completed:  10.00%,   1.20GB/min,",
+            "Elapsed: 00:00:10, Remaining: 00:00:10, Completed:  50.00%,   1.20GB/min,",
+            "Elapsed: 00:00:10, Remaining: 00:00:00, Completed: 100.00%,   1.20GB/min,",
+        ]);
+        assert_eq!(
+            estimates,
+            vec![(90, Some(90)), (10, Some(50)), (0, Some(0))]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    cell::{Cell, RefCell},
+    time::{Duration, Instant},
+};
+
+/// Coalesces a job's progress messages, sending the latest at most once per interval
+#[derive(Debug, Default)]
+pub struct ProgressThrottle {
+    /// requested with the job, otherwise the server's interval applies
+    interval: Option<Duration>,
+    last_sent: Cell<Option<Instant>>,
+    /// latest progress message held back within the interval
+    pending: RefCell<Option<String>>,
+}
+
+impl ProgressThrottle {
+    pub fn new(interval: Option<Duration>) -> ProgressThrottle {
+        ProgressThrottle {
+            interval,
+            ..ProgressThrottle::default()
+        }
+    }
+
+    fn is_due(&self, default_interval: Option<Duration>, now: Instant) -> bool {
+        match (self.interval.or(default_interval), self.last_sent.get()) {
+            (Some(interval), Some(last_sent)) => now.duration_since(last_sent) >= interval,
+            _ => true,
+        }
+    }
+
+    /// Returns the progress message to send now, otherwise holds it replacing any pending
+    pub fn progress(&self, msg: String, default_interval: Option<Duration>) -> Option<String> {
+        self.progress_at(msg, default_interval, Instant::now())
+    }
+
+    fn progress_at(
+        &self,
+        msg: String,
+        default_interval: Option<Duration>,
+        now: Instant,
+    ) -> Option<String> {
+        if self.is_due(default_interval, now) {
+            *self.pending.borrow_mut() = None;
+            self.last_sent.set(Some(now));
+            Some(msg)
+        } else {
+            *self.pending.borrow_mut() = Some(msg);
+            None
+        }
+    }
+
+    /// Returns the held progress message once its interval has passed
+    pub fn due(&self, default_interval: Option<Duration>) -> Option<String> {
+        self.due_at(default_interval, Instant::now())
+    }
+
+    /// Drops any held progress message, as the job has moved on, ie to syncing or finished
+    pub fn clear(&self) {
+        *self.pending.borrow_mut() = None;
+    }
+
+    fn due_at(&self, default_interval: Option<Duration>, now: Instant) -> Option<String> {
+        if self.pending.borrow().is_some() && self.is_due(default_interval, now) {
+            self.last_sent.set(Some(now));
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn unthrottled() {
+        let throttle = ProgressThrottle::new(None);
+        let now = Instant::now();
+        assert_eq!(
+            throttle.progress_at("1".into(), None, now),
+            Some("1".into())
+        );
+        assert_eq!(
+            throttle.progress_at("2".into(), None, now),
+            Some("2".into())
+        );
+        assert_eq!(throttle.due_at(None, now), None);
+    }
+
+    #[test]
+    fn coalesces_to_latest() {
+        let throttle = ProgressThrottle::new(None);
+        let start = Instant::now();
+        assert_eq!(
+            throttle.progress_at("1".into(), Some(SECOND), start),
+            Some("1".into())
+        );
+        let later = start + SECOND / 4;
+        assert_eq!(throttle.progress_at("2".into(), Some(SECOND), later), None);
+        assert_eq!(throttle.progress_at("3".into(), Some(SECOND), later), None);
+        assert_eq!(throttle.due_at(Some(SECOND), later), None);
+
+        let next = start + SECOND;
+        assert_eq!(throttle.due_at(Some(SECOND), next), Some("3".into()));
+        assert_eq!(throttle.due_at(Some(SECOND), next + SECOND), None);
+    }
+
+    #[test]
+    fn clear_drops_held_progress() {
+        let throttle = ProgressThrottle::new(Some(SECOND));
+        let start = Instant::now();
+        assert!(throttle.progress_at("1".into(), None, start).is_some());
+        assert_eq!(throttle.progress_at("2".into(), None, start), None);
+        throttle.clear();
+        assert_eq!(throttle.due_at(None, start + SECOND), None);
+    }
+
+    #[test]
+    fn job_interval_overrides_default() {
+        let throttle = ProgressThrottle::new(Some(SECOND * 5));
+        let start = Instant::now();
+        assert!(throttle.progress_at("1".into(), None, start).is_some());
+        assert_eq!(throttle.progress_at("2".into(), None, start + SECOND), None);
+        assert_eq!(
+            throttle.progress_at("3".into(), None, start + SECOND * 5),
+            Some("3".into())
+        );
+    }
+}
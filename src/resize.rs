@@ -0,0 +1,59 @@
+use crate::{child, fsck, include::*, mount};
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    process::Command,
+};
+
+/// File system types `grow` supports, "extfs" being any ext of an image partclone.extfs made
+pub fn is_supported(fstype: &str) -> bool {
+    matches!(
+        fstype,
+        "ext2" | "ext3" | "ext4" | "extfs" | "ntfs" | "xfs" | "btrfs"
+    )
+}
+
+/// Grows the file system on the device to fill it
+pub fn grow(device: &str, fstype: &str) -> IoResult<()> {
+    info!("Expanding {} filesystem on {}", fstype, device);
+    match fstype {
+        "ext2" | "ext3" | "ext4" | "extfs" => {
+            // resize2fs requires a freshly checked file system
+            fsck::repair_ext(device)?;
+            child::run(Command::new(child::cmd("resize2fs")).arg(device))
+        }
+        // twice to skip the confirmation prompt
+        "ntfs" => child::run(
+            Command::new(child::cmd("ntfsresize"))
+                .args(["--force", "--force"])
+                .arg(device),
+        ),
+        "xfs" => with_temporary_mount(device, |mountpoint| {
+            child::run(Command::new(child::cmd("xfs_growfs")).arg(mountpoint))
+        }),
+        "btrfs" => with_temporary_mount(device, |mountpoint| {
+            child::run(
+                Command::new(child::cmd("btrfs"))
+                    .args(["filesystem", "resize", "max"])
+                    .arg(mountpoint),
+            )
+        }),
+        _ => Err(IoError::new(
+            ErrorKind::Unsupported,
+            format!("Cannot expand {} filesystem", fstype),
+        )),
+    }
+}
+
+/// xfs & btrfs can only be grown while mounted
+fn with_temporary_mount<F>(device: &str, func: F) -> IoResult<()>
+where
+    F: FnOnce(&str) -> IoResult<()>,
+{
+    let mountpoint = mount::mount_temporary(device, "rw")?;
+    let result = func(&mountpoint);
+    if let Err(err) = mount::unmount_temporary(&mountpoint) {
+        error!("Failed to unmount {}: {}", mountpoint, err);
+        return result.and(Err(err));
+    }
+    result
+}
@@ -1,45 +1,79 @@
 use crate::{
-    child, clone::partclone_variant_from_image, compression::Compression, include::*, partclone,
+    asynchronous, child,
+    clone::partclone_variant_from_image,
+    compression::Compression,
+    export::{Export, ExportFormat},
+    failure::{Failure, PendingFailure},
+    fsuuid,
+    fsuuid::NewUuid,
+    image,
+    include::*,
+    jobs::JobSummary,
+    partclone,
     partclone::*,
+    resize,
 };
 use chrono::prelude::*;
 use std::{
-    cell::Cell,
+    cell::{Cell, RefCell},
     error::Error,
-    fmt,
+    fmt, fs,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Result as IoResult},
     os::unix::io::{FromRawFd, IntoRawFd},
+    path::Path,
     process::{Child, Command, Stdio},
     str,
-    sync::{mpsc, mpsc::Receiver},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+        mpsc::Receiver,
+    },
     thread,
 };
 use uuid::Uuid;
 
-#[derive(Debug)]
-pub struct RestoreStatusCommon<'a> {
-    pub id: &'a str,
-    pub source: &'a str,
-    pub destination: &'a str,
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RestoreStatusCommon {
+    /// "restore", or "export" for image exports
+    pub kind: &'static str,
+    pub id: String,
+    pub source: String,
+    pub destination: String,
     pub start: DateTime<Utc>,
 }
 
-#[derive(Debug)]
-pub enum RestoreStatus<'a> {
+#[derive(Clone, PartialEq, Debug)]
+pub enum RestoreStatus {
     Running {
-        common: RestoreStatusCommon<'a>,
+        common: RestoreStatusCommon,
         complete: f64,
         syncing: bool,
         rate: Option<String>,
         estimated_finish: Option<DateTime<Utc>>,
+        smoothed_estimated_finish: Option<DateTime<Utc>>,
+        metrics: Metrics,
+        /// image bytes read so far
+        compressed_bytes: u64,
+    },
+    /// Modifying the restored destination, ie "resizing" the file system to fill it
+    Finalizing {
+        common: RestoreStatusCommon,
+        phase: &'static str,
     },
     Finished {
-        common: RestoreStatusCommon<'a>,
+        common: RestoreStatusCommon,
         finish: DateTime<Utc>,
+        /// file system uuid set by `new_uuid`
+        new_uuid: Option<String>,
     },
     Failed {
-        common: RestoreStatusCommon<'a>,
+        common: RestoreStatusCommon,
         reason: String,
         finish: DateTime<Utc>,
+        /// command output the reason was classified from
+        output: Vec<String>,
     },
 }
 
@@ -48,57 +82,283 @@ pub struct RestoreJob {
     source: String,
     destination: String,
     id: String,
-    cat_cmd: Child,
+    image_bytes_read: Arc<AtomicU64>,
     compress_cmd: Child,
-    partclone_cmd: Child,
+    compress_stderr: Receiver<String>,
+    partclone_cmd: RefCell<Child>,
     start: DateTime<Utc>,
     sent_first_msg: Cell<bool>,
+    latest_status: RefCell<Option<RestoreStatus>>,
+    /// `complete` of the latest running status, kept once the job fails
+    latest_complete: Cell<f64>,
     partclone_status: Receiver<PartcloneStatus>,
+    fstype: String,
+    expand_filesystem: bool,
+    new_uuid: Option<Uuid>,
+    export: Option<Export>,
+    /// restoring into a file created for the job, removed unless the job finishes
+    raw_file: bool,
+    partclone_synced: Cell<bool>,
+    post_restore_task: RefCell<Option<Receiver<Result<(), String>>>>,
+    failure: RefCell<Option<PendingFailure>>,
 }
 
-impl<'j> RestoreJob {
-    pub fn try_recv(&'j self) -> Result<RestoreStatus<'j>, Box<dyn Error>> {
+/// Expands the file system, sets its uuid & finishes exports as requested after partclone has
+/// finished
+fn post_restore(
+    destination: &str,
+    fstype: &str,
+    expand_filesystem: bool,
+    new_uuid: Option<Uuid>,
+    export: Option<Export>,
+) -> Result<(), String> {
+    if expand_filesystem {
+        resize::grow(destination, fstype)
+            .map_err(|err| format!("Failed to expand filesystem: {}", err))?;
+    }
+    if let Some(uuid) = new_uuid {
+        fsuuid::set(destination, fstype, uuid)
+            .map_err(|err| format!("Failed to set filesystem uuid: {}", err))?;
+    }
+    if let Some(export) = export {
+        export
+            .finish()
+            .map_err(|err| format!("Failed to export {}: {}", export.format.name(), err))?;
+    }
+    Ok(())
+}
+
+/// Creates a new sparse file sized as the partition imaged in `source` when known
+fn create_sparse_file(source: &str, file: &str) -> Result<(), Box<dyn Error>> {
+    let sparse = match OpenOptions::new().write(true).create_new(true).open(file) {
+        Ok(sparse) => sparse,
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            return Err(format!("{} already exists", file).into());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let sized: IoResult<()> = image::partclone_header(source).and_then(|header| match header {
+        Some(header) => sparse.set_len(header.device_size),
+        None => Ok(()),
+    });
+    if let Err(err) = sized {
+        remove_file_log_errors(file);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+fn remove_file_log_errors(file: &str) {
+    if Path::new(file).exists()
+        && let Err(err) = fs::remove_file(file)
+    {
+        error!("Could not rm restore file {}: {}", file, err);
+    }
+}
+
+impl RestoreJob {
+    pub fn try_recv(&self) -> Result<RestoreStatus, Box<dyn Error>> {
+        let status = self.recv_status()?;
+        if let RestoreStatus::Running { complete, .. } = status {
+            self.latest_complete.set(complete);
+        }
+        *self.latest_status.borrow_mut() = Some(status.clone());
+        Ok(status)
+    }
+
+    /// Returns the latest status received, for clients asking rather than following messages
+    pub fn summary(&self) -> JobSummary {
+        let RestoreStatusCommon {
+            kind,
+            id,
+            source,
+            destination,
+            start,
+        } = self.clone_status_common();
+        let status = self
+            .latest_status
+            .borrow()
+            .clone()
+            .unwrap_or_else(|| self.started_status());
+        let (complete, rate, estimated_finish, phase) = match status {
+            RestoreStatus::Running {
+                complete,
+                syncing,
+                rate,
+                estimated_finish,
+                ..
+            } => (
+                complete,
+                rate,
+                estimated_finish,
+                if syncing { "syncing" } else { "restoring" },
+            ),
+            RestoreStatus::Finalizing { phase, .. } => (0.9999, None, None, phase),
+            RestoreStatus::Finished { .. } => (1.0, None, None, "finished"),
+            RestoreStatus::Failed { .. } => (self.latest_complete.get(), None, None, "failed"),
+        };
+        JobSummary {
+            id,
+            kind,
+            source,
+            destination,
+            start,
+            complete,
+            rate,
+            estimated_finish,
+            phase,
+        }
+    }
+
+    fn started_status(&self) -> RestoreStatus {
+        RestoreStatus::Running {
+            common: self.clone_status_common(),
+            complete: 0.0,
+            syncing: false,
+            rate: None,
+            estimated_finish: None,
+            smoothed_estimated_finish: None,
+            metrics: Metrics::default(),
+            compressed_bytes: 0,
+        }
+    }
+
+    fn recv_status(&self) -> Result<RestoreStatus, Box<dyn Error>> {
         if !self.sent_first_msg.get() {
             // bosh out an initial running message to show the clone has started
             self.sent_first_msg.set(true);
-            return Ok(RestoreStatus::Running {
-                common: self.clone_status_common(),
-                complete: 0.0,
-                syncing: false,
-                rate: None,
-                estimated_finish: None,
+            return Ok(self.started_status());
+        }
+
+        if self.failure.borrow().is_some() {
+            return self.try_failed_status();
+        }
+
+        if let Some(post_restore_task) = self.post_restore_task.borrow().as_ref() {
+            return Ok(match post_restore_task.try_recv()? {
+                Ok(_) => RestoreStatus::Finished {
+                    common: self.clone_status_common(),
+                    finish: Utc::now(),
+                    new_uuid: self.new_uuid.map(|uuid| uuid.to_string()),
+                },
+                Err(reason) => {
+                    error!("Restore to {} failed: {}", self.destination, reason);
+                    RestoreStatus::Failed {
+                        common: self.clone_status_common(),
+                        finish: Utc::now(),
+                        reason,
+                        output: vec![],
+                    }
+                }
             });
         }
 
+        if self.partclone_synced.get() {
+            // wait for partclone to release the destination before modifying the file system
+            return match self.partclone_cmd.borrow_mut().try_wait() {
+                Ok(Some(status)) if status.success() => {
+                    let destination = self.destination.clone();
+                    let fstype = self.fstype.clone();
+                    let (expand_filesystem, new_uuid) = (self.expand_filesystem, self.new_uuid);
+                    let export = self.export.clone();
+                    *self.post_restore_task.borrow_mut() =
+                        Some(asynchronous::receiver(move || {
+                            post_restore(&destination, &fstype, expand_filesystem, new_uuid, export)
+                        }));
+                    Err("Post restore task started".into())
+                }
+                Ok(None) => Err("Waiting for partclone to finish".into()),
+                Ok(Some(_)) | Err(_) => Ok(self.fail_status("Failed")),
+            };
+        }
+
         Ok(match self.partclone_status.try_recv()? {
             PartcloneStatus::Running {
                 rate,
                 estimated_finish,
+                smoothed_estimated_finish,
                 complete,
+                metrics,
             } => RestoreStatus::Running {
                 common: self.clone_status_common(),
                 complete: if complete > 0.9999 { 0.9999 } else { complete },
                 syncing: complete > 0.9999,
                 rate: Some(rate),
                 estimated_finish: Some(estimated_finish),
+                smoothed_estimated_finish,
+                metrics,
+                compressed_bytes: self.compressed_bytes(),
             },
+            PartcloneStatus::Synced { .. } if self.expand_filesystem => {
+                self.partclone_synced.set(true);
+                RestoreStatus::Finalizing {
+                    common: self.clone_status_common(),
+                    phase: "resizing",
+                }
+            }
+            PartcloneStatus::Synced { .. }
+                if self
+                    .export
+                    .as_ref()
+                    .is_some_and(|export| export.format != ExportFormat::Raw) =>
+            {
+                self.partclone_synced.set(true);
+                RestoreStatus::Finalizing {
+                    common: self.clone_status_common(),
+                    phase: "converting",
+                }
+            }
+            PartcloneStatus::Synced { .. } if self.new_uuid.is_some() || self.export.is_some() => {
+                self.partclone_synced.set(true);
+                RestoreStatus::Running {
+                    common: self.clone_status_common(),
+                    complete: 0.9999,
+                    syncing: true,
+                    rate: None,
+                    estimated_finish: None,
+                    smoothed_estimated_finish: None,
+                    metrics: Metrics::default(),
+                    compressed_bytes: self.compressed_bytes(),
+                }
+            }
             PartcloneStatus::Synced { finish } => RestoreStatus::Finished {
                 common: self.clone_status_common(),
                 finish,
+                new_uuid: None,
             },
-            PartcloneStatus::Failed { finish } => RestoreStatus::Failed {
-                common: self.clone_status_common(),
-                finish,
-                reason: "Failed".to_owned(),
-            },
+            PartcloneStatus::Failed { finish, output } => {
+                *self.failure.borrow_mut() = Some(PendingFailure::new(finish, output));
+                return self.try_failed_status();
+            }
         })
     }
 
-    pub fn clone_status_common(&'j self) -> RestoreStatusCommon<'j> {
+    /// Returns the failed status once the decompressor's output to classify the failure with is
+    /// available, see `PendingFailure`
+    fn try_failed_status(&self) -> Result<RestoreStatus, Box<dyn Error>> {
+        let failure = self.failure.borrow();
+        let failure = failure.as_ref().expect("!failure");
+        let Failure { reason, output } = failure
+            .try_failure(&self.compress_stderr)
+            .ok_or("Waiting for decompressor output")?;
+        Ok(RestoreStatus::Failed {
+            common: self.clone_status_common(),
+            finish: failure.finish,
+            reason,
+            output,
+        })
+    }
+
+    pub fn clone_status_common(&self) -> RestoreStatusCommon {
+        let (kind, destination) = match &self.export {
+            Some(export) => ("export", &export.destination),
+            None => ("restore", &self.destination),
+        };
         RestoreStatusCommon {
-            id: &self.id,
-            source: &self.source,
-            destination: &self.destination,
+            kind,
+            id: self.id.clone(),
+            source: self.source.clone(),
+            destination: destination.clone(),
             start: self.start,
         }
     }
@@ -107,39 +367,107 @@ impl<'j> RestoreJob {
         &self.id
     }
 
-    pub fn fail_status(&self, reason: &str) -> RestoreStatus<'_> {
+    fn compressed_bytes(&self) -> u64 {
+        self.image_bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub fn fail_status(&self, reason: &str) -> RestoreStatus {
         RestoreStatus::Failed {
             common: self.clone_status_common(),
             reason: reason.to_owned(),
             finish: Utc::now(),
+            output: vec![],
         }
     }
 
-    pub fn new(source: String, destination: String) -> Result<RestoreJob, Box<dyn Error>> {
-        let partclone_cmd = partclone::cmd(&partclone_variant_from_image(&source)?)?;
+    pub fn new(
+        id: String,
+        source: String,
+        destination: String,
+        expand_filesystem: bool,
+        new_uuid: Option<NewUuid>,
+    ) -> Result<RestoreJob, Box<dyn Error>> {
+        RestoreJob::start(
+            id,
+            source,
+            destination,
+            false,
+            expand_filesystem,
+            new_uuid,
+            None,
+        )
+    }
 
-        let z = Compression::from_file_name(&source)?;
+    /// Restores into a new regular file, sized as the imaged partition when known
+    pub fn new_to_file(
+        id: String,
+        source: String,
+        destination: String,
+    ) -> Result<RestoreJob, Box<dyn Error>> {
+        create_sparse_file(&source, &destination)?;
+        RestoreJob::start(id, source, destination.clone(), true, false, None, None).inspect_err(
+            |_| {
+                remove_file_log_errors(&destination);
+            },
+        )
+    }
 
-        let mut cat = Command::new("cat")
-            .arg(&source)
-            .stdout(Stdio::piped())
-            .stdin(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?;
+    /// Restores into a raw disk image, then moves or converts it to the export destination
+    pub fn new_export(
+        id: String,
+        source: String,
+        export: Export,
+    ) -> Result<RestoreJob, Box<dyn Error>> {
+        if Path::new(&export.destination).exists() {
+            return Err(format!("{} already exists", export.destination).into());
+        }
+        let raw = export.inprogress_destination();
+        create_sparse_file(&source, &raw)?;
+        RestoreJob::start(id, source, raw.clone(), true, false, None, Some(export)).inspect_err(
+            |_| {
+                remove_file_log_errors(&raw);
+            },
+        )
+    }
+
+    fn start(
+        id: String,
+        source: String,
+        destination: String,
+        raw_file: bool,
+        expand_filesystem: bool,
+        new_uuid: Option<NewUuid>,
+        export: Option<Export>,
+    ) -> Result<RestoreJob, Box<dyn Error>> {
+        let partclone_variant = partclone_variant_from_image(&source)?;
+        if expand_filesystem && !resize::is_supported(&partclone_variant) {
+            return Err(format!("Cannot expand {} filesystem", partclone_variant).into());
+        }
+        if new_uuid.is_some() && !fsuuid::is_supported(&partclone_variant) {
+            return Err(format!("Cannot set {} filesystem uuid", partclone_variant).into());
+        }
+        let partclone_cmd = partclone::cmd(&partclone_variant)?;
+
+        let z = Compression::from_file_name(&source)?;
 
+        let image = File::open(&source)?;
         let mut z_process = Command::new(z.command)
             .args(z.read_args)
             .stdout(Stdio::piped())
-            .stdin(unsafe {
-                Stdio::from_raw_fd(cat.stdout.take().expect("!cat.stdout").into_raw_fd())
-            })
-            .stderr(Stdio::null())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()?;
+        let compress_stderr = child::read_stderr(&mut z_process, z.command)?;
+        let image_bytes_read =
+            child::counted_copy(image, z_process.stdin.take().expect("!z.stdin"), &source)?;
 
         let mut partclone_cmd = {
             let mut args = Vec::new();
             if !partclone_cmd.ends_with("dd") {
                 args.push("-r");
+                if raw_file {
+                    args.push("-W");
+                }
             }
             args.push("-o");
             args.push(&destination);
@@ -170,13 +498,24 @@ impl<'j> RestoreJob {
         let job = RestoreJob {
             source,
             destination,
-            cat_cmd: cat,
+            image_bytes_read,
             compress_cmd: z_process,
-            partclone_cmd,
+            compress_stderr,
+            partclone_cmd: RefCell::new(partclone_cmd),
             partclone_status,
             start: Utc::now(),
             sent_first_msg: Cell::new(false),
-            id: Uuid::new_v4().to_string(),
+            latest_status: RefCell::new(None),
+            latest_complete: Cell::new(0.0),
+            id,
+            fstype: partclone_variant,
+            expand_filesystem,
+            new_uuid: new_uuid.map(NewUuid::to_uuid),
+            export,
+            raw_file,
+            partclone_synced: Cell::new(false),
+            post_restore_task: RefCell::new(None),
+            failure: RefCell::new(None),
         };
 
         Ok(job)
@@ -191,8 +530,18 @@ impl fmt::Display for RestoreJob {
 
 impl Drop for RestoreJob {
     fn drop(&mut self) {
-        child::drop_log_errors(&mut self.cat_cmd, "RestoreJob#cat_cmd");
         child::drop_log_errors(&mut self.compress_cmd, "RestoreJob#compress_cmd");
-        child::drop_log_errors(&mut self.partclone_cmd, "RestoreJob#partclone_cmd");
+        child::drop_log_errors(
+            &mut self.partclone_cmd.borrow_mut(),
+            "RestoreJob#partclone_cmd",
+        );
+        let finished = matches!(
+            *self.latest_status.borrow(),
+            Some(RestoreStatus::Finished { .. })
+        );
+        if self.raw_file && !finished {
+            // unfinished file or raw disk image of an export
+            remove_file_log_errors(&self.destination);
+        }
     }
 }
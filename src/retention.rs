@@ -0,0 +1,176 @@
+use crate::{clone, server::DeleteResult};
+use chrono::prelude::*;
+use std::{collections::HashSet, fs, io::Result as IoResult};
+
+/// Rules deciding which images of a backup name are kept, images not kept by any rule
+/// are pruned. A policy without any rules keeps everything.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct RetentionPolicy {
+    /// keep the newest n images
+    pub keep_last: Option<usize>,
+    /// keep the newest image of each of the n most recent days with images
+    pub keep_daily: Option<usize>,
+    /// keep the newest image of each of the n most recent iso weeks with images
+    pub keep_weekly: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none() && self.keep_daily.is_none() && self.keep_weekly.is_none()
+    }
+}
+
+/// Returns the files of `images`, `(file, timestamp)` pairs, that the policy does not retain
+fn images_to_prune(
+    mut images: Vec<(String, NaiveDateTime)>,
+    policy: RetentionPolicy,
+) -> Vec<String> {
+    if policy.is_empty() {
+        return Vec::new();
+    }
+    // newest first
+    images.sort_by_key(|(_, timestamp)| std::cmp::Reverse(*timestamp));
+
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    let mut prune = Vec::new();
+    for (idx, (file, timestamp)) in images.into_iter().enumerate() {
+        let mut keep = policy.keep_last.is_some_and(|n| idx < n);
+        if let Some(n) = policy.keep_daily
+            && days.len() < n
+            && days.insert(timestamp.date())
+        {
+            keep = true;
+        }
+        if let Some(n) = policy.keep_weekly {
+            let week = timestamp.iso_week();
+            if weeks.len() < n && weeks.insert((week.year(), week.week())) {
+                keep = true;
+            }
+        }
+        if !keep {
+            prune.push(file);
+        }
+    }
+    prune
+}
+
+/// Finished images in `directory` created with backup `name`, with their timestamps
+fn images_of(directory: &str, name: &str) -> IoResult<Vec<(String, NaiveDateTime)>> {
+    let mut images = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.ends_with(".inprogress") {
+            continue;
+        }
+        if let Some((image_name, timestamp)) = clone::image_name_and_timestamp(&file_name)
+            && image_name == name
+        {
+            images.push((format!("{}/{}", directory, file_name), timestamp));
+        }
+    }
+    Ok(images)
+}
+
+/// Deletes images of backup `name` in `directory` not retained by the policy
+pub fn prune(directory: &str, name: &str, policy: RetentionPolicy) -> IoResult<Vec<DeleteResult>> {
+    Ok(images_to_prune(images_of(directory, name)?, policy)
+        .into_iter()
+        .map(|file| {
+            let rm_result = fs::remove_file(&file);
+            DeleteResult(file, rm_result)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(timestamp: &str) -> (String, NaiveDateTime) {
+        (
+            format!("work-{}.apt.ext4.gz", timestamp),
+            NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H%M").unwrap(),
+        )
+    }
+
+    fn sorted(mut files: Vec<String>) -> Vec<String> {
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let images = vec![image("2017-04-18T1739"), image("2017-04-19T1739")];
+        assert!(images_to_prune(images, RetentionPolicy::default()).is_empty());
+    }
+
+    #[test]
+    fn keep_last() {
+        let images = vec![
+            image("2017-04-18T1739"),
+            image("2017-04-20T0900"),
+            image("2017-04-19T1739"),
+            image("2017-04-17T1739"),
+        ];
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(
+            sorted(images_to_prune(images, policy)),
+            vec![
+                "work-2017-04-17T1739.apt.ext4.gz".to_owned(),
+                "work-2017-04-18T1739.apt.ext4.gz".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_daily_keeps_newest_of_each_day() {
+        let images = vec![
+            image("2017-04-18T0900"),
+            image("2017-04-18T1739"),
+            image("2017-04-19T0800"),
+            image("2017-04-19T1200"),
+            image("2017-04-17T1739"),
+        ];
+        let policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(
+            sorted(images_to_prune(images, policy)),
+            vec![
+                "work-2017-04-17T1739.apt.ext4.gz".to_owned(),
+                "work-2017-04-18T0900.apt.ext4.gz".to_owned(),
+                "work-2017-04-19T0800.apt.ext4.gz".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn rules_combine() {
+        // 2017-04-10, 2017-04-11 & 2017-04-12 are in the same iso week
+        let images = vec![
+            image("2017-04-03T1200"),
+            image("2017-04-10T1200"),
+            image("2017-04-11T1200"),
+            image("2017-04-12T1200"),
+            image("2017-04-18T1200"),
+        ];
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            keep_daily: Some(2),
+            keep_weekly: Some(3),
+        };
+        assert_eq!(
+            sorted(images_to_prune(images, policy)),
+            vec![
+                "work-2017-04-10T1200.apt.ext4.gz".to_owned(),
+                "work-2017-04-11T1200.apt.ext4.gz".to_owned(),
+            ]
+        );
+    }
+}
@@ -1,37 +1,322 @@
 use crate::{
-    clone,
+    asynchronous, clone,
     clone::{CloneJob, CloneStatus},
+    compression::Compression,
+    devwatch::DeviceWatcher,
+    estimate,
+    estimate::CloneEstimateResult,
+    fsck::{FsckJob, FsckStatus},
+    fsuuid::NewUuid,
+    history,
+    history::{HistoryEntry, HistoryLog, HistoryResult},
+    hooks,
+    hooks::{Hooks, JobEnv},
+    image, import,
+    import::ImportResult,
     inbound::{Request, Request::*},
     include::*,
+    jobs::{JobList, JobResult, JobSummary},
     lsblk,
+    lsblk::PartitionRef,
+    mount,
+    mount::MountResult,
     outbound::*,
+    outbox,
+    outbox::Outbox,
+    progress::ProgressThrottle,
     restore::*,
+    retention,
+    retention::RetentionPolicy,
+    space,
 };
+use chrono::prelude::*;
+use json::JsonValue;
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    env,
     error::Error,
     fs,
     io::Result as IoResult,
     marker::Send,
     mem,
-    sync::mpsc::{Receiver, Sender, channel},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, Sender, TryRecvError, channel},
+    },
     thread,
+    time::Duration,
 };
+use uuid::Uuid;
+
+/// Longest shutdown waits for the post-hooks of cancelled jobs, before dying without them
+const SHUTDOWN_POST_HOOK_WAIT: Duration = Duration::from_secs(30);
 
 pub struct DeleteResult(pub String, pub IoResult<()>);
 
+/// A job, ie "clone" or "restore", that couldn't be started, its id if it was pending & why
+pub struct StartFailed(pub &'static str, pub Option<String>, pub String);
+
+/// A `running` status message with the block devices listed after mounting or unmounting
+pub struct RunningStatus(pub Vec<JsonValue>);
+
+/// Clone request arguments awaiting its pre-hook, the unmount of the source or a clean
+/// `fsck_first` check of it
+struct PendingClone {
+    id: Uuid,
+    start: DateTime<Utc>,
+    destination: String,
+    unmount_first: bool,
+    fsck_first: bool,
+    name: String,
+    compression: Compression,
+    retention: Option<RetentionPolicy>,
+    progress_interval: Option<Duration>,
+    hooks: Hooks,
+    /// checked against the destination free space before starting
+    estimated_image_size: Option<u64>,
+}
+
+impl PendingClone {
+    /// Returns the request parameters to record in the history, besides source & destination
+    fn parameters(&self) -> BTreeMap<String, String> {
+        let mut parameters = history::parameters([
+            ("name", &self.name),
+            ("compression", self.compression.name),
+            ("fsck_first", &self.fsck_first.to_string()),
+            ("unmount_first", &self.unmount_first.to_string()),
+        ]);
+        if let Some(retention) = &self.retention {
+            let keep = [
+                ("keep_last", retention.keep_last),
+                ("keep_daily", retention.keep_daily),
+                ("keep_weekly", retention.keep_weekly),
+            ];
+            for (key, n) in keep {
+                if let Some(n) = n {
+                    parameters.insert(key.to_owned(), n.to_string());
+                }
+            }
+        }
+        parameters
+    }
+
+    fn summary(&self, source: &str, phase: &'static str) -> JobSummary {
+        pending_summary(
+            self.id.to_string(),
+            "clone",
+            source,
+            &self.destination,
+            self.start,
+            phase,
+        )
+    }
+}
+
+type CreateRestore = Box<dyn FnOnce(String) -> Result<RestoreJob, Box<dyn Error>>>;
+
+/// Restore job arguments awaiting its pre-hook or the unmount of the destination
+struct PendingRestore {
+    /// "restore" or "export"
+    kind: &'static str,
+    id: String,
+    start: DateTime<Utc>,
+    source: String,
+    destination: String,
+    unmount_first: bool,
+    /// creates the job with its id
+    create: CreateRestore,
+    progress_interval: Option<Duration>,
+    hooks: Hooks,
+    /// request parameters to record in the history, besides source & destination
+    parameters: BTreeMap<String, String>,
+}
+
+impl PendingRestore {
+    fn job_env(&self) -> JobEnv {
+        JobEnv::new(self.kind, &self.source, &self.destination)
+    }
+}
+
+/// Returns the restore request parameters to record in the history, the actual new uuid of a
+/// finished restore replacing the requested one
+fn restore_parameters(
+    unmount_first: bool,
+    expand_filesystem: bool,
+    new_uuid: Option<NewUuid>,
+) -> BTreeMap<String, String> {
+    let mut parameters = history::parameters([
+        ("unmount_first", &unmount_first.to_string()),
+        ("expand_filesystem", &expand_filesystem.to_string()),
+    ]);
+    if let Some(new_uuid) = new_uuid {
+        let value = match new_uuid {
+            NewUuid::Random => "random".to_owned(),
+            NewUuid::Given(uuid) => uuid.to_string(),
+        };
+        parameters.insert("new_uuid".to_owned(), value);
+    }
+    parameters
+}
+
+/// A clone of a source, or a restore, to start once its pre-hook succeeds & it's unmounted
+enum PendingJob {
+    Clone(String, PendingClone),
+    Restore(PendingRestore),
+}
+
+impl PendingJob {
+    fn kind(&self) -> &'static str {
+        match self {
+            PendingJob::Clone(..) => "clone",
+            PendingJob::Restore(restore) => restore.kind,
+        }
+    }
+
+    fn id(&self) -> String {
+        match self {
+            PendingJob::Clone(_, clone) => clone.id.to_string(),
+            PendingJob::Restore(restore) => restore.id.clone(),
+        }
+    }
+
+    fn hooks(&self) -> &Hooks {
+        match self {
+            PendingJob::Clone(_, clone) => &clone.hooks,
+            PendingJob::Restore(restore) => &restore.hooks,
+        }
+    }
+
+    fn job_env(&self) -> JobEnv {
+        match self {
+            PendingJob::Clone(source, clone) => JobEnv::new("clone", source, &clone.destination),
+            PendingJob::Restore(restore) => restore.job_env(),
+        }
+    }
+
+    /// Returns the device to unmount first, if requested
+    fn unmount_device(&self) -> Option<String> {
+        match self {
+            PendingJob::Clone(source, clone) if clone.unmount_first => Some(source.clone()),
+            PendingJob::Restore(restore) if restore.unmount_first => {
+                Some(restore.destination.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn summary(&self, phase: &'static str) -> JobSummary {
+        match self {
+            PendingJob::Clone(source, clone) => clone.summary(source, phase),
+            PendingJob::Restore(restore) => pending_summary(
+                restore.id.clone(),
+                restore.kind,
+                &restore.source,
+                &restore.destination,
+                restore.start,
+                phase,
+            ),
+        }
+    }
+}
+
+fn pending_summary(
+    id: String,
+    kind: &'static str,
+    source: &str,
+    destination: &str,
+    start: DateTime<Utc>,
+    phase: &'static str,
+) -> JobSummary {
+    JobSummary {
+        id,
+        kind,
+        source: source.to_owned(),
+        destination: destination.to_owned(),
+        start,
+        complete: 0.0,
+        rate: None,
+        estimated_finish: None,
+        phase,
+    }
+}
+
+/// A pending job with the receiver of its concurrent preparation, ie a pre-hook or unmounting
+struct Preparing<T = ()> {
+    job: PendingJob,
+    done: Receiver<Result<T, String>>,
+    /// set to kill a pre-hook
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+/// Takes the pending jobs whose preparation is done with its result, leaving the others
+fn take_prepared<T>(
+    preparing: &mut Vec<Preparing<T>>,
+    stage: &str,
+) -> Vec<(PendingJob, Result<T, String>)> {
+    let mut prepared = Vec::new();
+    for prep in mem::take(preparing) {
+        match prep.done.try_recv() {
+            Err(TryRecvError::Empty) => preparing.push(prep),
+            Ok(result) => prepared.push((prep.job, result)),
+            Err(TryRecvError::Disconnected) => {
+                prepared.push((prep.job, Err(format!("{stage} failed"))))
+            }
+        }
+    }
+    prepared
+}
+
 pub struct Server {
+    /// socket of the client, or the latest client to reattach
     socket: zmq::Socket,
-    clones: HashMap<String, CloneJob>,
-    restores: HashMap<String, RestoreJob>,
+    /// socket bound at `APART_REATTACH_ADDRESS` for a new client to take over
+    reattach_socket: Option<zmq::Socket>,
+    clones: HashMap<String, (CloneJob, ProgressThrottle)>,
+    restores: HashMap<String, (RestoreJob, ProgressThrottle)>,
+    fscks: HashMap<String, (FsckJob, Option<PendingClone>)>,
     io_receiver: Receiver<Box<dyn ToYaml + Send>>,
     io_master_sender: Sender<Box<dyn ToYaml + Send>>,
+    device_watcher: DeviceWatcher,
+    outbox: RefCell<Outbox>,
+    /// interval between progress messages of jobs not requesting their own
+    progress_interval: Option<Duration>,
+    history: HistoryLog,
+    /// hooks of jobs not requesting their own
+    default_hooks: Hooks,
+    pre_hooks: Vec<Preparing>,
+    /// pending jobs unmounting their device & clones checking the destination has space for the
+    /// estimated image, received with the estimate
+    devices: Vec<Preparing<Option<u64>>>,
+    /// post-hook commands of running jobs by id
+    post_hooks: HashMap<String, String>,
+    /// request parameters of running jobs by id, added to their history entries
+    job_parameters: HashMap<String, BTreeMap<String, String>>,
 }
 
 impl Drop for Server {
     fn drop(&mut self) {
-        if self.zmq_send(&status_yaml("dying", Vec::new())).is_err() {
-            warn!("Failed to send dying status message");
+        let mut ended = Vec::new();
+        for (_, (job, _)) in self.clones.drain() {
+            ended.push(HistoryEntry::of_clone(&job.fail_status("Shutdown")));
+        }
+        for (_, (job, _)) in self.restores.drain() {
+            ended.push(HistoryEntry::of_restore(&job.fail_status("Shutdown")));
+        }
+        // jobs are cancelled, run their post-hooks before dying
+        let mut post_hooks = Vec::new();
+        for entry in ended.into_iter().flatten() {
+            let entry = self.with_job_parameters(entry);
+            if let Some(cmd) = entry.id.as_ref().and_then(|id| self.post_hooks.get(id)) {
+                post_hooks.push((cmd.clone(), JobEnv::of_entry(&entry)));
+            }
+            self.history.record(Some(entry));
+        }
+        hooks::post_all(post_hooks, SHUTDOWN_POST_HOOK_WAIT);
+        self.zmq_send(&status_yaml("dying", Vec::new()));
+        if let Err(err) = self.outbox.get_mut().flush(&self.socket) {
+            warn!("Failed to send dying status message: {}", err);
         }
     }
 }
@@ -39,95 +324,582 @@ impl Drop for Server {
 impl Server {
     /// Start up server using an input ipc address for communication with the client
     pub fn start_at(ipc_address: &str) -> Result<(), Box<dyn Error>> {
-        let socket = zmq::Context::new().socket(zmq::PAIR)?;
+        let ctx = zmq::Context::new();
+        let socket = ctx.socket(zmq::PAIR)?;
+        socket.set_sndhwm(outbox::SEND_HIGH_WATER_MARK)?; // before connecting to take effect
         socket.connect(ipc_address)?;
-        socket.set_sndtimeo(1000)?; // block 1s on sends or error
         socket.set_rcvtimeo(0)?; // non-blocking recv (EAGAIN when nothing)
         socket.set_linger(0)?; // don't tolerate disconnection
 
+        let reattach_socket = match env::var("APART_REATTACH_ADDRESS") {
+            Ok(address) => {
+                let reattach_socket = ctx.socket(zmq::PAIR)?;
+                reattach_socket.set_sndhwm(outbox::SEND_HIGH_WATER_MARK)?;
+                reattach_socket.bind(&address)?;
+                reattach_socket.set_linger(0)?;
+                info!("Clients may reattach at {}", address);
+                Some(reattach_socket)
+            }
+            Err(_) => None,
+        };
+
         let (io_master_sender, io_receiver) = channel();
         let mut server = Server {
             socket,
+            reattach_socket,
             clones: HashMap::new(),
             restores: HashMap::new(),
+            fscks: HashMap::new(),
             io_receiver,
             io_master_sender,
+            device_watcher: DeviceWatcher::new(),
+            outbox: RefCell::new(Outbox::new(outbox::disconnect_timeout())),
+            progress_interval: None,
+            history: HistoryLog::from_env(),
+            default_hooks: Hooks::from_env(),
+            pre_hooks: Vec::new(),
+            devices: Vec::new(),
+            post_hooks: HashMap::new(),
+            job_parameters: HashMap::new(),
         };
-        server.zmq_send(&status_yaml("started", lsblk::blockdevices()?))?;
+        server.zmq_send(&status_yaml("started", lsblk::blockdevices()?));
         server.run()
     }
 
-    fn zmq_send(&self, msg: &str) -> Result<(), Box<dyn Error>> {
-        match self.socket.send(msg, 0) {
-            Err(x) => Err(Box::new(x)),
-            Ok(_) => Ok(()),
+    /// Receives from the client, or from a reattaching client which then becomes the client
+    fn recv(&mut self) -> zmq::Result<Result<String, Vec<u8>>> {
+        let received = self.socket.recv_string(0);
+        if let Err(zmq::Error::EAGAIN) = received
+            && let Some(reattach_socket) = &mut self.reattach_socket
+        {
+            return match reattach_socket.recv_string(zmq::DONTWAIT) {
+                Ok(msg) => {
+                    info!("Client reattached");
+                    mem::swap(&mut self.socket, reattach_socket);
+                    Ok(msg)
+                }
+                Err(zmq::Error::EAGAIN) => received,
+                Err(err) => {
+                    warn!("Failed to receive from reattach socket: {}", err);
+                    received
+                }
+            };
+        }
+        received
+    }
+
+    /// Queues a message for the client, see `Outbox`
+    fn zmq_send(&self, msg: &str) {
+        self.outbox.borrow_mut().push(msg.to_owned());
+    }
+
+    /// Queues a message for the client, kept across a detach when it reports a job ending
+    fn zmq_send_message(&self, msg: &dyn ToYaml) {
+        if msg.ends_job() {
+            self.outbox.borrow_mut().push_ended(msg.to_yaml());
+        } else {
+            self.outbox.borrow_mut().push(msg.to_yaml());
+        }
+    }
+
+    /// Queues a job progress message, replacing its queued progress the client hasn't received
+    fn zmq_send_progress(&self, job_id: &str, msg: String) {
+        self.outbox.borrow_mut().push_progress(job_id, msg);
+    }
+
+    /// Delete images not retained by the policy concurrently, reporting each deletion
+    fn prune(&self, directory: String, name: String, policy: RetentionPolicy) {
+        let tx = self.io_master_sender.clone();
+        let history = self.history.clone();
+        thread::spawn(move || match retention::prune(&directory, &name, policy) {
+            Ok(results) => {
+                let start = Utc::now();
+                for result in results {
+                    history.record(Some(HistoryEntry::of_delete(&result, start, None)));
+                    if let Err(err) = tx.send(Box::new(result)) {
+                        debug!("Could not send, shutting down?: {}", err);
+                        return;
+                    }
+                }
+            }
+            Err(err) => error!("Failed to prune {} images in {}: {}", name, directory, err),
+        });
+    }
+
+    /// Prepares a pending job, running its pre-hook concurrently before continuing the job
+    fn prepare(&mut self, job: PendingJob) {
+        match job.hooks().pre.clone() {
+            Some(cmd) => {
+                let cancel = Arc::new(AtomicBool::new(false));
+                let done = hooks::pre(cmd, job.job_env(), Arc::clone(&cancel));
+                self.pre_hooks.push(Preparing {
+                    job,
+                    done,
+                    cancel: Some(cancel),
+                });
+            }
+            None => self.prepare_device(job),
+        }
+    }
+
+    /// Unmounts the device of an `unmount_first` pending job & checks a clone's destination has
+    /// space concurrently, as both can be slow, continuing the job once done
+    fn prepare_device(&mut self, job: PendingJob) {
+        let unmount = job.unmount_device();
+        let space_check = match &job {
+            PendingJob::Clone(source, clone) => {
+                Some((source.clone(), clone.destination.clone(), clone.compression))
+            }
+            PendingJob::Restore(_) => None,
+        };
+        if unmount.is_none() && space_check.is_none() {
+            return self.start_prepared(job, None);
+        }
+        let done = asynchronous::receiver(move || {
+            if let Some(device) = unmount {
+                mount::unmount_if_mounted(&device).map_err(|err| err.to_string())?;
+            }
+            match space_check {
+                Some((source, destination, z)) => {
+                    space::check(&source, &destination, z).map_err(|err| err.to_string())
+                }
+                None => Ok(None),
+            }
+        });
+        self.devices.push(Preparing {
+            job,
+            done,
+            cancel: None,
+        });
+    }
+
+    /// Starts a job prepared by its pre-hook & `prepare_device`
+    fn start_prepared(&mut self, job: PendingJob, estimated_image_size: Option<u64>) {
+        match job {
+            PendingJob::Clone(source, clone) => self.check_clone(
+                source,
+                PendingClone {
+                    estimated_image_size,
+                    ..clone
+                },
+            ),
+            PendingJob::Restore(restore) => self.create_restore(restore),
+        }
+    }
+
+    /// Reports a pending job failing to start. Its post-hook is run unless the job failed before
+    /// its pre-hook succeeded.
+    fn pending_failed(&self, job: PendingJob, reason: String, run_post_hook: bool) {
+        error!("{} aborted: {}", job.kind(), reason);
+        if run_post_hook && let Some(cmd) = job.hooks().post.clone() {
+            hooks::spawn_post(cmd, job.job_env().failed(&reason));
         }
+        self.zmq_send_message(&StartFailed(job.kind(), Some(job.id()), reason));
+    }
+
+    /// Cancels the pending job with the id, if any
+    fn cancel_pending(&mut self, id: &str) {
+        if let Some(idx) = self.pre_hooks.iter().position(|prep| prep.job.id() == id) {
+            let prep = self.pre_hooks.remove(idx);
+            if let Some(cancel) = prep.cancel {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            self.pending_failed(prep.job, "Cancelled".to_owned(), false);
+        } else if let Some(idx) = self.devices.iter().position(|prep| prep.job.id() == id) {
+            let prep = self.devices.remove(idx);
+            self.pending_failed(prep.job, "Cancelled".to_owned(), true);
+        } else if let Some(fsck_id) = self.fscks.iter().find_map(|(fsck_id, (_, clone))| {
+            clone
+                .as_ref()
+                .filter(|clone| clone.id.to_string() == id)
+                .map(|_| fsck_id.clone())
+        }) && let Some((fsck, Some(clone))) = self.fscks.remove(&fsck_id)
+        {
+            let source = fsck.device().to_owned();
+            mem::drop(fsck); // ensure the check is killed
+            self.pending_failed(
+                PendingJob::Clone(source, clone),
+                "Cancelled".to_owned(),
+                true,
+            );
+        }
+    }
+
+    /// Starts a clone once its `fsck_first` check, if any, is clean
+    fn check_clone(&mut self, source: String, clone: PendingClone) {
+        if !clone.fsck_first {
+            return self.create_clone(source, clone);
+        }
+        match FsckJob::new(source.clone(), true) {
+            Ok(job) => {
+                info!("Starting new job: {}", job);
+                self.fscks.insert(job.id(), (job, Some(clone)));
+            }
+            Err(err) => {
+                let job = PendingJob::Clone(source, clone);
+                self.pending_failed(job, format!("FsckJob creation failed: {}", err), true);
+            }
+        }
+    }
+
+    fn create_clone(&mut self, source: String, clone: PendingClone) {
+        let parameters = clone.parameters();
+        let PendingClone {
+            id,
+            destination,
+            name,
+            compression,
+            retention,
+            progress_interval,
+            hooks: Hooks { post, .. },
+            estimated_image_size,
+            ..
+        } = clone;
+        match CloneJob::new(
+            id,
+            source.clone(),
+            &destination,
+            &name,
+            compression,
+            retention,
+            estimated_image_size,
+        ) {
+            Ok(job) => {
+                info!("Starting new job: {}", job);
+                if let Some(cmd) = post {
+                    self.post_hooks.insert(job.id(), cmd);
+                }
+                self.job_parameters.insert(job.id(), parameters);
+                let throttle = ProgressThrottle::new(progress_interval);
+                self.clones.insert(job.id().to_owned(), (job, throttle));
+            }
+            Err(err) => {
+                error!("Clonejob creation failed: {}", err);
+                let reason = err.to_string();
+                if let Some(cmd) = post {
+                    let job = JobEnv::new("clone", &source, &destination).failed(&reason);
+                    hooks::spawn_post(cmd, job);
+                }
+                self.zmq_send_message(&StartFailed("clone", Some(id.to_string()), reason));
+            }
+        }
+    }
+
+    /// Returns summaries of the active & pending clone & restore jobs, oldest first
+    fn job_summaries(&self) -> Vec<JobSummary> {
+        let preparing = (self.pre_hooks.iter().map(|prep| &prep.job))
+            .chain(self.devices.iter().map(|prep| &prep.job));
+        let checking = self
+            .fscks
+            .values()
+            .filter_map(|(fsck, clone)| Some(clone.as_ref()?.summary(fsck.device(), "checking")));
+        let mut summaries: Vec<_> = (self.clones.values().map(|(job, _)| job.summary()))
+            .chain(self.restores.values().map(|(job, _)| job.summary()))
+            .chain(preparing.map(|job| job.summary("preparing")))
+            .chain(checking)
+            .collect();
+        summaries.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.id.cmp(&b.id)));
+        summaries
+    }
+
+    fn create_restore(&mut self, restore: PendingRestore) {
+        let job_env = restore.job_env();
+        let PendingRestore {
+            kind,
+            id,
+            create,
+            progress_interval,
+            hooks: Hooks { post, .. },
+            parameters,
+            ..
+        } = restore;
+        match create(id.clone()) {
+            Ok(job) => {
+                info!("Starting new job: {}", job);
+                if let Some(cmd) = post {
+                    self.post_hooks.insert(job.id().to_owned(), cmd);
+                }
+                self.job_parameters.insert(job.id().to_owned(), parameters);
+                let throttle = ProgressThrottle::new(progress_interval);
+                self.restores.insert(job.id().to_owned(), (job, throttle));
+            }
+            Err(err) => {
+                error!("RestoreJob creation failed: {}", err);
+                let reason = err.to_string();
+                if let Some(cmd) = post {
+                    hooks::spawn_post(cmd, job_env.failed(&reason));
+                }
+                self.zmq_send_message(&StartFailed(kind, Some(id), reason));
+            }
+        }
+    }
+
+    /// Returns the entry with the request parameters of its job
+    fn with_job_parameters(&self, entry: HistoryEntry) -> HistoryEntry {
+        match entry.id.as_ref().and_then(|id| self.job_parameters.get(id)) {
+            Some(parameters) => entry.with_parameters(parameters),
+            None => entry,
+        }
+    }
+
+    /// Records a finished or failed job in the history & runs its post-hook concurrently
+    fn job_ended(&self, entry: Option<HistoryEntry>) {
+        let entry = entry.map(|entry| self.with_job_parameters(entry));
+        if let Some(entry) = &entry
+            && let Some(cmd) = entry.id.as_ref().and_then(|id| self.post_hooks.get(id))
+        {
+            hooks::spawn_post(cmd.clone(), JobEnv::of_entry(entry));
+        }
+        self.history.record(entry);
+    }
+
+    /// Mount or unmount a device concurrently, reporting the result & the new device status
+    fn mount(&self, device: PartitionRef, mount: bool) {
+        let tx = self.io_master_sender.clone();
+        thread::spawn(move || {
+            let result = match device.resolve() {
+                Ok(device) if mount => match mount::mount_read_only(&device) {
+                    Ok(mountpoint) => MountResult::Mounted { device, mountpoint },
+                    Err(err) => MountResult::MountFailed {
+                        device,
+                        reason: err.to_string(),
+                    },
+                },
+                Ok(device) => match mount::unmount(&device) {
+                    Ok(_) => MountResult::Unmounted { device },
+                    Err(err) => MountResult::UnmountFailed {
+                        device,
+                        reason: err.to_string(),
+                    },
+                },
+                Err(err) => {
+                    let device = device.to_string();
+                    let reason = err.to_string();
+                    match mount {
+                        true => MountResult::MountFailed { device, reason },
+                        false => MountResult::UnmountFailed { device, reason },
+                    }
+                }
+            };
+            info!("{:?}", result);
+            let mounted = matches!(
+                result,
+                MountResult::Mounted { .. } | MountResult::Unmounted { .. }
+            );
+            if let Err(err) = tx.send(Box::new(result)) {
+                debug!("Could not send, shutting down?: {}", err);
+            } else if mounted {
+                let devices = lsblk::blockdevices().unwrap_or_else(|err| {
+                    error!("Failed to list block devices: {}", err);
+                    Vec::new()
+                });
+                if let Err(err) = tx.send(Box::new(RunningStatus(devices))) {
+                    debug!("Could not send, shutting down?: {}", err);
+                }
+            }
+        });
     }
 
     /// Start the event loop & run until a reason to stop
     fn run(&mut self) -> Result<(), Box<dyn Error>> {
         loop {
-            let mut did_work = match self.socket.recv_string(0) {
+            let mut did_work = match self.recv() {
                 Ok(Ok(msg)) => {
                     match Request::parse(&msg) {
                         Some(Status) => {
-                            self.zmq_send(&status_yaml("running", lsblk::blockdevices()?))?
+                            self.zmq_send(&status_yaml("running", lsblk::blockdevices()?))
                         }
                         Some(Kill) => {
                             info!("KillRequest received dying...");
                             return Ok(());
                         }
+                        Some(ProgressInterval { interval }) => self.progress_interval = interval,
+                        Some(History { filter }) => {
+                            let tx = self.io_master_sender.clone();
+                            let history = self.history.clone();
+                            thread::spawn(move || {
+                                let result = HistoryResult(history.query(&filter));
+                                if let Err(err) = tx.send(Box::new(result)) {
+                                    debug!("Could not send, shutting down?: {}", err);
+                                }
+                            });
+                        }
+                        Some(Jobs) => self.zmq_send(&JobList(self.job_summaries()).to_yaml()),
+                        Some(Job { id }) => {
+                            let summary = self
+                                .job_summaries()
+                                .into_iter()
+                                .find(|summary| summary.id == id);
+                            self.zmq_send(&JobResult(id, summary).to_yaml());
+                        }
                         Some(Clone {
                             source,
                             destination,
                             name,
                             compression,
-                        }) => match CloneJob::new(source, &destination, &name, compression) {
-                            Ok(job) => {
-                                info!("Starting new job: {}", job);
-                                self.clones.insert(job.id().to_owned(), job);
+                            retention,
+                            unmount_first,
+                            fsck_first,
+                            progress_interval,
+                            hooks,
+                        }) => {
+                            let clone = PendingClone {
+                                id: Uuid::new_v4(),
+                                start: Utc::now(),
+                                destination,
+                                unmount_first,
+                                fsck_first,
+                                name,
+                                compression,
+                                retention,
+                                progress_interval,
+                                hooks: hooks.or(&self.default_hooks),
+                                estimated_image_size: None,
+                            };
+                            match source.resolve() {
+                                Ok(source) => self.prepare(PendingJob::Clone(source, clone)),
+                                Err(err) => {
+                                    error!("Clonejob creation failed: {}", err);
+                                    self.zmq_send_message(&StartFailed(
+                                        "clone",
+                                        None,
+                                        err.to_string(),
+                                    ));
+                                }
                             }
-                            Err(err) => error!("Clonejob creation failed: {}", err),
-                        },
+                        }
                         Some(Restore {
                             source,
                             destination,
-                        }) => match RestoreJob::new(source, destination) {
-                            Ok(job) => {
-                                info!("Starting new job: {}", job);
-                                self.restores.insert(job.id().to_owned(), job);
+                            unmount_first,
+                            expand_filesystem,
+                            new_uuid,
+                            progress_interval,
+                            hooks,
+                        }) => match destination.resolve() {
+                            Ok(destination) => {
+                                let restore = PendingRestore {
+                                    kind: "restore",
+                                    id: Uuid::new_v4().to_string(),
+                                    start: Utc::now(),
+                                    source: source.clone(),
+                                    destination: destination.clone(),
+                                    unmount_first,
+                                    create: Box::new(move |id| {
+                                        RestoreJob::new(
+                                            id,
+                                            source,
+                                            destination,
+                                            expand_filesystem,
+                                            new_uuid,
+                                        )
+                                    }),
+                                    progress_interval,
+                                    hooks: hooks.or(&self.default_hooks),
+                                    parameters: restore_parameters(
+                                        unmount_first,
+                                        expand_filesystem,
+                                        new_uuid,
+                                    ),
+                                };
+                                self.prepare(PendingJob::Restore(restore));
+                            }
+                            Err(err) => {
+                                error!("RestoreJob creation failed: {}", err);
+                                self.zmq_send_message(&StartFailed(
+                                    "restore",
+                                    None,
+                                    err.to_string(),
+                                ));
                             }
-                            Err(err) => error!("RestoreJob creation failed: {}", err),
                         },
+                        Some(RestoreToFile {
+                            source,
+                            destination,
+                            progress_interval,
+                            hooks,
+                        }) => self.prepare(PendingJob::Restore(PendingRestore {
+                            kind: "restore",
+                            id: Uuid::new_v4().to_string(),
+                            start: Utc::now(),
+                            source: source.clone(),
+                            destination: destination.clone(),
+                            unmount_first: false,
+                            create: Box::new(move |id| {
+                                RestoreJob::new_to_file(id, source, destination)
+                            }),
+                            progress_interval,
+                            hooks: hooks.or(&self.default_hooks),
+                            parameters: BTreeMap::new(),
+                        })),
+                        Some(ExportRaw {
+                            source,
+                            export,
+                            progress_interval,
+                            hooks,
+                        }) => self.prepare(PendingJob::Restore(PendingRestore {
+                            kind: "export",
+                            id: Uuid::new_v4().to_string(),
+                            start: Utc::now(),
+                            source: source.clone(),
+                            destination: export.destination.clone(),
+                            unmount_first: false,
+                            create: Box::new(move |id| RestoreJob::new_export(id, source, export)),
+                            progress_interval,
+                            hooks: hooks.or(&self.default_hooks),
+                            parameters: BTreeMap::new(),
+                        })),
                         Some(CancelClone { id }) => {
-                            if let Some(job) = self.clones.remove(&id) {
+                            if let Some((job, _)) = self.clones.remove(&id) {
                                 // cancel clone concurrently as removing .inprogress image can be
                                 // slow
                                 let tx = self.io_master_sender.clone();
+                                let history = self.history.clone();
+                                let post_hook = self.post_hooks.remove(&id);
+                                let parameters = self.job_parameters.remove(&id);
                                 thread::spawn(move || {
                                     let cancelled_msg = job.fail_status("Cancelled");
                                     mem::drop(job); // ensure actually cancelled before messaging
+                                    let entry =
+                                        HistoryEntry::of_clone(&cancelled_msg).map(|entry| {
+                                            entry.with_parameters(&parameters.unwrap_or_default())
+                                        });
+                                    history.record(entry.clone());
                                     if let Err(err) = tx.send(Box::new(cancelled_msg)) {
                                         debug!("Could not send, shutting down?: {}", err);
                                     }
+                                    if let (Some(cmd), Some(entry)) = (post_hook, entry) {
+                                        hooks::post(&cmd, &JobEnv::of_entry(&entry));
+                                    }
                                 });
+                            } else {
+                                self.cancel_pending(&id);
                             }
                         }
                         Some(CancelRestore { id }) => {
-                            if let Some(job) = self.restores.remove(&id) {
-                                let cancelled_msg = job.fail_status("Cancelled").to_yaml();
+                            if let Some((job, _)) = self.restores.remove(&id) {
+                                let cancelled_msg = job.fail_status("Cancelled");
                                 mem::drop(job); // ensure actually cancelled before messaging
-                                self.zmq_send(&cancelled_msg)?;
+                                self.job_ended(HistoryEntry::of_restore(&cancelled_msg));
+                                self.post_hooks.remove(&id);
+                                self.job_parameters.remove(&id);
+                                self.zmq_send_message(&cancelled_msg);
+                            } else {
+                                self.cancel_pending(&id);
                             }
                         }
                         Some(DeleteImage { file }) => {
                             if clone::is_valid_image_name(&file) {
                                 let tx = self.io_master_sender.clone();
+                                let history = self.history.clone();
                                 thread::spawn(move || {
-                                    let rm_result = fs::remove_file(&file);
-                                    if let Err(err) =
-                                        tx.send(Box::new(DeleteResult(file, rm_result)))
-                                    {
+                                    let start = Utc::now();
+                                    let image_size = fs::metadata(&file).ok().map(|m| m.len());
+                                    let result = DeleteResult(file.clone(), fs::remove_file(&file));
+                                    history.record(Some(HistoryEntry::of_delete(
+                                        &result, start, image_size,
+                                    )));
+                                    if let Err(err) = tx.send(Box::new(result)) {
                                         debug!("Could not send, shutting down?: {}", err);
                                     }
                                 });
@@ -135,6 +907,65 @@ impl Server {
                                 warn!("Invalid image file for deletion: {}", file);
                             }
                         }
+                        Some(CloneEstimate { source }) => {
+                            let tx = self.io_master_sender.clone();
+                            thread::spawn(move || {
+                                let result = source.resolve().and_then(estimate::estimate);
+                                let msg = CloneEstimateResult(source.to_string(), result);
+                                if let Err(err) = tx.send(Box::new(msg)) {
+                                    debug!("Could not send, shutting down?: {}", err);
+                                }
+                            });
+                        }
+                        Some(ImageInfo { file }) => {
+                            let tx = self.io_master_sender.clone();
+                            thread::spawn(move || {
+                                let header = image::partclone_header(&file);
+                                if let Err(err) = tx.send(Box::new(image::ImageInfo(file, header)))
+                                {
+                                    debug!("Could not send, shutting down?: {}", err);
+                                }
+                            });
+                        }
+                        Some(ImportImage {
+                            source,
+                            destination,
+                            name,
+                        }) => {
+                            let tx = self.io_master_sender.clone();
+                            thread::spawn(move || {
+                                let result = import::import(&source, &destination, name.as_deref());
+                                if let Err(err) = tx.send(Box::new(ImportResult(source, result))) {
+                                    debug!("Could not send, shutting down?: {}", err);
+                                }
+                            });
+                        }
+                        Some(Prune {
+                            directory,
+                            name,
+                            retention,
+                        }) => self.prune(directory, name, retention),
+                        Some(Mount { device }) => self.mount(device, true),
+                        Some(Unmount { device }) => self.mount(device, false),
+                        Some(Fsck { device }) => {
+                            match device
+                                .resolve()
+                                .and_then(|device| FsckJob::new(device, false))
+                            {
+                                Ok(job) => {
+                                    info!("Starting new job: {}", job);
+                                    self.fscks.insert(job.id(), (job, None));
+                                }
+                                Err(err) => {
+                                    error!("FsckJob creation failed: {}", err);
+                                    self.zmq_send_message(&StartFailed(
+                                        "fsck",
+                                        None,
+                                        err.to_string(),
+                                    ));
+                                }
+                            }
+                        }
                         _ => warn!("Unhandled inbound message:\n{}", msg),
                     };
                     true
@@ -155,42 +986,152 @@ impl Server {
             };
 
             let mut finished_job_ids = Vec::new();
-            for (id, job) in &self.clones {
+            for (id, (job, throttle)) in &self.clones {
                 if let Ok(status) = job.try_recv() {
-                    self.zmq_send(&status.to_yaml())?;
+                    match status {
+                        CloneStatus::Running { .. } => {
+                            if let Some(msg) =
+                                throttle.progress(status.to_yaml(), self.progress_interval)
+                            {
+                                self.zmq_send_progress(id, msg);
+                            }
+                        }
+                        _ => {
+                            // held progress would go backwards after syncing
+                            throttle.clear();
+                            self.zmq_send_message(&status);
+                        }
+                    }
                     match status {
                         CloneStatus::Running { .. } | CloneStatus::Syncing { .. } => (),
-                        _ => finished_job_ids.push(id.to_owned()),
+                        CloneStatus::Finished { .. } => {
+                            self.job_ended(HistoryEntry::of_clone(&status));
+                            if let Some((directory, name, policy)) = job.retention() {
+                                self.prune(directory.to_owned(), name.to_owned(), policy);
+                            }
+                            finished_job_ids.push(id.to_owned());
+                        }
+                        _ => {
+                            self.job_ended(HistoryEntry::of_clone(&status));
+                            finished_job_ids.push(id.to_owned());
+                        }
                     }
                     did_work = true;
+                } else if let Some(msg) = throttle.due(self.progress_interval) {
+                    self.zmq_send_progress(id, msg);
+                    did_work = true;
                 }
             }
             for id in &finished_job_ids {
                 // allow CloneJob Drop to cleanup resources
                 self.clones.remove(id);
+                self.post_hooks.remove(id);
+                self.job_parameters.remove(id);
             }
 
             let mut finished_job_ids = Vec::new();
-            for (id, job) in &self.restores {
+            for (id, (job, throttle)) in &self.restores {
                 if let Ok(status) = job.try_recv() {
-                    self.zmq_send(&status.to_yaml())?;
                     match status {
-                        RestoreStatus::Running { .. } => (),
-                        _ => finished_job_ids.push(id.to_owned()),
+                        RestoreStatus::Running { syncing: false, .. } => {
+                            if let Some(msg) =
+                                throttle.progress(status.to_yaml(), self.progress_interval)
+                            {
+                                self.zmq_send_progress(id, msg);
+                            }
+                        }
+                        RestoreStatus::Running { .. } | RestoreStatus::Finalizing { .. } => {
+                            // held progress would go backwards after syncing
+                            throttle.clear();
+                            self.zmq_send(&status.to_yaml());
+                        }
+                        _ => {
+                            throttle.clear();
+                            self.zmq_send_message(&status);
+                            self.job_ended(HistoryEntry::of_restore(&status));
+                            finished_job_ids.push(id.to_owned());
+                        }
                     }
                     did_work = true;
+                } else if let Some(msg) = throttle.due(self.progress_interval) {
+                    self.zmq_send_progress(id, msg);
+                    did_work = true;
                 }
             }
             for id in &finished_job_ids {
                 // allow RestoreJob Drop to cleanup resources
                 self.restores.remove(id);
+                self.post_hooks.remove(id);
+                self.job_parameters.remove(id);
+            }
+
+            let mut finished_job_ids = Vec::new();
+            for (id, (job, _)) in &self.fscks {
+                if let Ok(status) = job.try_recv() {
+                    self.zmq_send_message(&status);
+                    if let FsckStatus::Finished {
+                        ref common,
+                        finish,
+                        clean,
+                        exit_code,
+                    } = status
+                    {
+                        let reason = (!clean).then(|| job.failure_reason(exit_code));
+                        self.history
+                            .record(Some(HistoryEntry::of_fsck(common, finish, reason)));
+                        finished_job_ids.push((id.to_owned(), clean, exit_code));
+                    }
+                    did_work = true;
+                }
+            }
+            for (id, clean, exit_code) in finished_job_ids {
+                if let Some((job, Some(clone))) = self.fscks.remove(&id) {
+                    let source = job.device().to_owned();
+                    if clean {
+                        self.create_clone(source, clone);
+                    } else {
+                        let reason = job.failure_reason(exit_code);
+                        self.pending_failed(PendingJob::Clone(source, clone), reason, true);
+                    }
+                }
+            }
+
+            for (job, result) in take_prepared(&mut self.pre_hooks, "pre-hook") {
+                match result {
+                    Ok(()) => self.prepare_device(job),
+                    Err(reason) => self.pending_failed(job, reason, false),
+                }
+                did_work = true;
+            }
+
+            for (job, result) in take_prepared(&mut self.devices, "device preparation") {
+                match result {
+                    Ok(estimated_image_size) => self.start_prepared(job, estimated_image_size),
+                    Err(reason) => self.pending_failed(job, reason, true),
+                }
+                did_work = true;
             }
 
             if let Ok(result) = self.io_receiver.try_recv() {
-                self.zmq_send(&result.to_yaml())?;
+                self.zmq_send_message(result.as_ref());
                 did_work = true
             }
 
+            if let Some(changes) = self.device_watcher.poll() {
+                info!("Block devices changed: {:?}", changes);
+                self.zmq_send(&changes.to_yaml());
+                did_work = true
+            }
+
+            match self.outbox.get_mut().flush(&self.socket) {
+                Ok(sent) => did_work |= sent > 0,
+                Err(err) if self.reattach_socket.is_some() => {
+                    warn!("{}, detaching until a client reattaches", err);
+                    self.outbox.get_mut().detach();
+                }
+                Err(err) => return Err(err),
+            }
+
             if did_work {
                 self.socket.set_rcvtimeo(0)?;
             } else {
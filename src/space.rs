@@ -0,0 +1,61 @@
+use crate::{child, compression::Compression, estimate, include::*, lsblk};
+use std::{
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    process::{Command, Stdio},
+    str,
+};
+
+pub const OUT_OF_SPACE: &str = "Destination out of space";
+
+pub fn out_of_space() -> IoError {
+    IoError::new(ErrorKind::StorageFull, OUT_OF_SPACE)
+}
+
+/// Returns the bytes available to write on the file system of `path`
+pub fn available(path: &str) -> IoResult<u64> {
+    let output = Command::new(child::cmd("df"))
+        .args(["--output=avail", "-B1", path])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(IoError::other(format!("df failed: {}", output.status)));
+    }
+    // "Avail\n123456789\n"
+    str::from_utf8(&output.stdout)
+        .ok()
+        .and_then(|out| out.lines().nth(1))
+        .and_then(|avail| avail.trim().parse().ok())
+        .ok_or_else(|| IoError::other("Unexpected df output"))
+}
+
+/// Checks the destination has space for the estimated image of a clone, returning the estimate
+/// when the source partition is known. Used bytes of unmounted file systems are read with
+/// partclone, falling back to the partition size.
+pub fn check(source: &str, destination: &str, z: Compression) -> IoResult<Option<u64>> {
+    let Some(part) = lsblk::partition_matching(source) else {
+        return Ok(None);
+    };
+    let (used, exact) = estimate::used_reading_fs(&part, source);
+    if !exact {
+        info!(
+            "Estimating {} image of {} from its partition size",
+            z.name, source
+        );
+    }
+    let needed = estimate::image_size(used, z);
+    match available(destination) {
+        Ok(available) if available < needed => {
+            warn!(
+                "Estimated {} image of {} needs {} bytes, {} has {} available",
+                z.name, source, needed, destination, available
+            );
+            Err(out_of_space())
+        }
+        Ok(_) => Ok(Some(needed)),
+        Err(err) => {
+            warn!("Could not check free space of {}: {}", destination, err);
+            Ok(Some(needed))
+        }
+    }
+}
@@ -3,7 +3,7 @@
 mod coreutil;
 
 use crate::coreutil::CoreHandle;
-use std::time::Duration;
+use std::{fs, time::Duration};
 use wait_timeout::ChildExt;
 
 macro_rules! assert_partition {
@@ -28,6 +28,12 @@ fn initial_status_message() {
     let sda = &core.initial_message["sources"][0];
     assert_eq!(sda["name"].as_str(), Some("sda"));
     assert_eq!(sda["size"].as_i64(), Some(750_156_374_016));
+    assert_eq!(sda["model"].as_str(), Some("Samsung SSD 850"));
+    assert_eq!(sda["serial"].as_str(), Some("S21PNXAG123456"));
+    assert_eq!(sda["transport"].as_str(), Some("sata"));
+    assert_eq!(sda["rotational"].as_bool(), Some(false));
+    assert_eq!(sda["removable"].as_bool(), Some(false));
+    assert_eq!(sda["read_only"].as_bool(), Some(false));
 
     assert_partition!(
         &sda["parts"][0],
@@ -62,6 +68,15 @@ fn initial_status_message() {
             uuid: Some("345-345-345"),
         }
     );
+    assert_eq!(sda["parts"][2]["partuuid"].as_str(), Some("0b4fd5c9-03"));
+    assert_eq!(sda["parts"][2]["partlabel"].as_str(), Some("root"));
+    assert_eq!(
+        sda["parts"][2]["parttype"].as_str(),
+        Some("0fc63daf-8483-4772-8e79-3d69d8477de4")
+    );
+    assert_eq!(sda["parts"][2]["read_only"].as_bool(), Some(false));
+    assert_eq!(sda["parts"][2]["fsused"].as_i64(), Some(45_062_541_312));
+    assert_eq!(sda["parts"][2]["fsavail"].as_i64(), Some(126_716_387_328));
     assert_partition!(
         &sda["parts"][3],
         PartitionExpectation {
@@ -88,6 +103,10 @@ fn initial_status_message() {
     let sdb = &core.initial_message["sources"][1];
     assert_eq!(sdb["name"].as_str(), Some("sdb"));
     assert_eq!(sdb["size"].as_i64(), Some(62_109_253_632));
+    assert_eq!(sdb["transport"].as_str(), Some("usb"));
+    assert_eq!(sdb["rotational"].as_bool(), Some(true));
+    assert_eq!(sdb["removable"].as_bool(), Some(true));
+    assert_eq!(sdb["read_only"].as_bool(), Some(false));
 
     assert_partition!(
         &sdb["parts"][0],
@@ -123,8 +142,38 @@ fn initial_status_message() {
         }
     );
 
+    let sdc = &core.initial_message["sources"][2];
+    assert_eq!(sdc["name"].as_str(), Some("sdc"));
+    assert_eq!(sdc["type"].as_str(), Some("disk"));
+    let luks = &sdc["parts"][0]["children"][0];
+    assert_eq!(luks["type"].as_str(), Some("crypt"));
+    assert_eq!(luks["path"].as_str(), Some("/dev/mapper/luks-789"));
+    let lvm = &luks["children"][0];
+    assert_eq!(lvm["type"].as_str(), Some("lvm"));
+    assert_eq!(lvm["path"].as_str(), Some("/dev/mapper/vg-home"));
+    assert_partition!(
+        lvm,
+        PartitionExpectation {
+            name: "vg-home",
+            size: 62_087_233_024,
+            fstype: Some("f2fs"),
+            label: Some("home"),
+            mounted: false,
+            uuid: Some("901-901-901"),
+        }
+    );
+
+    let loop0 = &core.initial_message["sources"][3];
+    assert_eq!(loop0["type"].as_str(), Some("loop"));
+    assert_eq!(loop0["parts"][0]["path"].as_str(), Some("/dev/loop0"));
+    assert_eq!(loop0["parts"][0]["fstype"].as_str(), Some("ext2"));
+
     let compression_options = &core.initial_message["compression_options"];
     assert_eq!(compression_options[0].as_str(), Some("gz"));
+
+    let export_formats = &core.initial_message["export_formats"];
+    assert_eq!(export_formats[0].as_str(), Some("raw"));
+    assert_eq!(export_formats[1].as_str(), Some("qcow2"));
 }
 
 #[test]
@@ -152,6 +201,45 @@ fn status_request() {
     );
 }
 
+#[test]
+fn status_request_with_older_lsblk() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of(".control.mocklsblk"), "2.32").unwrap();
+
+    core.send("type: status-request");
+    let message = core.expect_message_with(|msg| msg["type"].as_str() == Some("status"));
+    assert_eq!(message["sources"][0]["name"].as_str(), Some("sda"));
+    assert_eq!(
+        message["sources"][0]["parts"][2]["name"].as_str(),
+        Some("sda3")
+    );
+
+    let args = core
+        .get_tmp_file_contents_utf8(".latest.args.mocklsblk")
+        .unwrap();
+    assert!(!args.contains("fsused"), "{}", args);
+    assert!(!args.contains("path"), "{}", args);
+}
+
+#[test]
+fn jobs_request_without_jobs() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send("type: jobs-request");
+    let message = core.expect_message_with(|msg| msg["type"].as_str() == Some("jobs"));
+    assert_eq!(message["jobs"].as_vec().map(Vec::len), Some(0));
+}
+
+#[test]
+fn reattach_status_request() {
+    let mut core = CoreHandle::new().unwrap();
+
+    core.reattach().expect("!reattach");
+    core.send("type: status-request");
+    let message = core.expect_message_with(|msg| msg["type"].as_str() == Some("status"));
+    assert_eq!(message["status"].as_str(), Some("running"));
+}
+
 #[test]
 fn kill_request() {
     let mut core = CoreHandle::new().unwrap();
@@ -166,6 +254,111 @@ fn kill_request() {
     }
 }
 
+#[test]
+fn devices_changed() {
+    let core = CoreHandle::new().unwrap();
+
+    let partitions = core.get_tmp_file_contents_utf8("mockpartitions").unwrap();
+    core.write_tmp_file_atomically(
+        "mockpartitions",
+        &format!("{partitions}   8       32   62109253 sdc\n   8       33   62108204 sdc1\n"),
+    )
+    .unwrap();
+    let mountinfo = core.get_tmp_file_contents_utf8("mockmountinfo").unwrap();
+    core.write_tmp_file_atomically(
+        "mockmountinfo",
+        &format!("{mountinfo}36 28 8:33 / /media/my\\040usb rw - vfat /dev/sdc1 rw\n"),
+    )
+    .unwrap();
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("devices-changed"));
+    assert_eq!(msg["added"][0].as_str(), Some("sdc"));
+    assert_eq!(msg["added"][1].as_str(), Some("sdc1"));
+    assert_eq!(msg["removed"].as_vec().map(Vec::len), Some(0));
+    assert_eq!(msg["mounted"][0]["device"].as_str(), Some("/dev/sdc1"));
+    assert_eq!(
+        msg["mounted"][0]["mountpoint"].as_str(),
+        Some("/media/my usb")
+    );
+    assert_eq!(msg["unmounted"].as_vec().map(Vec::len), Some(0));
+
+    core.write_tmp_file_atomically("mockpartitions", &partitions)
+        .unwrap();
+    core.write_tmp_file_atomically("mockmountinfo", &mountinfo)
+        .unwrap();
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("devices-changed"));
+    assert_eq!(msg["removed"][0].as_str(), Some("sdc"));
+    assert_eq!(msg["removed"][1].as_str(), Some("sdc1"));
+    assert_eq!(msg["unmounted"][0]["device"].as_str(), Some("/dev/sdc1"));
+}
+
+#[test]
+fn mount_read_only() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: mount\nuuid: 456-456-456");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("mounted"));
+    assert_eq!(msg["device"].as_str(), Some("/dev/sdb1"));
+    let mountpoint = msg["mountpoint"].as_str().expect("!mountpoint");
+    assert!(mountpoint.starts_with(&format!("{}/apart-mount-", core.tmp_dir())));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockmount")
+            .expect("!mount args"),
+        format!("-o\nro\n/dev/sdb1\n{}\n", mountpoint)
+    );
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("status"));
+    assert_eq!(msg["status"].as_str(), Some("running"));
+}
+
+#[test]
+fn mount_failure() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(
+        core.path_of(".control.mockmount"),
+        "mount: /dev/sdb1: can't read superblock",
+    )
+    .unwrap();
+    core.send("type: mount\ndevice: /dev/sdb1");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("mount-failed"));
+    assert_eq!(msg["device"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("mount: /dev/sdb1: can't read superblock")
+    );
+}
+
+#[test]
+fn unmount() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: unmount\ndevice: /dev/sda3");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("unmounted"));
+    assert_eq!(msg["device"].as_str(), Some("/dev/sda3"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockumount")
+            .expect("!umount args"),
+        "/dev/sda3\n"
+    );
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("status"));
+    assert_eq!(msg["status"].as_str(), Some("running"));
+}
+
+#[test]
+fn unmount_unknown_label() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: unmount\nlabel: not-a-label");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("unmount-failed"));
+    assert_eq!(msg["device"].as_str(), Some("label not-a-label"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("No partition found with label not-a-label")
+    );
+}
+
 #[derive(Debug, Clone, Copy)]
 struct PartitionExpectation {
     name: &'static str,
@@ -4,8 +4,10 @@ use crate::coreutil::*;
 use chrono::{TimeDelta, prelude::*};
 use log::warn;
 use std::{
+    fs,
     path::Path,
     process::{Command, Stdio},
+    thread,
     time::{Duration, Instant},
 };
 
@@ -84,6 +86,11 @@ fn do_clone_job() {
 
     assert_eq!(msg["id"].as_str(), id);
     assert_eq!(msg["rate"].as_str(), Some("0.01GB/min"));
+    assert_eq!(msg["bytes_per_second"].as_i64(), Some(166_667));
+    assert_eq!(msg["bytes_done"].as_i64(), Some(3_878_912 * 512));
+    assert_eq!(msg["bytes_total"].as_i64(), Some(62_914_560 * 512));
+    assert_eq!(msg["elapsed_seconds"].as_i64(), Some(12));
+    assert!(msg["compressed_bytes"].as_i64().is_some());
 
     let estimated_finish = msg["estimated_finish"]
         .as_str()
@@ -129,6 +136,16 @@ fn do_clone_job() {
         decompress_gz(&output).expect("!decompress"),
         "mock-partition-/dev/sda5-data"
     );
+    // image bytes per byte output by partclone
+    let partclone_bytes = "mock-partition-/dev/sda5-data".len() as f64;
+    let ratio = msg["compression_ratio"]
+        .as_f64()
+        .expect("missing clone.compression_ratio");
+    assert!(
+        (ratio - output.len() as f64 / partclone_bytes).abs() < 0.0001,
+        "unexpected compression_ratio {}",
+        ratio
+    );
 
     assert!(
         core.tmp_file_contents_is_1(".latest.finished.mockpcl.dd.txt"),
@@ -426,6 +443,136 @@ fn handle_partclone_rate_output() {
     assert_eq!(msg["rate"].as_str(), Some("2.34GB/min"));
 }
 
+#[test]
+fn clone_progress_interval() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: progress-interval\nprogress_interval: 60");
+
+    let clone_msg = format!(
+        "type: clone\n\
+         source: /dev/sda5\n\
+         destination: {destination}\n\
+         name: clone_progress_interval",
+        destination = core.tmp_dir()
+    );
+    core.send(&clone_msg);
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone"));
+    assert_eq!(msg["complete"].as_f64(), Some(0.0));
+
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(0.5))
+        .expect("!set_mock_partclone");
+    thread::sleep(Duration::from_millis(100));
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+
+    // progress is held back within the interval, but not finishing
+    let msg = core.expect_message_with(|msg| {
+        assert_ne!(
+            msg["complete"].as_f64(),
+            Some(0.5),
+            "progress not coalesced"
+        );
+        msg["finish"].as_str().is_some()
+    });
+    assert_eq!(msg["type"].as_str(), Some("clone"));
+    assert_eq!(msg["complete"].as_f64(), Some(1.0));
+}
+
+#[test]
+fn reattach_to_running_clone() {
+    let mut core = CoreHandle::new().unwrap();
+
+    let clone_msg = format!(
+        "type: clone\n\
+         source: /dev/sda5\n\
+         destination: {destination}\n\
+         name: reattach_to_running_clone",
+        destination = core.tmp_dir()
+    );
+    core.send(&clone_msg);
+    let msg = core.expect_message_with(|msg| {
+        msg["type"].as_str() == Some("clone") && msg["rate"].as_str().is_some()
+    });
+    let id = msg["id"].as_str().unwrap().to_owned();
+
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(0.25))
+        .expect("!set_mock_partclone");
+    thread::sleep(Duration::from_millis(50));
+
+    // a new client takes over & asks for the state of every job
+    core.reattach().expect("!reattach");
+    core.send("type: jobs-request");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("jobs"));
+    assert_eq!(msg["jobs"].as_vec().map(Vec::len), Some(1));
+    let job = &msg["jobs"][0];
+    assert_eq!(job["id"].as_str(), Some(id.as_str()));
+    assert_eq!(job["kind"].as_str(), Some("clone"));
+    assert_eq!(job["source"].as_str(), Some("/dev/sda5"));
+    assert_eq!(job["phase"].as_str(), Some("cloning"));
+    assert_eq!(job["complete"].as_f64(), Some(0.25));
+    assert!(job["start"].as_str().is_some());
+    assert!(
+        job["destination"]
+            .as_str()
+            .unwrap()
+            .starts_with(core.tmp_dir())
+    );
+
+    core.send(&format!("type: job-request\nid: {}", id));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("job"));
+    assert_eq!(msg["id"].as_str(), Some(id.as_str()));
+    assert_eq!(msg["kind"].as_str(), Some("clone"));
+    assert_eq!(msg["complete"].as_f64(), Some(0.25));
+
+    core.send("type: job-request\nid: not-a-job");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("job-failed"));
+    assert_eq!(msg["id"].as_str(), Some("not-a-job"));
+
+    // the reattached client continues to receive job progress
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+    let msg = core.expect_message_with(|msg| msg["finish"].as_str().is_some());
+    assert_eq!(msg["id"].as_str(), Some(id.as_str()));
+}
+
+#[test]
+fn clone_finishes_while_detached() {
+    let mut core = CoreHandle::with_env(&[("APART_DISCONNECT_TIMEOUT", "1")]).unwrap();
+
+    let clone_msg = format!(
+        "type: clone\n\
+         source: /dev/sda5\n\
+         destination: {destination}\n\
+         name: clone_finishes_while_detached",
+        destination = core.tmp_dir()
+    );
+    core.send(&clone_msg);
+    let msg = core.expect_message_with(|msg| {
+        msg["type"].as_str() == Some("clone") && msg["rate"].as_str().is_some()
+    });
+    let id = msg["id"].as_str().unwrap().to_owned();
+
+    // the client goes away, its replacement yet to send a message, so progress fills the queue
+    core.reattach().expect("!reattach");
+    thread::sleep(Duration::from_millis(500));
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+    // clone finishes & the core detaches after the disconnect timeout
+    thread::sleep(Duration::from_millis(2500));
+
+    core.send("type: jobs-request");
+    let msg = core.expect_message();
+    assert_eq!(msg["type"].as_str(), Some("clone"));
+    assert_eq!(msg["id"].as_str(), Some(id.as_str()));
+    assert!(msg["finish"].as_str().is_some());
+    assert!(msg["image_size"].as_i64().is_some());
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("jobs"));
+    assert_eq!(msg["jobs"].as_vec().map(Vec::len), Some(0));
+}
+
 #[test]
 fn cancel_clone_job() {
     let core = CoreHandle::new().unwrap();
@@ -516,6 +663,15 @@ fn clone_job_error() {
     let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
     assert_eq!(msg["id"].as_str(), id);
     assert_eq!(msg["error"].as_str(), Some("Failed"));
+    let output = msg["output"].as_vec().expect("!output");
+    assert_eq!(output.last().and_then(|l| l.as_str()), Some("Mock failure"));
+    assert!(
+        output
+            .iter()
+            .all(|l| !l.as_str().unwrap().starts_with("Elapsed:")),
+        "progress in output {:?}",
+        output
+    );
 
     let inprogress_path = format!("{}.inprogress", destination);
     let start = Instant::now();
@@ -538,6 +694,342 @@ fn clone_job_error() {
     );
 }
 
+#[test]
+fn clone_history() {
+    let core = CoreHandle::new().unwrap();
+
+    core.set_mock_partclone("ext2", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {}\n\
+         name: history_finished\n\
+         compression: gz\n\
+         retention:\n  keep_last: 3",
+        core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| {
+        msg["type"].as_str() == Some("clone") && msg["finish"].as_str().is_some()
+    });
+    let finished_id = msg["id"].as_str().unwrap().to_owned();
+    let image = msg["destination"].as_str().unwrap().to_owned();
+    let image_size = msg["image_size"].as_i64();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda5\n\
+         destination: {}\n\
+         name: history_cancelled",
+        core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone"));
+    core.send(&format!(
+        "type: cancel-clone\nid: {}",
+        msg["id"].as_str().unwrap()
+    ));
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+
+    core.send(&format!("type: delete-clone\nfile: {}", image));
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("deleted-clone"));
+
+    core.send("type: history-request");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("history"));
+    let entries = msg["entries"].as_vec().expect("!entries");
+    assert_eq!(entries.len(), 3);
+
+    assert_eq!(entries[0]["kind"].as_str(), Some("clone"));
+    assert_eq!(entries[0]["id"].as_str(), Some(finished_id.as_str()));
+    assert_eq!(entries[0]["outcome"].as_str(), Some("finished"));
+    assert_eq!(entries[0]["source_uuid"].as_str(), Some("456-456-456"));
+    assert_eq!(entries[0]["image_size"].as_i64(), image_size);
+    assert_eq!(
+        entries[0]["parameters"]["source"].as_str(),
+        Some("/dev/sdb1")
+    );
+    assert_eq!(
+        entries[0]["parameters"]["destination"].as_str(),
+        Some(image.as_str())
+    );
+    let parameters = &entries[0]["parameters"];
+    assert_eq!(parameters["name"].as_str(), Some("history_finished"));
+    assert_eq!(parameters["compression"].as_str(), Some("gz"));
+    assert_eq!(parameters["keep_last"].as_str(), Some("3"));
+    assert_eq!(parameters["keep_daily"].as_str(), None);
+    assert_eq!(parameters["fsck_first"].as_str(), Some("false"));
+    assert_eq!(parameters["unmount_first"].as_str(), Some("false"));
+    assert!(entries[0]["duration_seconds"].as_f64().is_some());
+
+    assert_eq!(entries[1]["kind"].as_str(), Some("clone"));
+    assert_eq!(entries[1]["outcome"].as_str(), Some("failed"));
+    assert_eq!(entries[1]["reason"].as_str(), Some("Cancelled"));
+    assert_eq!(
+        entries[1]["parameters"]["name"].as_str(),
+        Some("history_cancelled")
+    );
+
+    assert_eq!(entries[2]["kind"].as_str(), Some("delete"));
+    assert_eq!(entries[2]["outcome"].as_str(), Some("finished"));
+    assert_eq!(
+        entries[2]["parameters"]["file"].as_str(),
+        Some(image.as_str())
+    );
+    assert_eq!(entries[2]["image_size"].as_i64(), image_size);
+
+    core.send("type: history-request\noutcome: failed");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("history"));
+    assert_eq!(msg["entries"].as_vec().map(Vec::len), Some(1));
+
+    core.send("type: history-request\nsource_uuid: 456-456-456");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("history"));
+    assert_eq!(msg["entries"].as_vec().map(Vec::len), Some(1));
+
+    core.send(&format!(
+        "type: history-request\nsince: {:?}",
+        Utc::now() + TimeDelta::try_minutes(1).unwrap()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("history"));
+    assert_eq!(msg["entries"].as_vec().map(Vec::len), Some(0));
+
+    let history = core
+        .get_tmp_file_contents_utf8("history.jsonl")
+        .expect("!history file");
+    assert_eq!(history.lines().count(), 3);
+}
+
+#[test]
+fn clone_hooks() {
+    let core = CoreHandle::new().unwrap();
+
+    core.set_mock_partclone("ext2", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {tmp}\n\
+         name: clone_hooks\n\
+         pre_hook: {tmp}/mockhook\n\
+         post_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| {
+        msg["type"].as_str() == Some("clone") && msg["finish"].as_str().is_some()
+    });
+    let id = msg["id"].as_str().unwrap();
+    let image = msg["destination"].as_str().unwrap();
+
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.env.mockhook.pre")
+            .expect("pre-hook not run"),
+        format!(
+            "APART_JOB_DESTINATION={}\n\
+             APART_JOB_KIND=clone\n\
+             APART_JOB_SOURCE=/dev/sdb1\n",
+            core.tmp_dir()
+        )
+    );
+    assert_eq!(
+        core.expect_tmp_file_contents_utf8(".latest.env.mockhook.post"),
+        format!(
+            "APART_JOB_DESTINATION={image}\n\
+             APART_JOB_ID={id}\n\
+             APART_JOB_IMAGE={image}\n\
+             APART_JOB_KIND=clone\n\
+             APART_JOB_OUTCOME=finished\n\
+             APART_JOB_SOURCE=/dev/sdb1\n",
+        )
+    );
+}
+
+#[test]
+fn clone_pre_hook_failure() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of(".control.mockhook.pre"), "database busy").unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {tmp}\n\
+         name: clone_pre_hook_failure\n\
+         pre_hook: {tmp}/mockhook\n\
+         post_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("pre-hook failed: database busy")
+    );
+
+    assert!(
+        !core.path_of(".latest.s.mockpcl.ext2.txt").exists(),
+        "partclone invoked"
+    );
+    assert!(
+        !core.path_of(".latest.env.mockhook.post").exists(),
+        "post-hook run"
+    );
+}
+
+#[test]
+fn clone_pre_hook_before_unmount_first() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of(".control.mockhook.pre"), "database busy").unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda3\n\
+         destination: {tmp}\n\
+         name: clone_pre_hook_before_unmount_first\n\
+         unmount_first: true\n\
+         pre_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("pre-hook failed: database busy")
+    );
+    assert!(msg["id"].as_str().is_some());
+    assert!(
+        !core.path_of(".latest.args.mockumount").exists(),
+        "unmounted before pre-hook"
+    );
+}
+
+#[test]
+fn clone_unmount_first_failure_after_pre_hook() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(
+        core.path_of(".control.mockumount"),
+        "umount: /: target is busy.",
+    )
+    .unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda3\n\
+         destination: {tmp}\n\
+         name: clone_unmount_first_failure_after_pre_hook\n\
+         unmount_first: true\n\
+         pre_hook: {tmp}/mockhook\n\
+         post_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("Failed to unmount /dev/sda3: umount: /: target is busy.")
+    );
+    assert!(
+        core.path_of(".latest.env.mockhook.pre").exists(),
+        "pre-hook not run"
+    );
+    // the pre-hook's effects are undone by the post-hook
+    let post_env = core.expect_tmp_file_contents_utf8(".latest.env.mockhook.post");
+    assert!(
+        post_env.contains("APART_JOB_OUTCOME=failed\n"),
+        "{}",
+        post_env
+    );
+}
+
+#[test]
+fn cancel_clone_in_pre_hook() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of(".delay.mockhook.pre"), "10").unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {tmp}\n\
+         name: cancel_clone_in_pre_hook\n\
+         pre_hook: {tmp}/mockhook\n\
+         post_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+
+    core.send("type: jobs-request");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("jobs"));
+    assert_eq!(msg["jobs"].as_vec().map(Vec::len), Some(1));
+    let job = &msg["jobs"][0];
+    assert_eq!(job["kind"].as_str(), Some("clone"));
+    assert_eq!(job["source"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(job["phase"].as_str(), Some("preparing"));
+    assert_eq!(job["complete"].as_f64(), Some(0.0));
+    let id = job["id"].as_str().unwrap().to_owned();
+
+    core.send(&format!("type: cancel-clone\nid: {}", id));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["id"].as_str(), Some(id.as_str()));
+    assert_eq!(msg["error"].as_str(), Some("Cancelled"));
+
+    core.send("type: jobs-request");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("jobs"));
+    assert_eq!(msg["jobs"].as_vec().map(Vec::len), Some(0));
+    assert!(
+        !core.path_of(".latest.s.mockpcl.ext2.txt").exists(),
+        "partclone invoked"
+    );
+    assert!(
+        !core.path_of(".latest.env.mockhook.post").exists(),
+        "post-hook run"
+    );
+}
+
+#[test]
+fn clone_pre_hook_timeout() {
+    let core = CoreHandle::with_env(&[("APART_HOOK_TIMEOUT", "1")]).unwrap();
+    fs::write(core.path_of(".delay.mockhook.pre"), "10").unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {tmp}\n\
+         name: clone_pre_hook_timeout\n\
+         pre_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    thread::sleep(Duration::from_millis(500));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["error"].as_str(), Some("pre-hook timed out after 1s"));
+    assert!(
+        !core.path_of(".latest.s.mockpcl.ext2.txt").exists(),
+        "partclone invoked"
+    );
+}
+
+#[test]
+fn shutdown_post_hook_timeout() {
+    let core = CoreHandle::with_env(&[("APART_HOOK_TIMEOUT", "1")]).unwrap();
+    fs::write(core.path_of(".delay.mockhook.post"), "10").unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda5\n\
+         destination: {tmp}\n\
+         name: shutdown_post_hook_timeout\n\
+         post_hook: {tmp}/mockhook",
+        tmp = core.tmp_dir()
+    ));
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("clone"));
+    let start = Instant::now();
+    core.send("type: kill-request");
+    thread::sleep(Duration::from_millis(1500));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("status"));
+    assert_eq!(msg["status"].as_str(), Some("dying"));
+    assert!(
+        start.elapsed() < Duration::from_secs(5),
+        "post-hook not killed"
+    );
+    assert_eq!(
+        core.expect_tmp_file_contents_utf8(".latest.env.mockhook.post")
+            .lines()
+            .find(|line| line.starts_with("APART_JOB_REASON=")),
+        Some("APART_JOB_REASON=Shutdown")
+    );
+}
+
 #[test]
 fn delete_image() {
     let core = CoreHandle::new().unwrap();
@@ -575,3 +1067,358 @@ fn delete_image_not_found() {
     assert_eq!(msg["file"].as_str().unwrap(), image);
     assert_eq!(msg["error"].as_str(), Some("No such file"));
 }
+
+#[test]
+fn prune_images() {
+    let core = CoreHandle::new().unwrap();
+    let image = |timestamp: &str| format!("{}/prune_job-{}.apt.dd.gz", core.tmp_dir(), timestamp);
+    for timestamp in ["2017-04-18T1739", "2017-04-19T1739", "2017-04-20T1739"] {
+        std::fs::write(image(timestamp), "mock").unwrap();
+    }
+    // other backup names & in progress images are untouched
+    std::fs::write(format!("{}.inprogress", image("2017-04-17T1739")), "mock").unwrap();
+
+    core.send(&format!(
+        "type: prune\n\
+         directory: {}\n\
+         name: prune_job\n\
+         retention:\n  \
+           keep_last: 1",
+        core.tmp_dir()
+    ));
+
+    let mut deleted = Vec::new();
+    for _ in 0..2 {
+        let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("deleted-clone"));
+        deleted.push(msg["file"].as_str().unwrap().to_owned());
+    }
+    deleted.sort();
+    assert_eq!(
+        deleted,
+        vec![image("2017-04-18T1739"), image("2017-04-19T1739")]
+    );
+    assert!(Path::new(&image("2017-04-20T1739")).exists());
+    assert!(Path::new(&format!("{}.inprogress", image("2017-04-17T1739"))).exists());
+    assert!(
+        Path::new(&format!(
+            "{}/mockimg-2017-04-20T1500.apt.dd.gz",
+            core.tmp_dir()
+        ))
+        .exists()
+    );
+}
+
+#[test]
+fn clone_by_source_uuid() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source_uuid: 456-456-456\n\
+         destination: {destination}\n\
+         name: uuid_job",
+        destination = core.tmp_dir()
+    ));
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["source"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(msg["source_uuid"].as_str(), Some("456-456-456"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.s.mockpcl.ext2.txt")
+            .expect("!last source"),
+        "/dev/sdb1"
+    );
+}
+
+#[test]
+fn clone_by_unknown_source_label() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source_label: not-a-label\n\
+         destination: {destination}\n\
+         name: label_job",
+        destination = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("No partition found with label not-a-label")
+    );
+}
+
+#[test]
+fn clone_stacked_lvm_volume() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/mapper/vg-home\n\
+         destination: {destination}\n\
+         name: lvm_job",
+        destination = core.tmp_dir()
+    ));
+    core.set_mock_partclone(
+        "f2fs",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["source"].as_str(), Some("/dev/mapper/vg-home"));
+    assert_eq!(msg["source_uuid"].as_str(), Some("901-901-901"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.s.mockpcl.f2fs.txt")
+            .expect("!last source"),
+        "/dev/mapper/vg-home"
+    );
+}
+
+#[test]
+fn clone_loop_device() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/loop0\n\
+         destination: {destination}\n\
+         name: loop_job",
+        destination = core.tmp_dir()
+    ));
+    let expected_filename = format!(
+        "loop_job-{}.apt.ext2.gz",
+        Local::now().format("%Y-%m-%dT%H%M")
+    );
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["source"].as_str(), Some("/dev/loop0"));
+    assert_eq!(
+        msg["destination"].as_str(),
+        Some(format!("{}/{}", core.tmp_dir(), expected_filename).as_ref())
+    );
+}
+
+#[test]
+fn clone_unmount_first() {
+    let core = CoreHandle::new().unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda3\n\
+         destination: {destination}\n\
+         name: root_job\n\
+         unmount_first: true",
+        destination = core.tmp_dir()
+    ));
+    core.expect_message_with(|msg| msg["source"].as_str() == Some("/dev/sda3"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockumount")
+            .expect("!umount args"),
+        "/dev/sda3\n"
+    );
+}
+
+#[test]
+fn clone_unmount_first_failure() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(
+        core.path_of(".control.mockumount"),
+        "umount: /: target is busy.",
+    )
+    .unwrap();
+
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sda3\n\
+         destination: {destination}\n\
+         name: root_job\n\
+         unmount_first: true",
+        destination = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("Failed to unmount /dev/sda3: umount: /: target is busy.")
+    );
+}
+
+#[test]
+fn clone_estimate() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: clone-estimate\nsource: /dev/sdb1");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-estimate"));
+    assert_eq!(msg["source"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(msg["size"].as_i64(), Some(524_288_000));
+    assert_eq!(msg["used"].as_i64(), Some(123_731_968));
+    assert_eq!(msg["used_exact"].as_bool(), Some(true));
+
+    let gz = &msg["estimates"][0];
+    assert_eq!(gz["compression"].as_str(), Some("gz"));
+    assert_eq!(gz["image_size"].as_i64(), Some(30_932_992));
+    assert!(gz["seconds"].as_i64().expect("!seconds") > 0);
+    let uncompressed = &msg["estimates"][1];
+    assert_eq!(uncompressed["compression"].as_str(), Some("uncompressed"));
+    assert_eq!(uncompressed["image_size"].as_i64(), Some(123_731_968));
+}
+
+#[test]
+fn clone_estimate_unmounted() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: clone-estimate\nsource_label: main");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-estimate"));
+    assert_eq!(msg["source"].as_str(), Some("/dev/sdb3"));
+    // "Space in use" of mock partclone info
+    assert_eq!(msg["used"].as_i64(), Some(3_000_000 * 4096));
+    assert_eq!(msg["used_exact"].as_bool(), Some(true));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.s.mockpcl.f2fs.txt")
+            .expect("!partclone -s"),
+        "/dev/sdb3"
+    );
+}
+
+#[test]
+fn clone_estimate_unmounted_unreadable() {
+    let core = CoreHandle::new().unwrap();
+    core.set_mock_partclone("f2fs", MockPartcloneState::new().error(true))
+        .expect("!set_mock_partclone");
+    core.send("type: clone-estimate\nsource_label: main");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-estimate"));
+    assert_eq!(msg["used"].as_i64(), Some(59_436_433_408));
+    assert_eq!(msg["used_exact"].as_bool(), Some(false));
+}
+
+#[test]
+fn clone_estimate_dd() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: clone-estimate\nsource: /dev/sda5");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-estimate"));
+    assert_eq!(msg["used"].as_i64(), Some(32_212_254_720));
+    assert_eq!(msg["used_exact"].as_bool(), Some(true));
+}
+
+#[test]
+fn clone_estimate_unknown_source() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: clone-estimate\nsource_label: not-a-label");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-estimate-failed"));
+    assert_eq!(msg["source"].as_str(), Some("label not-a-label"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("No partition found with label not-a-label")
+    );
+}
+
+#[test]
+fn clone_destination_out_of_space() {
+    let core = CoreHandle::new().unwrap();
+    // estimated gz image of /dev/sdb1 is ~31MB
+    fs::write(core.path_of(".control.mockdf"), "30000000").unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {}\n\
+         name: full",
+        core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Destination out of space"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockdf")
+            .expect("!df args"),
+        format!("--output=avail\n-B1\n{}\n", core.tmp_dir())
+    );
+    assert!(
+        core.path_of(".latest.s.mockpcl.ext2.txt")
+            .metadata()
+            .is_err()
+    );
+}
+
+#[test]
+fn clone_unmounted_destination_out_of_space() {
+    let core = CoreHandle::new().unwrap();
+    // /dev/sdb3 is unmounted, mock partclone info has 3000000 blocks of 4096 in use
+    fs::write(core.path_of(".control.mockdf"), "1000000").unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source_label: main\n\
+         destination: {}\n\
+         name: full",
+        core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Destination out of space"));
+    assert!(
+        core.path_of(".latest.s.mockpcl.f2fs.txt").exists(),
+        "used blocks not read"
+    );
+    assert!(
+        !core.path_of(".latest.c.mockpcl.f2fs.txt").exists(),
+        "clone started"
+    );
+}
+
+#[test]
+fn clone_destination_fills_up() {
+    let core = CoreHandle::new().unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {}\n\
+         name: filling",
+        core.tmp_dir()
+    ));
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("clone"));
+
+    fs::write(core.path_of(".control.mockdf"), "0").unwrap();
+    core.set_mock_partclone("ext2", MockPartcloneState::new().complete(0.5))
+        .expect("!set_mock_partclone");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Destination out of space"));
+}
+
+#[test]
+fn clone_failure_reason() {
+    let core = CoreHandle::new().unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {}\n\
+         name: bad",
+        core.tmp_dir()
+    ));
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("clone"));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new()
+            .complete(0.2)
+            .error_message("read error at block 1234: Input/output error"),
+    )
+    .expect("!set_mock_partclone");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Bad sectors"));
+    let output = msg["output"].as_vec().expect("!output");
+    assert_eq!(
+        output.last().and_then(|l| l.as_str()),
+        Some("read error at block 1234: Input/output error")
+    );
+}
@@ -19,6 +19,7 @@ pub struct CoreHandle {
     pub initial_message: Yaml,
     tmp_dir: TmpDir,
     pub socket: zmq::Socket,
+    reattach_address: String,
 }
 
 impl Drop for CoreHandle {
@@ -120,6 +121,7 @@ pub struct MockPartcloneState {
     pub complete: f64,
     pub rate: String,
     pub error: bool,
+    pub error_message: String,
 }
 
 impl MockPartcloneState {
@@ -128,6 +130,7 @@ impl MockPartcloneState {
             complete: 0.0,
             rate: "1.11GB/min".to_owned(),
             error: false,
+            error_message: "Mock failure".to_owned(),
         }
     }
     pub fn complete(&mut self, complete: f64) -> &mut MockPartcloneState {
@@ -142,12 +145,23 @@ impl MockPartcloneState {
         self.error = error;
         self
     }
+    pub fn error_message(&mut self, message: &str) -> &mut MockPartcloneState {
+        self.error = true;
+        self.error_message = message.to_owned();
+        self
+    }
 }
 
 impl CoreHandle {
     pub fn new() -> Result<CoreHandle> {
+        CoreHandle::with_env(&[])
+    }
+
+    /// Starts the core with additional environment variables
+    pub fn with_env(vars: &[(&str, &str)]) -> Result<CoreHandle> {
         let uuid = uuid::Uuid::new_v4();
         let ipc_address = format!("ipc:///tmp/apart-{}.ipc", uuid);
+        let reattach_address = format!("ipc:///tmp/apart-{}-reattach.ipc", uuid);
         let ctx = zmq::Context::new();
         let socket = ctx.socket(zmq::PAIR)?;
         socket.bind(&ipc_address)?;
@@ -176,6 +190,38 @@ impl CoreHandle {
             .env("RUST_LOG", "info")
             .env("APART_PARTCLONE_CMD", format!("{}/mockpcl", tmp_dir.dir))
             .env("APART_LSBLK_CMD", format!("{}/mocklsblk", tmp_dir.dir))
+            .env(
+                "APART_PROC_PARTITIONS",
+                format!("{}/mockpartitions", tmp_dir.dir),
+            )
+            .env(
+                "APART_PROC_MOUNTINFO",
+                format!("{}/mockmountinfo", tmp_dir.dir),
+            )
+            .env("APART_MOUNT_CMD", format!("{}/mockmount", tmp_dir.dir))
+            .env("APART_UMOUNT_CMD", format!("{}/mockumount", tmp_dir.dir))
+            .env("APART_E2FSCK_CMD", format!("{}/mockfsck", tmp_dir.dir))
+            .env("APART_FSCK_F2FS_CMD", format!("{}/mockfsck", tmp_dir.dir))
+            .env("APART_RESIZE2FS_CMD", format!("{}/mockresize", tmp_dir.dir))
+            .env(
+                "APART_NTFSRESIZE_CMD",
+                format!("{}/mockresize", tmp_dir.dir),
+            )
+            .env("APART_TUNE2FS_CMD", format!("{}/mockuuid", tmp_dir.dir))
+            .env("APART_XFS_ADMIN_CMD", format!("{}/mockuuid", tmp_dir.dir))
+            .env("APART_BTRFSTUNE_CMD", format!("{}/mockuuid", tmp_dir.dir))
+            .env(
+                "APART_QEMU_IMG_CMD",
+                format!("{}/mockqemu-img", tmp_dir.dir),
+            )
+            .env("APART_DF_CMD", format!("{}/mockdf", tmp_dir.dir))
+            .env("APART_REATTACH_ADDRESS", &reattach_address)
+            .env(
+                "APART_HISTORY_FILE",
+                format!("{}/history.jsonl", tmp_dir.dir),
+            )
+            .env("TMPDIR", &tmp_dir.dir)
+            .envs(vars.iter().copied())
             .spawn()?;
 
         let message = expect_message_from(&socket);
@@ -184,9 +230,22 @@ impl CoreHandle {
             socket,
             initial_message: message,
             tmp_dir,
+            reattach_address,
         })
     }
 
+    /// Replaces the client socket with a new one connected to the core's reattach address, as a
+    /// restarted client would
+    pub fn reattach(&mut self) -> Result<()> {
+        let socket = zmq::Context::new().socket(zmq::PAIR)?;
+        socket.connect(&self.reattach_address)?;
+        socket.set_sndtimeo(1000)?;
+        socket.set_rcvtimeo(1000)?;
+        socket.set_linger(0)?;
+        self.socket = socket;
+        Ok(())
+    }
+
     pub fn expect_message(&self) -> Yaml {
         expect_message_from(&self.socket)
     }
@@ -220,6 +279,7 @@ impl CoreHandle {
             complete,
             ref rate,
             error,
+            ref error_message,
         }: &MockPartcloneState,
     ) -> Result<()> {
         let mut file = fs::OpenOptions::new()
@@ -231,10 +291,12 @@ impl CoreHandle {
             file,
             "complete={:.2}\n\
              rate=\"{}\"\n\
-             error={}",
+             error={}\n\
+             error_message=\"{}\"",
             complete * 100.,
             rate,
-            error
+            error,
+            error_message
         )?;
         Ok(())
     }
@@ -251,6 +313,29 @@ impl CoreHandle {
         Ok(contents)
     }
 
+    /// Waits up to 1 second for a file written concurrently, ie by a post-hook
+    pub fn expect_tmp_file_contents_utf8(&self, filename: &str) -> String {
+        let start = Instant::now();
+        loop {
+            if let Ok(contents) = self.get_tmp_file_contents_utf8(filename) {
+                return contents;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "{} not written within 1 second",
+                filename
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Writes a temporary file then renames it, so concurrent readers never see partial contents
+    pub fn write_tmp_file_atomically(&self, filename: &str, contents: &str) -> Result<()> {
+        let tmp = self.path_of(&format!("{}.tmp", filename));
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, self.path_of(filename))
+    }
+
     pub fn tmp_file_contents_is_1(&self, filename: &str) -> bool {
         if let Ok(contents) = self.get_tmp_file_contents_utf8(filename) {
             return contents.trim() == "1";
@@ -0,0 +1,117 @@
+mod coreutil;
+
+use crate::coreutil::*;
+
+// Tests asserting from a client's perspective performing file system checks
+
+#[test]
+fn fsck_request() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: fsck\ndevice: /dev/sdb1");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("fsck"));
+    assert_eq!(msg["device"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(msg["fstype"].as_str(), Some("ext2"));
+    assert_eq!(msg["finished"].as_bool(), Some(false));
+    assert!(msg["before"].is_badvalue());
+
+    let msg = core.expect_message_with(|msg| msg["output"].as_str().is_some());
+    assert_eq!(
+        msg["output"].as_str(),
+        Some("Pass 1: Checking inodes, blocks, and sizes")
+    );
+
+    let msg = core.expect_message_with(|msg| msg["finished"].as_bool() == Some(true));
+    assert_eq!(msg["clean"].as_bool(), Some(true));
+    assert_eq!(msg["exit_code"].as_i64(), Some(0));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockfsck")
+            .expect("!fsck args"),
+        "-n\n/dev/sdb1\n"
+    );
+}
+
+#[test]
+fn fsck_finding_errors() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(core.path_of(".control.mockfsck"), "4").unwrap();
+    core.send("type: fsck\ndevice: /dev/mapper/vg-home");
+
+    let msg = core.expect_message_with(|msg| {
+        msg["output"].as_str() == Some("Inode 12 has illegal block(s).  Clear? no")
+    });
+    assert_eq!(msg["fstype"].as_str(), Some("f2fs"));
+
+    let msg = core.expect_message_with(|msg| msg["finished"].as_bool() == Some(true));
+    assert_eq!(msg["clean"].as_bool(), Some(false));
+    assert_eq!(msg["exit_code"].as_i64(), Some(4));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockfsck")
+            .expect("!fsck args"),
+        "--dry-run\n/dev/mapper/vg-home\n"
+    );
+}
+
+#[test]
+fn fsck_unsupported_fstype() {
+    let core = CoreHandle::new().unwrap();
+    core.send("type: fsck\ndevice: /dev/sda5");
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("fsck-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("No filesystem check available for /dev/sda5 fstype ''")
+    );
+}
+
+#[test]
+fn clone_fsck_first() {
+    let core = CoreHandle::new().unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {destination}\n\
+         name: checked_job\n\
+         fsck_first: true",
+        destination = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["finished"].as_bool() == Some(true));
+    assert_eq!(msg["type"].as_str(), Some("fsck"));
+    assert_eq!(msg["before"].as_str(), Some("clone"));
+    assert_eq!(msg["clean"].as_bool(), Some(true));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["type"].as_str(), Some("clone"));
+    assert_eq!(msg["source"].as_str(), Some("/dev/sdb1"));
+}
+
+#[test]
+fn clone_fsck_first_finding_errors() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(core.path_of(".control.mockfsck"), "4").unwrap();
+    core.send(&format!(
+        "type: clone\n\
+         source: /dev/sdb1\n\
+         destination: {destination}\n\
+         name: checked_job\n\
+         fsck_first: true",
+        destination = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("clone-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("e2fsck found errors on /dev/sdb1 (exit code 4)")
+    );
+    assert!(
+        core.path_of(".latest.s.mockpcl.ext2.txt")
+            .metadata()
+            .is_err()
+    );
+}
@@ -0,0 +1,67 @@
+mod coreutil;
+
+use crate::coreutil::*;
+
+// Tests asserting from a client's perspective inspecting image files
+
+#[test]
+fn image_info() {
+    let core = CoreHandle::new().unwrap();
+
+    // partclone v2 image header of a 1MiB ext4 partition
+    let mut header = vec![0_u8; 110];
+    header[..16].copy_from_slice(b"partclone-image\0");
+    header[16..23].copy_from_slice(b"0.3.13\0");
+    header[30..34].copy_from_slice(b"0002");
+    header[36..42].copy_from_slice(b"EXTFS\0");
+    header[52..60].copy_from_slice(&1_048_576_u64.to_le_bytes());
+    header[60..68].copy_from_slice(&256_u64.to_le_bytes());
+    header[68..76].copy_from_slice(&42_u64.to_le_bytes());
+    header[84..88].copy_from_slice(&4096_u32.to_le_bytes());
+    std::fs::write(
+        core.path_of("header-2017-04-20T1500.apt.ext4.uncompressed"),
+        &header,
+    )
+    .unwrap();
+
+    core.send(&format!(
+        "type: image-info\n\
+         file: {}/header-2017-04-20T1500.apt.ext4.uncompressed",
+        core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("image-info"));
+    assert_eq!(msg["format"].as_str(), Some("partclone"));
+    assert_eq!(msg["fs"].as_str(), Some("EXTFS"));
+    assert_eq!(msg["partclone_variant"].as_str(), Some("extfs"));
+    assert_eq!(msg["version"].as_str(), Some("0002"));
+    assert_eq!(msg["partclone_version"].as_str(), Some("0.3.13"));
+    assert_eq!(msg["device_size"].as_i64(), Some(1_048_576));
+    assert_eq!(msg["block_size"].as_i64(), Some(4096));
+    assert_eq!(msg["total_blocks"].as_i64(), Some(256));
+    assert_eq!(msg["used_blocks"].as_i64(), Some(42));
+}
+
+#[test]
+fn image_info_dd_image() {
+    let core = CoreHandle::new().unwrap();
+    let file = format!("{}/mockimg-2017-04-20T1500.apt.dd.gz", core.tmp_dir());
+    core.send(&format!("type: image-info\nfile: {}", file));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("image-info"));
+    assert_eq!(msg["file"].as_str(), Some(file.as_ref()));
+    assert_eq!(msg["format"].as_str(), Some("dd"));
+    assert!(msg["device_size"].is_badvalue());
+}
+
+#[test]
+fn image_info_missing_file() {
+    let core = CoreHandle::new().unwrap();
+    core.send(&format!(
+        "type: image-info\nfile: {}/missing-2017-04-20T1500.apt.ext4.gz",
+        core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("image-info-failed"));
+    assert_eq!(msg["error"].as_str(), Some("No such file"));
+}
@@ -0,0 +1,105 @@
+mod coreutil;
+
+use crate::coreutil::*;
+use flate2::{Compression, write::GzEncoder};
+use regex::Regex;
+use std::{fs, io::Write};
+
+// Tests asserting from a client's perspective importing images made by other tools
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+    gz.write_all(bytes).unwrap();
+    gz.finish().unwrap()
+}
+
+/// partclone v2 image header of a 1MiB ext4 partition
+fn partclone_header() -> Vec<u8> {
+    let mut header = vec![0_u8; 110];
+    header[..16].copy_from_slice(b"partclone-image\0");
+    header[30..34].copy_from_slice(b"0002");
+    header[36..42].copy_from_slice(b"EXTFS\0");
+    header[52..60].copy_from_slice(&1_048_576_u64.to_le_bytes());
+    header
+}
+
+#[test]
+fn import_clonezilla_directory() {
+    let core = CoreHandle::new().unwrap();
+
+    let clonezilla = core.path_of("2019-05-01-12-img");
+    fs::create_dir(&clonezilla).unwrap();
+    let image = gzip(b"mock-partition-/dev/sda1-data");
+    let (aa, ab) = image.split_at(image.len() / 2);
+    fs::write(clonezilla.join("sda1.ext4-ptcl-img.gz.aa"), aa).unwrap();
+    fs::write(clonezilla.join("sda1.ext4-ptcl-img.gz.ab"), ab).unwrap();
+    fs::write(clonezilla.join("sda2.dd-ptcl-img.uncomp"), "sda2-data").unwrap();
+    fs::write(clonezilla.join("parts"), "sda1 sda2").unwrap();
+
+    core.send(&format!(
+        "type: import-image\n\
+         source: {tmp}/2019-05-01-12-img\n\
+         destination: {tmp}\n\
+         name: laptop",
+        tmp = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("imported-image"));
+    let images: Vec<_> = msg["images"]
+        .as_vec()
+        .expect("!images")
+        .iter()
+        .map(|image| image.as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(images.len(), 2, "{images:?}");
+
+    let sda1_re = Regex::new(r"/laptop-sda1-\d{4}-\d\d-\d\dT\d{4}\.apt\.ext4\.gz$").unwrap();
+    assert!(sda1_re.is_match(&images[0]), "{}", images[0]);
+    assert_eq!(fs::read(&images[0]).unwrap(), image);
+
+    let sda2_re =
+        Regex::new(r"/laptop-sda2-\d{4}-\d\d-\d\dT\d{4}\.apt\.dd\.uncompressed$").unwrap();
+    assert!(sda2_re.is_match(&images[1]), "{}", images[1]);
+    assert_eq!(fs::read_to_string(&images[1]).unwrap(), "sda2-data");
+}
+
+#[test]
+fn import_partclone_dump() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of("server.img"), gzip(&partclone_header())).unwrap();
+
+    core.send(&format!(
+        "type: import-image\n\
+         source: {tmp}/server.img\n\
+         destination: {tmp}",
+        tmp = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("imported-image"));
+    let image = msg["images"][0].as_str().expect("!images");
+    let image_re = Regex::new(r"/server-\d{4}-\d\d-\d\dT\d{4}\.apt\.extfs\.gz$").unwrap();
+    assert!(image_re.is_match(image), "{}", image);
+    assert_eq!(
+        fs::read(image).unwrap(),
+        fs::read(core.path_of("server.img")).unwrap()
+    );
+}
+
+#[test]
+fn import_unknown_file() {
+    let core = CoreHandle::new().unwrap();
+    fs::write(core.path_of("notes.txt"), "not an image").unwrap();
+
+    core.send(&format!(
+        "type: import-image\n\
+         source: {tmp}/notes.txt\n\
+         destination: {tmp}",
+        tmp = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("import-image-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some(format!("{}/notes.txt is not a partclone image", core.tmp_dir()).as_ref())
+    );
+}
@@ -3,7 +3,12 @@ mod coreutil;
 use crate::coreutil::*;
 use chrono::{TimeDelta, prelude::*};
 use log::warn;
-use std::process::{Command, Stdio};
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
 
 // Tests asserting from a client's perspective performing a partition restore
 
@@ -59,6 +64,15 @@ fn restore_success() {
     assert_eq!(msg["id"].as_str(), id);
     assert_eq!(msg["rate"].as_str(), Some("0.01GB/min"));
     assert_eq!(msg["syncing"].as_bool(), Some(false));
+    assert_eq!(msg["bytes_per_second"].as_i64(), Some(166_667));
+    assert_eq!(msg["bytes_done"].as_i64(), Some(3_878_912 * 512));
+    assert_eq!(msg["bytes_total"].as_i64(), Some(62_914_560 * 512));
+    assert_eq!(msg["elapsed_seconds"].as_i64(), Some(12));
+    // the mock reads all of the image before reporting progress
+    assert_eq!(
+        msg["compressed_bytes"].as_i64(),
+        Some(fs::metadata(&source_image).unwrap().len() as i64)
+    );
 
     let estimated_finish = msg["estimated_finish"]
         .as_str()
@@ -203,6 +217,428 @@ fn restore_using_partclone_fstype_variant_ext2() {
     );
 }
 
+#[test]
+fn restore_by_destination_label() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination_label: boot",
+        source = source_image
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["destination"].as_str(), Some("/dev/sdb1"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.o.mockpcl.ext2.txt")
+            .expect("!last -o"),
+        "/dev/sdb1"
+    );
+}
+
+#[test]
+fn restore_expand_filesystem() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         expand_filesystem: true",
+        source = source_image
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    core.expect_message_with(|msg| msg["phase"].as_str() == Some("resizing"));
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["phase"].as_str(), Some("finished"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockfsck")
+            .expect("!e2fsck args"),
+        "-f\n-y\n/dev/sdb1\n"
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockresize")
+            .expect("!resize2fs args"),
+        "/dev/sdb1\n"
+    );
+}
+
+#[test]
+fn restore_extfs_image_expand_filesystem_new_uuid() {
+    let core = CoreHandle::new().unwrap();
+    // as imported from a partclone.extfs image, restored by partclone.extfs
+    let source_image = format!("{}/imported-2017-04-20T1500.apt.extfs.gz", core.tmp_dir());
+    fs::copy(
+        core.path_of("mockimg-2017-04-20T1500.apt.ext2.gz"),
+        &source_image,
+    )
+    .unwrap();
+    fs::copy(core.path_of("mockpcl.ext2"), core.path_of("mockpcl.extfs")).unwrap();
+
+    core.set_mock_partclone("extfs", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         expand_filesystem: true\n\
+         new_uuid: 2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4",
+        source = source_image
+    ));
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["phase"].as_str(), Some("finished"));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockresize")
+            .expect("!resize2fs args"),
+        "/dev/sdb1\n"
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockuuid")
+            .expect("!tune2fs args"),
+        "-U\n2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4\n/dev/sdb1\n"
+    );
+}
+
+#[test]
+fn restore_progress_interval_syncing() {
+    let core = CoreHandle::new().unwrap();
+    // resizing outlasts the progress interval
+    fs::write(core.path_of(".delay.mockresize"), "0.5").unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         expand_filesystem: true\n\
+         progress_interval: 0.2",
+        source = source_image
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("restore"));
+    assert_eq!(msg["complete"].as_f64(), Some(0.0));
+
+    core.set_mock_partclone("ext2", MockPartcloneState::new().complete(0.5))
+        .expect("!set_mock_partclone");
+    thread::sleep(Duration::from_millis(50));
+    core.set_mock_partclone("ext2", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+
+    // syncing is sent immediately & progress held back isn't sent after it
+    core.expect_message_with(|msg| msg["phase"].as_str() == Some("syncing"));
+    let msg = core.expect_message();
+    assert_eq!(msg["phase"].as_str(), Some("resizing"));
+    let msg = core.expect_message();
+    assert_eq!(msg["phase"].as_str(), Some("finished"));
+    assert_eq!(msg["complete"].as_f64(), Some(1.0));
+}
+
+#[test]
+fn restore_expand_filesystem_failure() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(
+        core.path_of(".control.mockresize"),
+        "resize2fs: Bad magic number in super-block",
+    )
+    .unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         expand_filesystem: true",
+        source = source_image
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("Failed to expand filesystem: resize2fs: Bad magic number in super-block")
+    );
+}
+
+#[test]
+fn restore_expand_unsupported_filesystem() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.f2fs.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/abc122\n\
+         expand_filesystem: true",
+        source = source_image
+    ));
+
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Cannot expand f2fs filesystem"));
+}
+
+#[test]
+fn restore_new_uuid() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         new_uuid: 2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4",
+        source = source_image
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(
+        msg["uuid"].as_str(),
+        Some("2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4")
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockuuid")
+            .expect("!tune2fs args"),
+        "-U\n2cd7f7a1-4be2-4e4c-8d51-6cc9c7d2b0f4\n/dev/sdb1\n"
+    );
+}
+
+#[test]
+fn restore_random_new_uuid() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/sdb1\n\
+         new_uuid: true",
+        source = source_image
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    let uuid = msg["uuid"].as_str().expect("!uuid");
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockuuid")
+            .expect("!tune2fs args"),
+        format!("-U\n{}\n/dev/sdb1\n", uuid)
+    );
+
+    core.send("type: history-request");
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("history"));
+    let parameters = &msg["entries"][0]["parameters"];
+    assert_eq!(parameters["new_uuid"].as_str(), Some(uuid));
+    assert_eq!(parameters["expand_filesystem"].as_str(), Some("false"));
+    assert_eq!(parameters["unmount_first"].as_str(), Some("false"));
+}
+
+#[test]
+fn restore_new_uuid_unsupported_filesystem() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.f2fs.gz"
+    );
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/abc122\n\
+         new_uuid: true",
+        source = source_image
+    ));
+
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("Cannot set f2fs filesystem uuid")
+    );
+}
+
+#[test]
+fn restore_to_file() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    let destination = format!("{}/sdb1.img", core.tmp_dir());
+    core.send(&format!(
+        "type: restore-to-file\n\
+         source: {source}\n\
+         destination: {destination}",
+        source = source_image,
+        destination = destination
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(msg["type"].as_str(), Some("restore"));
+    assert_eq!(msg["destination"].as_str(), Some(destination.as_ref()));
+    assert!(
+        core.tmp_file_contents_is_1(".latest.W.mockpcl.ext2.txt"),
+        "partclone.ext2 not invoked with -W"
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8("sdb1.img")
+            .expect("!sdb1.img"),
+        MOCK_IMAGE_CONTENTS
+    );
+}
+
+#[test]
+fn restore_to_file_sized_from_header() {
+    let core = CoreHandle::new().unwrap();
+
+    // partclone v2 image header of a 1MiB partition
+    let mut header = vec![0_u8; 110];
+    header[..16].copy_from_slice(b"partclone-image\0");
+    header[30..34].copy_from_slice(b"0002");
+    header[36..42].copy_from_slice(b"EXTFS\0");
+    header[52..60].copy_from_slice(&1_048_576_u64.to_le_bytes());
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "header-2017-04-20T1500.apt.ext2.uncompressed"
+    );
+    std::fs::write(&source_image, &header).unwrap();
+
+    let destination = format!("{}/sdb1.img", core.tmp_dir());
+    core.send(&format!(
+        "type: restore-to-file\n\
+         source: {source}\n\
+         destination: {destination}",
+        source = source_image,
+        destination = destination
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    core.expect_message_with(|msg| msg["complete"].as_f64() == Some(1.0));
+    assert_eq!(
+        core.path_of("sdb1.img").metadata().unwrap().len(),
+        1_048_576
+    );
+}
+
+#[test]
+fn restore_to_existing_file() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    let destination = format!("{}/mocklsblk", core.tmp_dir());
+    core.send(&format!(
+        "type: restore-to-file\n\
+         source: {source}\n\
+         destination: {destination}",
+        source = source_image,
+        destination = destination
+    ));
+
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some(format!("{} already exists", destination).as_ref())
+    );
+    assert!(core.path_of("mocklsblk").metadata().unwrap().len() > 0);
+}
+
+#[test]
+fn restore_to_file_then_cancel() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: restore-to-file\n\
+         source: {source}\n\
+         destination: {tmp}/sdb1.img",
+        source = source_image,
+        tmp = core.tmp_dir()
+    ));
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("restore"));
+    let id = msg["id"].as_str().expect("!id");
+    assert!(core.path_of("sdb1.img").exists());
+
+    core.send(&format!("type: cancel-restore\nid: {}", id));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+    assert_eq!(msg["error"].as_str(), Some("Cancelled"));
+    assert!(
+        !core.path_of("sdb1.img").exists(),
+        "cancelled restore file not removed"
+    );
+}
+
 #[test]
 fn restore_lz4_compressed() {
     let _ = env_logger::try_init();
@@ -404,6 +840,40 @@ fn restore_then_cancel() {
     );
 }
 
+#[test]
+fn restore_progress_interval() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!("{}/{}", core.tmp_dir(), "mockimg-2017-04-20T1500.apt.dd.gz");
+    let restore_msg = format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/abc124\n\
+         progress_interval: 60",
+        source = source_image
+    );
+    core.send(&restore_msg);
+
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("restore"));
+    assert_eq!(msg["complete"].as_f64(), Some(0.0));
+
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(0.5))
+        .expect("!set_mock_partclone");
+    thread::sleep(Duration::from_millis(100));
+    core.set_mock_partclone("dd", MockPartcloneState::new().complete(1.0))
+        .expect("!set_mock_partclone");
+
+    let msg = core.expect_message_with(|msg| {
+        assert_ne!(
+            msg["complete"].as_f64(),
+            Some(0.5),
+            "progress not coalesced"
+        );
+        msg["finish"].as_str().is_some()
+    });
+    assert_eq!(msg["type"].as_str(), Some("restore"));
+}
+
 #[test]
 fn restore_error() {
     let core = CoreHandle::new().unwrap();
@@ -440,3 +910,179 @@ fn restore_error() {
         "partclone not cancelled"
     );
 }
+
+#[test]
+fn restore_post_hook_on_failure() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!("{}/{}", core.tmp_dir(), "mockimg-2017-04-20T1500.apt.dd.gz");
+    core.send(&format!(
+        "type: restore\n\
+         source: {source}\n\
+         destination: /dev/abc124\n\
+         post_hook: {tmp}/mockhook",
+        source = source_image,
+        tmp = core.tmp_dir()
+    ));
+    let msg = core.expect_message_with(|msg| msg["type"].as_str() == Some("restore"));
+    let id = msg["id"].as_str().unwrap();
+
+    core.set_mock_partclone("dd", MockPartcloneState::new().error(true))
+        .expect("!set_mock_partclone");
+    core.expect_message_with(|msg| msg["type"].as_str() == Some("restore-failed"));
+
+    assert_eq!(
+        core.expect_tmp_file_contents_utf8(".latest.env.mockhook.post"),
+        format!(
+            "APART_JOB_DESTINATION=/dev/abc124\n\
+             APART_JOB_ID={id}\n\
+             APART_JOB_IMAGE={source_image}\n\
+             APART_JOB_KIND=restore\n\
+             APART_JOB_OUTCOME=failed\n\
+             APART_JOB_REASON=Failed\n\
+             APART_JOB_SOURCE={source_image}\n",
+        )
+    );
+    assert!(
+        !core.path_of(".latest.env.mockhook.pre").exists(),
+        "pre-hook run"
+    );
+}
+
+#[test]
+fn export_raw() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    let destination = format!("{}/sdb1.img", core.tmp_dir());
+    core.send(&format!(
+        "type: export-raw\n\
+         source: {source}\n\
+         destination: {destination}",
+        source = source_image,
+        destination = destination
+    ));
+
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("export"));
+    assert_eq!(msg["destination"].as_str(), Some(destination.as_ref()));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["phase"].as_str() == Some("finished"));
+    assert_eq!(msg["type"].as_str(), Some("export"));
+    assert_eq!(msg["destination"].as_str(), Some(destination.as_ref()));
+    assert!(
+        core.tmp_file_contents_is_1(".latest.W.mockpcl.ext2.txt"),
+        "partclone.ext2 not invoked with -W"
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8("sdb1.img")
+            .expect("!sdb1.img"),
+        MOCK_IMAGE_CONTENTS
+    );
+    assert!(core.path_of("sdb1.img.inprogress").metadata().is_err());
+}
+
+#[test]
+fn export_qcow2() {
+    let core = CoreHandle::new().unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    let destination = format!("{}/sdb1.qcow2", core.tmp_dir());
+    core.send(&format!(
+        "type: export-raw\n\
+         source: {source}\n\
+         destination: {destination}\n\
+         format: qcow2",
+        source = source_image,
+        destination = destination
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["phase"].as_str() == Some("converting"));
+    assert_eq!(msg["type"].as_str(), Some("export"));
+
+    let msg = &core.expect_message_with(|msg| msg["phase"].as_str() == Some("finished"));
+    assert_eq!(msg["destination"].as_str(), Some(destination.as_ref()));
+    assert_eq!(
+        core.get_tmp_file_contents_utf8(".latest.args.mockqemu-img")
+            .expect("!qemu-img args"),
+        format!("convert\n-f\nraw\n-O\nqcow2\n{destination}.inprogress\n{destination}\n")
+    );
+    assert!(core.path_of("sdb1.qcow2.inprogress").metadata().is_err());
+}
+
+#[test]
+fn export_qcow2_failure() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(
+        core.path_of(".control.mockqemu-img"),
+        "qemu-img: No space left on device",
+    )
+    .unwrap();
+
+    let source_image = format!(
+        "{}/{}",
+        core.tmp_dir(),
+        "mockimg-2017-04-20T1500.apt.ext2.gz"
+    );
+    core.send(&format!(
+        "type: export-raw\n\
+         source: {source}\n\
+         destination: {tmp}/sdb1.qcow2\n\
+         format: qcow2",
+        source = source_image,
+        tmp = core.tmp_dir()
+    ));
+
+    core.set_mock_partclone(
+        "ext2",
+        MockPartcloneState::new().complete(1.0).rate("1.23GB/min"),
+    )
+    .expect("!set_mock_partclone");
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("export-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some("Failed to export qcow2: qemu-img: No space left on device")
+    );
+    assert!(core.path_of("sdb1.qcow2.inprogress").metadata().is_err());
+    assert!(core.path_of("sdb1.qcow2").metadata().is_err());
+}
+
+#[test]
+fn export_to_existing_file() {
+    let core = CoreHandle::new().unwrap();
+    std::fs::write(core.path_of("sdb1.img"), "existing").unwrap();
+
+    core.send(&format!(
+        "type: export-raw\n\
+         source: {tmp}/mockimg-2017-04-20T1500.apt.ext2.gz\n\
+         destination: {tmp}/sdb1.img",
+        tmp = core.tmp_dir()
+    ));
+
+    let msg = &core.expect_message_with(|msg| msg["type"].as_str() == Some("export-failed"));
+    assert_eq!(
+        msg["error"].as_str(),
+        Some(format!("{}/sdb1.img already exists", core.tmp_dir()).as_ref())
+    );
+    assert_eq!(
+        core.get_tmp_file_contents_utf8("sdb1.img").unwrap(),
+        "existing"
+    );
+}